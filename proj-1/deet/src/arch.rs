@@ -0,0 +1,632 @@
+//! What deet needs to know about the processor: the instruction breakpoints are made of and where
+//! the program counter is left after one traps, the registers and how they're read and written,
+//! how frames are chained through the frame pointer, and the calling convention used to call
+//! functions of the program. `Native` is the architecture deet was built for, which is the one the
+//! programs it starts run on.
+
+#[cfg(target_arch = "x86_64")]
+use nix::sys::ptrace;
+use nix::unistd::Pid;
+#[cfg(target_arch = "aarch64")]
+use std::mem::size_of;
+
+pub trait Arch {
+    /// Name of the architecture, for messages
+    const NAME: &'static str;
+    /// Instruction that traps into the debugger, written over the code at breakpoints
+    const BREAKPOINT: &'static [u8];
+    /// How far past a breakpoint the program counter is once its trap has been reported
+    const TRAP_PC_OFFSET: usize;
+    /// Instruction that makes a system call, which checkpoints run in place of the current one
+    const SYSCALL: &'static [u8];
+    /// DWARF numbers of the stack and frame pointers
+    const STACK_POINTER: u16;
+    const FRAME_POINTER: u16;
+    /// Where the return address is saved, relative to the frame pointer. The caller's frame
+    /// pointer is saved right at the frame pointer.
+    const RETURN_ADDRESS_OFFSET: usize;
+    /// How far returning from a function moves the stack pointer up from where it was on entry
+    const RETURN_POPS: usize;
+    /// Names of the registers by DWARF number
+    const REGISTER_NAMES: &'static [&'static str];
+    /// Number of integer and floating point arguments passed in registers
+    const MAX_INT_ARGS: usize;
+    const MAX_FLOAT_ARGS: usize;
+
+    /// The general purpose registers, as ptrace reads them
+    type Registers: Copy;
+    /// The floating point and vector registers
+    type FpRegisters: Copy;
+
+    fn registers(pid: Pid) -> Result<Self::Registers, nix::Error>;
+    fn set_registers(pid: Pid, regs: Self::Registers) -> Result<(), nix::Error>;
+    fn fp_registers(pid: Pid) -> Result<Self::FpRegisters, nix::Error>;
+    fn set_fp_registers(pid: Pid, fpregs: &Self::FpRegisters) -> Result<(), nix::Error>;
+
+    fn pc(regs: &Self::Registers) -> usize;
+    fn set_pc(regs: &mut Self::Registers, pc: usize);
+    fn stack_pointer(regs: &Self::Registers) -> usize;
+    fn set_stack_pointer(regs: &mut Self::Registers, sp: usize);
+    fn frame_pointer(regs: &Self::Registers) -> usize;
+    fn set_frame_pointer(regs: &mut Self::Registers, fp: usize);
+
+    /// Returns the value of the register with the given DWARF number.
+    fn register(regs: &Self::Registers, number: u16) -> Option<u64>;
+    /// Sets the register with the given DWARF number, if there is one.
+    fn set_register(regs: &mut Self::Registers, number: u16, value: u64);
+
+    /// Returns the registers worth showing, by name, with the program counter and stack first.
+    fn display_registers(regs: &Self::Registers) -> Vec<(&'static str, u64)>;
+
+    /// Returns the address a function stopped at its first instruction, before it has set up a
+    /// frame, will return to.
+    fn entry_return_address(pid: Pid, regs: &Self::Registers) -> Result<usize, nix::Error>;
+
+    /// Returns the integer argument of the given index, for a function stopped at its first
+    /// instruction.
+    fn int_arg(regs: &Self::Registers, index: usize) -> u64;
+    fn return_value(regs: &Self::Registers) -> u64;
+    fn set_return_value(regs: &mut Self::Registers, value: u64);
+    /// Returns the raw low 64 bits of the floating point return register.
+    fn float_return_value(fpregs: &Self::FpRegisters) -> u64;
+
+    /// Sets up the registers for `SYSCALL` to fork the process.
+    fn set_fork_syscall(regs: &mut Self::Registers);
+
+    /// Sets up the registers to call func with the given arguments and return to
+    /// return_address, leaving alone the stack in use. Floating point arguments are the raw bits
+    /// to place in the low half of their registers. Returns the stack address the return address
+    /// has to be written to, if it's passed on the stack.
+    fn set_up_call(
+        regs: &mut Self::Registers,
+        fpregs: &mut Self::FpRegisters,
+        func: usize,
+        return_address: usize,
+        int_args: &[u64],
+        float_args: &[u64],
+    ) -> Option<usize>;
+
+    /// Whether `written_ranges` decodes instructions of this architecture, which process record
+    /// needs
+    const CAN_RECORD: bool;
+
+    /// Returns the memory ranges (address, length) that the instruction at the start of code may
+    /// write, for process record, reading memory it points to with read_word. Fails with the
+    /// reason if the instruction can't be recorded, as on architectures that aren't decoded.
+    fn written_ranges(
+        code: &[u8],
+        regs: &Self::Registers,
+        read_word: &dyn Fn(u64) -> Option<u64>,
+    ) -> Result<Vec<(u64, usize)>, String>;
+
+    /// Returns the address of the breakpoint a process stopped with the given program counter has
+    /// hit, if it stopped at one.
+    fn breakpoint_at(pc: usize) -> usize {
+        pc.wrapping_sub(Self::TRAP_PC_OFFSET)
+    }
+
+    /// Returns the program counter of a process that stopped at the breakpoint at addr.
+    fn pc_after_breakpoint(addr: usize) -> usize {
+        addr + Self::TRAP_PC_OFFSET
+    }
+
+    fn register_name(number: u16) -> Option<&'static str> {
+        Self::REGISTER_NAMES.get(number as usize).copied()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub type Native = X86_64;
+#[cfg(target_arch = "aarch64")]
+pub type Native = Aarch64;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+compile_error!("deet only supports x86-64 and aarch64");
+
+pub type Registers = <Native as Arch>::Registers;
+pub type FpRegisters = <Native as Arch>::FpRegisters;
+
+/// x86-64 under the System V ABI. Breakpoints are the one-byte int3, which leaves the
+/// instruction pointer just past it, and calls push the return address on the stack.
+#[cfg(target_arch = "x86_64")]
+pub struct X86_64;
+
+#[cfg(target_arch = "x86_64")]
+impl Arch for X86_64 {
+    const NAME: &'static str = "x86-64";
+    const BREAKPOINT: &'static [u8] = &[0xcc];
+    const TRAP_PC_OFFSET: usize = 1;
+    const SYSCALL: &'static [u8] = &[0x0f, 0x05];
+    const STACK_POINTER: u16 = 7;
+    const FRAME_POINTER: u16 = 6;
+    const RETURN_ADDRESS_OFFSET: usize = 8;
+    const RETURN_POPS: usize = 8;
+    const REGISTER_NAMES: &'static [&'static str] = &[
+        "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13", "r14",
+        "r15", "rip",
+    ];
+    const MAX_INT_ARGS: usize = 6;
+    const MAX_FLOAT_ARGS: usize = 8;
+    const CAN_RECORD: bool = true;
+
+    type Registers = libc::user_regs_struct;
+    type FpRegisters = libc::user_fpregs_struct;
+
+    fn registers(pid: Pid) -> Result<libc::user_regs_struct, nix::Error> {
+        ptrace::getregs(pid)
+    }
+
+    fn set_registers(pid: Pid, regs: libc::user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(pid, regs)
+    }
+
+    fn fp_registers(pid: Pid) -> Result<libc::user_fpregs_struct, nix::Error> {
+        let mut fpregs: libc::user_fpregs_struct = unsafe { std::mem::zeroed() };
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GETFPREGS,
+                pid.as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                &mut fpregs as *mut libc::user_fpregs_struct,
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(fpregs)
+    }
+
+    fn set_fp_registers(pid: Pid, fpregs: &libc::user_fpregs_struct) -> Result<(), nix::Error> {
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETFPREGS,
+                pid.as_raw(),
+                std::ptr::null_mut::<libc::c_void>(),
+                fpregs as *const libc::user_fpregs_struct,
+            )
+        };
+        nix::errno::Errno::result(res)?;
+        Ok(())
+    }
+
+    fn pc(regs: &libc::user_regs_struct) -> usize {
+        regs.rip as usize
+    }
+
+    fn set_pc(regs: &mut libc::user_regs_struct, pc: usize) {
+        regs.rip = pc as u64;
+    }
+
+    fn stack_pointer(regs: &libc::user_regs_struct) -> usize {
+        regs.rsp as usize
+    }
+
+    fn set_stack_pointer(regs: &mut libc::user_regs_struct, sp: usize) {
+        regs.rsp = sp as u64;
+    }
+
+    fn frame_pointer(regs: &libc::user_regs_struct) -> usize {
+        regs.rbp as usize
+    }
+
+    fn set_frame_pointer(regs: &mut libc::user_regs_struct, fp: usize) {
+        regs.rbp = fp as u64;
+    }
+
+    fn register(regs: &libc::user_regs_struct, number: u16) -> Option<u64> {
+        Some(match number {
+            0 => regs.rax,
+            1 => regs.rdx,
+            2 => regs.rcx,
+            3 => regs.rbx,
+            4 => regs.rsi,
+            5 => regs.rdi,
+            6 => regs.rbp,
+            7 => regs.rsp,
+            8 => regs.r8,
+            9 => regs.r9,
+            10 => regs.r10,
+            11 => regs.r11,
+            12 => regs.r12,
+            13 => regs.r13,
+            14 => regs.r14,
+            15 => regs.r15,
+            16 => regs.rip,
+            _ => return None,
+        })
+    }
+
+    fn set_register(regs: &mut libc::user_regs_struct, number: u16, value: u64) {
+        let reg = match number {
+            0 => &mut regs.rax,
+            1 => &mut regs.rdx,
+            2 => &mut regs.rcx,
+            3 => &mut regs.rbx,
+            4 => &mut regs.rsi,
+            5 => &mut regs.rdi,
+            6 => &mut regs.rbp,
+            7 => &mut regs.rsp,
+            8 => &mut regs.r8,
+            9 => &mut regs.r9,
+            10 => &mut regs.r10,
+            11 => &mut regs.r11,
+            12 => &mut regs.r12,
+            13 => &mut regs.r13,
+            14 => &mut regs.r14,
+            15 => &mut regs.r15,
+            16 => &mut regs.rip,
+            _ => return,
+        };
+        *reg = value;
+    }
+
+    fn display_registers(regs: &libc::user_regs_struct) -> Vec<(&'static str, u64)> {
+        vec![
+            ("rip", regs.rip),
+            ("rsp", regs.rsp),
+            ("rbp", regs.rbp),
+            ("rax", regs.rax),
+            ("rbx", regs.rbx),
+            ("rcx", regs.rcx),
+            ("rdx", regs.rdx),
+            ("rsi", regs.rsi),
+            ("rdi", regs.rdi),
+            ("r8", regs.r8),
+            ("r9", regs.r9),
+            ("r10", regs.r10),
+            ("r11", regs.r11),
+            ("r12", regs.r12),
+            ("r13", regs.r13),
+            ("r14", regs.r14),
+            ("r15", regs.r15),
+            ("eflags", regs.eflags),
+        ]
+    }
+
+    fn entry_return_address(pid: Pid, regs: &libc::user_regs_struct) -> Result<usize, nix::Error> {
+        Ok(ptrace::read(pid, regs.rsp as ptrace::AddressType)? as usize)
+    }
+
+    fn int_arg(regs: &libc::user_regs_struct, index: usize) -> u64 {
+        [regs.rdi, regs.rsi, regs.rdx, regs.rcx, regs.r8, regs.r9][index]
+    }
+
+    fn return_value(regs: &libc::user_regs_struct) -> u64 {
+        regs.rax
+    }
+
+    fn set_return_value(regs: &mut libc::user_regs_struct, value: u64) {
+        regs.rax = value;
+    }
+
+    fn float_return_value(fpregs: &libc::user_fpregs_struct) -> u64 {
+        fpregs.xmm_space[0] as u64 | (fpregs.xmm_space[1] as u64) << 32
+    }
+
+    fn set_fork_syscall(regs: &mut libc::user_regs_struct) {
+        regs.rax = libc::SYS_fork as u64;
+    }
+
+    fn set_up_call(
+        regs: &mut libc::user_regs_struct,
+        fpregs: &mut libc::user_fpregs_struct,
+        func: usize,
+        _return_address: usize,
+        int_args: &[u64],
+        float_args: &[u64],
+    ) -> Option<usize> {
+        let mut int_regs = [
+            &mut regs.rdi, &mut regs.rsi, &mut regs.rdx, &mut regs.rcx, &mut regs.r8, &mut regs.r9,
+        ];
+        for (reg, val) in int_regs.iter_mut().zip(int_args) {
+            **reg = *val;
+        }
+        for (i, &bits) in float_args.iter().enumerate() {
+            fpregs.xmm_space[i * 4] = bits as u32;
+            fpregs.xmm_space[i * 4 + 1] = (bits >> 32) as u32;
+            fpregs.xmm_space[i * 4 + 2] = 0;
+            fpregs.xmm_space[i * 4 + 3] = 0;
+        }
+        // For variadic callees, al holds the number of vector registers used.
+        regs.rax = float_args.len() as u64;
+        // Skip the red zone, align the stack and make room for the return address.
+        regs.rsp = ((regs.rsp - 128) & !0xf) - 8;
+        regs.rip = func as u64;
+        Some(regs.rsp as usize)
+    }
+
+    fn written_ranges(
+        code: &[u8],
+        regs: &libc::user_regs_struct,
+        read_word: &dyn Fn(u64) -> Option<u64>,
+    ) -> Result<Vec<(u64, usize)>, String> {
+        crate::record::written_ranges(code, regs, read_word)
+    }
+}
+
+/// Note types of the register sets read with PTRACE_GETREGSET, from elf.h
+#[cfg(target_arch = "aarch64")]
+const NT_PRSTATUS: usize = 1;
+#[cfg(target_arch = "aarch64")]
+const NT_PRFPREG: usize = 2;
+/// Number of the clone system call on aarch64, which has no fork
+#[cfg(target_arch = "aarch64")]
+const AARCH64_SYS_CLONE: u64 = 220;
+
+/// The general purpose registers of an aarch64 process, laid out as the kernel's
+/// `struct user_pt_regs`.
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Aarch64Registers {
+    /// x0 to x30, where x29 is the frame pointer and x30 the link register
+    pub regs: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u64,
+}
+
+/// The vector registers of an aarch64 process, laid out as the kernel's
+/// `struct user_fpsimd_state`.
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Aarch64FpRegisters {
+    pub vregs: [u128; 32],
+    pub fpsr: u32,
+    pub fpcr: u32,
+}
+
+/// Reads the register set of the given note type, which must have T's layout.
+#[cfg(target_arch = "aarch64")]
+fn get_regset<T: Copy>(pid: Pid, note_type: usize) -> Result<T, nix::Error> {
+    let mut regs: T = unsafe { std::mem::zeroed() };
+    let mut iov = libc::iovec { iov_base: &mut regs as *mut T as *mut libc::c_void, iov_len: size_of::<T>() };
+    let res = unsafe {
+        libc::ptrace(libc::PTRACE_GETREGSET, pid.as_raw(), note_type as *mut libc::c_void, &mut iov as *mut libc::iovec)
+    };
+    nix::errno::Errno::result(res)?;
+    Ok(regs)
+}
+
+#[cfg(target_arch = "aarch64")]
+fn set_regset<T: Copy>(pid: Pid, note_type: usize, regs: &T) -> Result<(), nix::Error> {
+    let mut iov = libc::iovec { iov_base: regs as *const T as *mut libc::c_void, iov_len: size_of::<T>() };
+    let res = unsafe {
+        libc::ptrace(libc::PTRACE_SETREGSET, pid.as_raw(), note_type as *mut libc::c_void, &mut iov as *mut libc::iovec)
+    };
+    nix::errno::Errno::result(res)?;
+    Ok(())
+}
+
+/// 64-bit Arm under the AAPCS64. Breakpoints are `brk #0`, which leaves the program counter at
+/// it, and calls pass the return address in the link register, which the callee saves in its
+/// frame record along with the caller's frame pointer.
+#[cfg(target_arch = "aarch64")]
+pub struct Aarch64;
+
+#[cfg(target_arch = "aarch64")]
+impl Arch for Aarch64 {
+    const NAME: &'static str = "aarch64";
+    const BREAKPOINT: &'static [u8] = &[0x00, 0x00, 0x20, 0xd4];
+    const TRAP_PC_OFFSET: usize = 0;
+    const SYSCALL: &'static [u8] = &[0x01, 0x00, 0x00, 0xd4];
+    const STACK_POINTER: u16 = 31;
+    const FRAME_POINTER: u16 = 29;
+    const RETURN_ADDRESS_OFFSET: usize = 8;
+    const RETURN_POPS: usize = 0;
+    const REGISTER_NAMES: &'static [&'static str] = &[
+        "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14", "x15",
+        "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28", "x29", "x30",
+        "sp",
+    ];
+    const MAX_INT_ARGS: usize = 8;
+    const MAX_FLOAT_ARGS: usize = 8;
+    const CAN_RECORD: bool = false;
+
+    type Registers = Aarch64Registers;
+    type FpRegisters = Aarch64FpRegisters;
+
+    fn registers(pid: Pid) -> Result<Aarch64Registers, nix::Error> {
+        get_regset(pid, NT_PRSTATUS)
+    }
+
+    fn set_registers(pid: Pid, regs: Aarch64Registers) -> Result<(), nix::Error> {
+        set_regset(pid, NT_PRSTATUS, &regs)
+    }
+
+    fn fp_registers(pid: Pid) -> Result<Aarch64FpRegisters, nix::Error> {
+        get_regset(pid, NT_PRFPREG)
+    }
+
+    fn set_fp_registers(pid: Pid, fpregs: &Aarch64FpRegisters) -> Result<(), nix::Error> {
+        set_regset(pid, NT_PRFPREG, fpregs)
+    }
+
+    fn pc(regs: &Aarch64Registers) -> usize {
+        regs.pc as usize
+    }
+
+    fn set_pc(regs: &mut Aarch64Registers, pc: usize) {
+        regs.pc = pc as u64;
+    }
+
+    fn stack_pointer(regs: &Aarch64Registers) -> usize {
+        regs.sp as usize
+    }
+
+    fn set_stack_pointer(regs: &mut Aarch64Registers, sp: usize) {
+        regs.sp = sp as u64;
+    }
+
+    fn frame_pointer(regs: &Aarch64Registers) -> usize {
+        regs.regs[29] as usize
+    }
+
+    fn set_frame_pointer(regs: &mut Aarch64Registers, fp: usize) {
+        regs.regs[29] = fp as u64;
+    }
+
+    fn register(regs: &Aarch64Registers, number: u16) -> Option<u64> {
+        match number {
+            0..=30 => Some(regs.regs[number as usize]),
+            31 => Some(regs.sp),
+            _ => None,
+        }
+    }
+
+    fn set_register(regs: &mut Aarch64Registers, number: u16, value: u64) {
+        match number {
+            0..=30 => regs.regs[number as usize] = value,
+            31 => regs.sp = value,
+            _ => {}
+        }
+    }
+
+    fn display_registers(regs: &Aarch64Registers) -> Vec<(&'static str, u64)> {
+        let mut registers = vec![("pc", regs.pc), ("sp", regs.sp)];
+        registers.extend(Self::REGISTER_NAMES.iter().zip(regs.regs.iter()).map(|(name, value)| (*name, *value)));
+        registers.push(("pstate", regs.pstate));
+        registers
+    }
+
+    fn entry_return_address(_pid: Pid, regs: &Aarch64Registers) -> Result<usize, nix::Error> {
+        Ok(regs.regs[30] as usize)
+    }
+
+    fn int_arg(regs: &Aarch64Registers, index: usize) -> u64 {
+        regs.regs[..Self::MAX_INT_ARGS][index]
+    }
+
+    fn return_value(regs: &Aarch64Registers) -> u64 {
+        regs.regs[0]
+    }
+
+    fn set_return_value(regs: &mut Aarch64Registers, value: u64) {
+        regs.regs[0] = value;
+    }
+
+    fn float_return_value(fpregs: &Aarch64FpRegisters) -> u64 {
+        fpregs.vregs[0] as u64
+    }
+
+    fn set_fork_syscall(regs: &mut Aarch64Registers) {
+        // clone(SIGCHLD, 0, 0, 0, 0) is what fork does on architectures without it
+        regs.regs[8] = AARCH64_SYS_CLONE;
+        regs.regs[0] = libc::SIGCHLD as u64;
+        for reg in &mut regs.regs[1..5] {
+            *reg = 0;
+        }
+    }
+
+    fn set_up_call(
+        regs: &mut Aarch64Registers,
+        fpregs: &mut Aarch64FpRegisters,
+        func: usize,
+        return_address: usize,
+        int_args: &[u64],
+        float_args: &[u64],
+    ) -> Option<usize> {
+        for (reg, val) in regs.regs.iter_mut().zip(int_args) {
+            *reg = *val;
+        }
+        for (reg, &bits) in fpregs.vregs.iter_mut().zip(float_args) {
+            *reg = bits as u128;
+        }
+        regs.regs[30] = return_address as u64;
+        regs.sp &= !0xf;
+        regs.pc = func as u64;
+        None
+    }
+
+    fn written_ranges(
+        _code: &[u8],
+        _regs: &Aarch64Registers,
+        _read_word: &dyn Fn(u64) -> Option<u64>,
+    ) -> Result<Vec<(u64, usize)>, String> {
+        Err(format!("Process record is not supported on {}.", Self::NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zeroed() -> (Registers, FpRegisters) {
+        unsafe { (std::mem::zeroed(), std::mem::zeroed()) }
+    }
+
+    #[test]
+    fn maps_breakpoints_and_trap_pcs() {
+        assert_eq!(Native::breakpoint_at(Native::pc_after_breakpoint(0x1000)), 0x1000);
+        // int3 leaves the instruction pointer past it, while brk leaves it at the breakpoint
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(X86_64::breakpoint_at(0x1001), 0x1000);
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(Aarch64::breakpoint_at(0x1000), 0x1000);
+    }
+
+    #[test]
+    fn reads_and_writes_registers_by_dwarf_number() {
+        let (mut regs, _) = zeroed();
+        for number in 0..Native::REGISTER_NAMES.len() as u16 {
+            Native::set_register(&mut regs, number, 0x100 + number as u64);
+        }
+        for number in 0..Native::REGISTER_NAMES.len() as u16 {
+            assert_eq!(Native::register(&regs, number), Some(0x100 + number as u64), "{}", Native::REGISTER_NAMES[number as usize]);
+        }
+        assert_eq!(Native::register(&regs, Native::REGISTER_NAMES.len() as u16), None);
+        assert_eq!(Native::stack_pointer(&regs), 0x100 + Native::STACK_POINTER as usize);
+        assert_eq!(Native::frame_pointer(&regs), 0x100 + Native::FRAME_POINTER as usize);
+        assert_eq!(Native::register_name(Native::STACK_POINTER), Some(Native::display_registers(&regs)[1].0));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn sets_up_x86_64_calls() {
+        let (mut regs, mut fpregs) = zeroed();
+        regs.rsp = 0x7fff_1238;
+        let return_slot = X86_64::set_up_call(&mut regs, &mut fpregs, 0x401000, 0x400000, &[1, 2, 3], &[1.5f64.to_bits()]);
+        assert_eq!((regs.rdi, regs.rsi, regs.rdx), (1, 2, 3));
+        assert_eq!((0..3).map(|i| X86_64::int_arg(&regs, i)).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(fpregs.xmm_space[0] as u64 | (fpregs.xmm_space[1] as u64) << 32, 1.5f64.to_bits());
+        assert_eq!(regs.rax, 1);
+        assert_eq!(regs.rip, 0x401000);
+        // Past the red zone, and aligned once the return address is pushed
+        assert!(regs.rsp <= 0x7fff_1238 - 128);
+        assert_eq!((regs.rsp + 8) % 16, 0);
+        assert_eq!(return_slot, Some(regs.rsp as usize));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn sets_up_x86_64_fork() {
+        let (mut regs, _) = zeroed();
+        X86_64::set_fork_syscall(&mut regs);
+        assert_eq!(regs.rax, libc::SYS_fork as u64);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn sets_up_aarch64_calls() {
+        let (mut regs, mut fpregs) = zeroed();
+        regs.sp = 0x7fff_1238;
+        let return_slot = Aarch64::set_up_call(&mut regs, &mut fpregs, 0x401000, 0x400000, &[1, 2, 3], &[1.5f64.to_bits()]);
+        assert_eq!(&regs.regs[..3], &[1, 2, 3]);
+        assert_eq!(fpregs.vregs[0], 1.5f64.to_bits() as u128);
+        // The return address goes in the link register rather than on the stack
+        assert_eq!(regs.regs[30], 0x400000);
+        assert_eq!(return_slot, None);
+        assert_eq!(regs.pc, 0x401000);
+        assert_eq!(regs.sp % 16, 0);
+        assert_eq!(Aarch64::entry_return_address(Pid::from_raw(0), &regs).unwrap(), 0x400000);
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[test]
+    fn sets_up_aarch64_fork() {
+        let (mut regs, _) = zeroed();
+        regs.regs[1] = 7;
+        Aarch64::set_fork_syscall(&mut regs);
+        assert_eq!(regs.regs[8], AARCH64_SYS_CLONE);
+        assert_eq!(regs.regs[0], libc::SIGCHLD as u64);
+        assert_eq!(regs.regs[1], 0);
+    }
+}
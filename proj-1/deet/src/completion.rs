@@ -0,0 +1,140 @@
+//! Tab completion at the prompt. What's offered depends on where the cursor is: command names
+//! first, then whatever the command takes, such as locations for `break`, variables for `print`
+//! or file names for `run`.
+
+use crate::debugger_command::{find_command, Args, COMMANDS};
+use deet::dwarf_data::DwarfData;
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+pub struct CommandCompleter {
+    functions: Vec<String>,
+    globals: Vec<String>,
+    /// Base names of the source files, which locations can use on their own
+    files: Vec<String>,
+    /// Variables of the function the program is stopped in
+    locals: Vec<String>,
+    filenames: FilenameCompleter,
+}
+
+impl CommandCompleter {
+    /// Collects the names to offer from the program's debugging information.
+    pub fn new(debug_data: &DwarfData) -> CommandCompleter {
+        let sorted = |mut names: Vec<String>| {
+            names.sort();
+            names.dedup();
+            names
+        };
+        let files = debug_data
+            .source_files()
+            .iter()
+            .map(|file| file.rsplit('/').next().unwrap_or(file).to_string())
+            .collect();
+        CommandCompleter {
+            functions: sorted(debug_data.function_names().into_iter().map(str::to_string).collect()),
+            globals: sorted(debug_data.global_variable_names().into_iter().map(str::to_string).collect()),
+            files: sorted(files),
+            locals: Vec::new(),
+            filenames: FilenameCompleter::new(),
+        }
+    }
+
+    /// Sets the variables in scope where the program is stopped.
+    pub fn set_locals(&mut self, locals: Vec<String>) {
+        self.locals = locals;
+    }
+
+    /// Completes the name of a command, or the second word of one such as `info line`.
+    fn complete_command(&self, words: &[&str], word: &str) -> Vec<Pair> {
+        let mut names: Vec<&str> = COMMANDS
+            .iter()
+            .flat_map(|spec| {
+                // Aliases are only offered for the second word, where `i` stands for `info`
+                let aliases = if words.is_empty() { &[][..] } else { spec.aliases };
+                std::iter::once(spec.name).chain(aliases.iter().copied())
+            })
+            .filter_map(|name| {
+                let mut name_words = name.split(' ');
+                // The word of the name after those typed, so that `info l` offers `line`
+                if words.iter().all(|typed| name_words.next() == Some(typed)) {
+                    name_words.next()
+                } else {
+                    None
+                }
+            })
+            .filter(|name| name.starts_with(word))
+            .collect();
+        names.sort();
+        names.dedup();
+        names.into_iter().map(|name| candidate(name, " ")).collect()
+    }
+}
+
+fn candidate(name: &str, suffix: &str) -> Pair {
+    Pair { display: name.to_string(), replacement: format!("{}{}", name, suffix) }
+}
+
+fn matching<'a>(names: impl Iterator<Item = &'a String>, word: &str, suffix: &str) -> Vec<Pair> {
+    names.filter(|name| name.starts_with(word)).map(|name| candidate(name, suffix)).collect()
+}
+
+impl Completer for CommandCompleter {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let mut words: Vec<&str> = before.split_whitespace().collect();
+        // The word the cursor is in, which is empty after a space
+        let word = match before.ends_with(char::is_whitespace) || before.is_empty() {
+            true => "",
+            false => words.pop().unwrap(),
+        };
+        let start = pos - word.len();
+        let (spec, name_words) = match find_command(&words) {
+            Some(command) => command,
+            None if words.len() <= 1 => return Ok((start, self.complete_command(&words, word))),
+            None => return Ok((start, Vec::new())),
+        };
+        // Commands that start longer names, such as `set` and `record`, offer their next word
+        if name_words == words.len() && words.len() == 1 {
+            let subcommands = self.complete_command(&words, word);
+            if !subcommands.is_empty() {
+                return Ok((start, subcommands));
+            }
+        }
+        match spec.args {
+            Args::None | Args::Text => Ok((start, Vec::new())),
+            Args::Path => self.filenames.complete(line, pos, ctx),
+            Args::Command => Ok((start, self.complete_command(&words[name_words..], word))),
+            Args::Location => {
+                let mut candidates = matching(self.functions.iter(), word, " ");
+                candidates.extend(matching(self.files.iter(), word, ":"));
+                Ok((start, candidates))
+            }
+            Args::Expression => {
+                // The identifier the cursor is at the end of, which may be a Rust path
+                let part_start = word
+                    .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+                    .map_or(0, |idx| idx + 1);
+                let part = &word[part_start..];
+                let mut candidates = matching(self.locals.iter(), part, "");
+                candidates.extend(matching(self.globals.iter(), part, ""));
+                candidates.extend(matching(self.functions.iter(), part, ""));
+                Ok((pos - part.len(), candidates))
+            }
+        }
+    }
+}
+
+impl Hinter for CommandCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for CommandCompleter {}
+
+impl Validator for CommandCompleter {}
+
+impl Helper for CommandCompleter {}
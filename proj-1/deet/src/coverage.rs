@@ -0,0 +1,132 @@
+//! `deet coverage`, line coverage without rebuilding the program. A breakpoint is set at the start
+//! of every line in the line tables, and removed the first time it is hit, so that each line
+//! costs one trap at most. The lines reached are written as an lcov tracefile, which genhtml and
+//! most coverage tools read.
+//!
+//! Only the process started is covered. Processes it forks are stopped as they start, the
+//! breakpoints they inherited are taken out, and they are left to run on their own. A child of
+//! vfork shares the program's memory until it execs, so the breakpoints stay, and it would get
+//! SIGTRAP if it ran code on a line not yet reached in the meantime.
+
+use deet::arch::{Arch, Native};
+use deet::dwarf_data::DwarfData;
+use deet::inferior::{Event, Inferior, LaunchOptions, Redirections, Status};
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+
+/// Lines and functions of one source file, and whether they were reached.
+#[derive(Default)]
+struct FileCoverage {
+    lines: BTreeMap<usize, bool>,
+    /// Functions by name, with their line and whether they were called
+    functions: BTreeMap<String, (usize, bool)>,
+}
+
+/// Formats the coverage of each file as an lcov tracefile.
+fn tracefile(files: &BTreeMap<String, FileCoverage>) -> String {
+    let mut out = String::from("TN:\n");
+    for (path, file) in files {
+        out += &format!("SF:{}\n", path);
+        for (name, (line, _)) in &file.functions {
+            out += &format!("FN:{},{}\n", line, name);
+        }
+        for (name, (_, called)) in &file.functions {
+            out += &format!("FNDA:{},{}\n", *called as usize, name);
+        }
+        out += &format!("FNF:{}\n", file.functions.len());
+        out += &format!("FNH:{}\n", file.functions.values().filter(|(_, called)| *called).count());
+        for (line, hit) in &file.lines {
+            out += &format!("DA:{},{}\n", line, *hit as usize);
+        }
+        out += &format!("LF:{}\n", file.lines.len());
+        out += &format!("LH:{}\n", file.lines.values().filter(|hit| **hit).count());
+        out += "end_of_record\n";
+    }
+    out
+}
+
+/// Runs target with args to completion and writes the lines it reached to output.
+pub fn coverage(target: &str, args: &[String], output: &str) -> Result<(), String> {
+    let debug_data = DwarfData::from_file(target).map_err(|err| format!("Could not load {}: {:?}", target, err))?;
+    let (args, redirections) = Redirections::parse(args)?;
+
+    // Only the last row at an address takes effect, so that's the line the address belongs to
+    let mut lines_at: HashMap<usize, (&str, usize)> = HashMap::new();
+    let mut functions = Vec::new();
+    for (file, _) in debug_data.units() {
+        for line in file.lines.iter().filter(|line| line.number > 0) {
+            lines_at.insert(line.address, (line.file.as_str(), line.number));
+        }
+        functions.extend(file.functions.iter().filter(|func| func.address != 0));
+    }
+    let mut break_points: HashMap<usize, Vec<u8>> = lines_at.keys().map(|addr| (*addr, Vec::new())).collect();
+    let mut inferior = Inferior::new(target, &args, &LaunchOptions::default(), &redirections).map_err(|err| err.to_string())?;
+    let invalid = inferior.insert_breakpoints(&mut break_points);
+    if !invalid.is_empty() {
+        println!(
+            "Could not set breakpoints at {} of {} line addresses, whose lines count as not reached",
+            invalid.len(),
+            lines_at.len()
+        );
+    }
+    inferior
+        .set_ptrace_options(ptrace::Options::PTRACE_O_TRACEFORK | ptrace::Options::PTRACE_O_TRACEVFORK)
+        .map_err(|err| format!("Could not trace forks: {}", err))?;
+
+    let mut reached = HashSet::new();
+    let mut signal = None;
+    let status = loop {
+        inferior.resume(signal.take()).map_err(|err| format!("Could not resume the program: {}", err))?;
+        match inferior.wait(None).map_err(|err| format!("Could not wait for the program: {}", err))? {
+            Status::Stopped(Signal::SIGTRAP, pc, true) if break_points.contains_key(&Native::breakpoint_at(pc)) => {
+                let addr = Native::breakpoint_at(pc);
+                let orig_bytes = break_points.remove(&addr).unwrap();
+                inferior
+                    .remove_breakpoint(addr, &orig_bytes)
+                    .map_err(|err| format!("Could not remove the breakpoint at {:#x}: {}", addr, err))?;
+                reached.insert(addr);
+            }
+            // Pass on the signals the program gets
+            Status::Stopped(other, ..) => signal = Some(other),
+            Status::Event(Event::Fork(child), _) => inferior
+                .detach_child(child, &break_points, false)
+                .map_err(|err| format!("Could not detach from forked process {}: {}", child, err))?,
+            // The child of a vfork shares the memory, breakpoints included, until it execs
+            Status::Event(Event::Vfork(child), _) => inferior
+                .detach_child(child, &break_points, true)
+                .map_err(|err| format!("Could not detach from forked process {}: {}", child, err))?,
+            status => break status,
+        }
+    };
+    match status {
+        Status::Exited(exit_code) => println!("Child exited (status {})", exit_code),
+        Status::Signaled(signal) => println!("Child exited due to signal {}", signal),
+        Status::Stopped(..) | Status::Event(..) => {}
+    }
+
+    let mut files: BTreeMap<String, FileCoverage> = BTreeMap::new();
+    for (addr, (path, number)) in &lines_at {
+        let hit = files.entry(path.to_string()).or_default().lines.entry(*number).or_insert(false);
+        *hit |= reached.contains(addr);
+    }
+    for func in functions {
+        let line = match debug_data.get_line_from_addr(func.address) {
+            Some(line) => line,
+            None => continue,
+        };
+        let name = func.linkage_name.clone().unwrap_or_else(|| func.qualified_name.clone());
+        let file = files.entry(line.file).or_default();
+        file.functions.insert(name, (func.line_number, reached.contains(&func.address)));
+    }
+
+    fs::write(output, tracefile(&files)).map_err(|err| format!("Could not write {}: {}", output, err))?;
+    for (path, file) in &files {
+        let hit = file.lines.values().filter(|hit| **hit).count();
+        let total = file.lines.len();
+        println!("{}: {} of {} lines ({:.1}%)", path, hit, total, 100.0 * hit as f64 / total as f64);
+    }
+    println!("Wrote coverage of {} files to {}", files.len(), output);
+    Ok(())
+}
@@ -0,0 +1,310 @@
+//! Locates debugging information kept outside of the executable: separate debug files found
+//! through `.note.gnu.build-id` or `.gnu_debuglink` (as installed by distribution `-dbg`/
+//! `-debuginfo` packages), and split DWARF `.dwo` files and `.dwp` packages.
+
+use object::{Object, ObjectSection};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Colon-separated list of directories searched for separate debug files, like gdb's
+/// `set debug-file-directory`
+const DEBUG_DIRECTORY_VAR: &str = "DEET_DEBUG_FILE_DIRECTORY";
+const DEFAULT_DEBUG_DIRECTORY: &str = "/usr/lib/debug";
+
+pub fn debug_file_directories() -> Vec<PathBuf> {
+    let dirs = std::env::var(DEBUG_DIRECTORY_VAR).unwrap_or_else(|_| DEFAULT_DEBUG_DIRECTORY.to_string());
+    dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from).collect()
+}
+
+/// Returns the (decompressed) contents of the named section, if the object has it.
+pub fn section_data<'data>(object: &object::File<'data>, name: &str) -> Option<Cow<'data, [u8]>> {
+    object.section_by_name(name)?.uncompressed_data().ok()
+}
+
+/// Returns true if the object has DWARF debugging information of its own.
+pub fn has_debug_info(object: &object::File) -> bool {
+    section_data(object, ".debug_info").is_some_and(|data| !data.is_empty())
+}
+
+/// Finds the separate debug file for the executable at path, first by build ID
+/// (`<dir>/.build-id/ab/cdef.debug`) and then by the name in its `.gnu_debuglink` section.
+pub fn find_debug_file(path: &Path, object: &object::File) -> Option<PathBuf> {
+    let dirs = debug_file_directories();
+    if let Some(build_id) = build_id(object) {
+        if build_id.len() > 1 {
+            let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+            let found = dirs
+                .iter()
+                .map(|dir| dir.join(".build-id").join(&hex[..2]).join(format!("{}.debug", &hex[2..])))
+                .find(|candidate| candidate.is_file());
+            if found.is_some() {
+                return found;
+            }
+        }
+    }
+
+    let (name, crc) = debuglink(object)?;
+    // Canonicalize so that the global debug directories mirror the real location of the file
+    let exe_dir = fs::canonicalize(path).ok()?.parent()?.to_path_buf();
+    let mut candidates = vec![exe_dir.join(&name), exe_dir.join(".debug").join(&name)];
+    for dir in &dirs {
+        candidates.push(dir.join(exe_dir.strip_prefix("/").unwrap_or(&exe_dir)).join(&name));
+    }
+    candidates
+        .into_iter()
+        .find(|candidate| candidate.is_file() && fs::read(candidate).is_ok_and(|data| crc32(&data) == crc))
+}
+
+/// Returns the descriptor of the NT_GNU_BUILD_ID note.
+pub fn build_id(object: &object::File) -> Option<Vec<u8>> {
+    let data = section_data(object, ".note.gnu.build-id")?;
+    parse_build_id(&data, object.is_little_endian())
+}
+
+/// Finds the NT_GNU_BUILD_ID note among the notes of a note section.
+fn parse_build_id(data: &[u8], little_endian: bool) -> Option<Vec<u8>> {
+    const NT_GNU_BUILD_ID: u32 = 3;
+    let mut offset = 0;
+    while offset + 12 <= data.len() {
+        let name_size = read_u32(data, offset, little_endian)? as usize;
+        let desc_size = read_u32(data, offset + 4, little_endian)? as usize;
+        let note_type = read_u32(data, offset + 8, little_endian)?;
+        let name_start = offset + 12;
+        let desc_start = name_start + align4(name_size);
+        let name = data.get(name_start..name_start + name_size)?;
+        if note_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(data.get(desc_start..desc_start + desc_size)?.to_vec());
+        }
+        offset = desc_start + align4(desc_size);
+    }
+    None
+}
+
+/// Returns the file name and CRC-32 of the debug file named in `.gnu_debuglink`.
+fn debuglink(object: &object::File) -> Option<(String, u32)> {
+    let data = section_data(object, ".gnu_debuglink")?;
+    parse_debuglink(&data, object.is_little_endian())
+}
+
+/// Parses a `.gnu_debuglink` section: a NUL-terminated file name, padded to four bytes, then
+/// the CRC-32 of the file.
+fn parse_debuglink(data: &[u8], little_endian: bool) -> Option<(String, u32)> {
+    let name_len = data.iter().position(|b| *b == 0)?;
+    let name = String::from_utf8_lossy(&data[..name_len]).into_owned();
+    let crc = read_u32(data, align4(name_len + 1), little_endian)?;
+    Some((name, crc))
+}
+
+/// Finds the .dwo file holding a split unit. Its DW_AT_dwo_name is relative to the unit's
+/// compilation directory, but we also look next to the executable in case the objects moved.
+pub fn find_dwo_file(dwo_name: &str, comp_dir: Option<&str>, path: &Path) -> Option<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(comp_dir) = comp_dir {
+        candidates.push(Path::new(comp_dir).join(dwo_name));
+    }
+    candidates.push(PathBuf::from(dwo_name));
+    if let (Some(dir), Some(file_name)) = (path.parent(), Path::new(dwo_name).file_name()) {
+        candidates.push(dir.join(file_name));
+    }
+    candidates.into_iter().find(|candidate| candidate.is_file())
+}
+
+/// Section contributions (section id, offset, size) of each unit, by DWO ID
+type UnitContributions = HashMap<u64, Vec<(u32, usize, usize)>>;
+
+/// Where the contents of a section of a package are.
+enum SectionData {
+    Mapped(usize, usize), // Offset and size in the mapped file
+    Decompressed(Vec<u8>),
+}
+
+/// A DWARF package (`.dwp`), which bundles the .dwo files of a program into one set of
+/// sections. Its `.debug_cu_index` maps each unit's DWO ID to the parts of those sections that
+/// belong to it.
+pub struct DwarfPackage {
+    mmap: memmap::Mmap,
+    sections: HashMap<String, SectionData>,
+    units: UnitContributions,
+}
+
+impl DwarfPackage {
+    /// Opens the package next to the executable at path (`<path>.dwp`), if there is one.
+    pub fn open(path: &Path) -> Option<DwarfPackage> {
+        let mut dwp_path = path.as_os_str().to_os_string();
+        dwp_path.push(".dwp");
+        let file = fs::File::open(dwp_path).ok()?;
+        let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+        let (sections, units) = {
+            let object = object::File::parse(&mmap).ok()?;
+            let index = section_data(&object, ".debug_cu_index")?;
+            let units = parse_cu_index(&index, object.is_little_endian())?;
+            // Sections are sliced out of the mapping, and only copied if they are compressed
+            let mut sections = HashMap::new();
+            for section in object.sections() {
+                let (name, data) = match (section.name(), section.uncompressed_data()) {
+                    (Ok(name), Ok(data)) if name.ends_with(".dwo") => (name.to_string(), data),
+                    _ => continue,
+                };
+                let data = match data {
+                    Cow::Borrowed(data) => SectionData::Mapped(data.as_ptr() as usize - mmap.as_ptr() as usize, data.len()),
+                    Cow::Owned(data) => SectionData::Decompressed(data),
+                };
+                sections.insert(name, data);
+            }
+            (sections, units)
+        };
+        Some(DwarfPackage { mmap, sections, units })
+    }
+
+    pub fn contains(&self, dwo_id: u64) -> bool {
+        self.units.contains_key(&dwo_id)
+    }
+
+    /// Returns the part of the named section (e.g. `.debug_info.dwo`) that belongs to the unit
+    /// with the given DWO ID. Sections that aren't indexed, like `.debug_str.dwo`, are shared
+    /// by all units and returned whole.
+    pub fn section(&self, dwo_id: u64, name: &str) -> Option<Cow<'_, [u8]>> {
+        let data: &[u8] = match self.sections.get(name)? {
+            SectionData::Mapped(offset, size) => self.mmap.get(*offset..*offset + *size)?,
+            SectionData::Decompressed(data) => data,
+        };
+        let contributions = self.units.get(&dwo_id)?;
+        let section_id = match name {
+            ".debug_info.dwo" => 1,
+            ".debug_abbrev.dwo" => 3,
+            ".debug_line.dwo" => 4,
+            ".debug_loc.dwo" | ".debug_loclists.dwo" => 5,
+            ".debug_str_offsets.dwo" => 6,
+            ".debug_rnglists.dwo" => 8,
+            _ => return Some(Cow::Borrowed(data)),
+        };
+        let (_, offset, size) = contributions.iter().find(|(id, _, _)| *id == section_id)?;
+        Some(Cow::Borrowed(data.get(*offset..*offset + *size)?))
+    }
+}
+
+/// Parses a `.debug_cu_index` section (version 2, the GNU extension, or version 5).
+fn parse_cu_index(data: &[u8], little_endian: bool) -> Option<UnitContributions> {
+    let version = read_u32(data, 0, little_endian)?;
+    if version != 2 && version & 0xffff != 5 {
+        return None;
+    }
+    let columns = read_u32(data, 4, little_endian)? as usize;
+    let unit_count = read_u32(data, 8, little_endian)? as usize;
+    let slots = read_u32(data, 12, little_endian)? as usize;
+    let signatures = 16;
+    let indices = signatures + 8 * slots;
+    let section_ids = indices + 4 * slots;
+    let offsets = section_ids + 4 * columns;
+    let sizes = offsets + 4 * columns * unit_count;
+
+    let mut units = HashMap::new();
+    for slot in 0..slots {
+        let row = read_u32(data, indices + 4 * slot, little_endian)? as usize;
+        if row == 0 {
+            continue;
+        }
+        let dwo_id = read_u64(data, signatures + 8 * slot, little_endian)?;
+        let mut contributions = Vec::new();
+        for column in 0..columns {
+            let cell = 4 * ((row - 1) * columns + column);
+            contributions.push((
+                read_u32(data, section_ids + 4 * column, little_endian)?,
+                read_u32(data, offsets + cell, little_endian)? as usize,
+                read_u32(data, sizes + cell, little_endian)? as usize,
+            ));
+        }
+        units.insert(dwo_id, contributions);
+    }
+    Some(units)
+}
+
+fn read_u32(data: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+}
+
+fn read_u64(data: &[u8], offset: usize, little_endian: bool) -> Option<u64> {
+    let bytes = data.get(offset..offset + 8)?.try_into().ok()?;
+    Some(if little_endian { u64::from_le_bytes(bytes) } else { u64::from_be_bytes(bytes) })
+}
+
+fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// The CRC-32 (as used by zlib) that .gnu_debuglink records for the debug file.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn computes_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn finds_build_id_note() {
+        // An NT_GNU_ABI_TAG note to skip, then the build ID, whose 3-byte descriptor is padded
+        let mut data = u32s(&[4, 16, 1]);
+        data.extend(b"GNU\0");
+        data.extend(u32s(&[0, 3, 10, 0]));
+        data.extend(u32s(&[4, 3, 3]));
+        data.extend(b"GNU\0");
+        data.extend(vec![0xab, 0xcd, 0xef, 0]);
+        assert_eq!(parse_build_id(&data, true), Some(vec![0xab, 0xcd, 0xef]));
+        assert_eq!(parse_build_id(&data[..32], true), None);
+    }
+
+    #[test]
+    fn parses_debuglink() {
+        // The name and its terminator take 10 bytes, padded to 12
+        let mut data = b"prog.debug\0\0".to_vec();
+        data.extend(u32s(&[0x1234_5678]));
+        assert_eq!(parse_debuglink(&data, true), Some(("prog.debug".to_string(), 0x1234_5678)));
+        assert_eq!(parse_debuglink(&data[..12], true), None);
+    }
+
+    #[test]
+    fn parses_cu_index() {
+        // Two columns (.debug_info.dwo and .debug_abbrev.dwo), two units and four hash slots,
+        // of which slots 1 and 3 are used
+        let mut data = u32s(&[2, 2, 2, 4]);
+        for signature in [0u64, 0x1111, 0, 0x2222].iter() {
+            data.extend(signature.to_le_bytes().to_vec());
+        }
+        data.extend(u32s(&[0, 2, 0, 1])); // rows, counting from 1
+        data.extend(u32s(&[1, 3])); // section ids
+        data.extend(u32s(&[0, 0, 0x40, 0x20])); // offsets
+        data.extend(u32s(&[0x40, 0x20, 0x30, 0x10])); // sizes
+
+        let units = parse_cu_index(&data, true).unwrap();
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[&0x1111], vec![(1, 0x40, 0x30), (3, 0x20, 0x10)]);
+        assert_eq!(units[&0x2222], vec![(1, 0, 0x40), (3, 0, 0x20)]);
+    }
+
+    #[test]
+    fn rejects_unknown_cu_index_version() {
+        assert_eq!(parse_cu_index(&u32s(&[3, 0, 0, 0]), true), None);
+    }
+}
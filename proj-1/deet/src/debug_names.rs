@@ -0,0 +1,233 @@
+//! Reads the DWARF 5 name index, `.debug_names`, which lists the functions, variables and types
+//! each compilation unit defines. With it, the index of units can be built without parsing them.
+
+use std::convert::TryInto;
+
+/// A name in the index: the unit defining it, by offset in .debug_info, the tag of the DIE and
+/// the offset of the name in .debug_str.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameEntry {
+    pub unit_offset: usize,
+    pub tag: u16,
+    pub name_offset: usize,
+}
+
+/// Returns the entries of every name index in a .debug_names section, or None if it's malformed
+/// or uses a form we don't know how to skip.
+pub fn parse(data: &[u8], little_endian: bool) -> Option<Vec<NameEntry>> {
+    let mut entries = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let mut reader = Reader { data, pos: start, little_endian, offset_size: 4 };
+        let mut length = reader.u32()? as usize;
+        if length == 0xffff_ffff {
+            reader.offset_size = 8;
+            length = reader.u64()? as usize;
+        }
+        let end = reader.pos.checked_add(length)?;
+        reader.data = data.get(..end)?;
+        parse_index(&mut reader, &mut entries)?;
+        start = end;
+    }
+    Some(entries)
+}
+
+/// Parses the index whose header follows the unit length reader has just read. The reader's
+/// data ends with the index.
+fn parse_index(reader: &mut Reader, entries: &mut Vec<NameEntry>) -> Option<()> {
+    if reader.u16()? != 5 {
+        return None;
+    }
+    reader.u16()?; // padding
+    let comp_unit_count = reader.u32()? as usize;
+    let local_type_unit_count = reader.u32()? as usize;
+    let foreign_type_unit_count = reader.u32()? as usize;
+    let bucket_count = reader.u32()? as usize;
+    let name_count = reader.u32()? as usize;
+    let abbrev_table_size = reader.u32()? as usize;
+    let augmentation_string_size = reader.u32()? as usize;
+    reader.skip((augmentation_string_size + 3) & !3)?;
+
+    let mut units = Vec::with_capacity(comp_unit_count);
+    for _ in 0..comp_unit_count {
+        units.push(reader.offset()?);
+    }
+    reader.skip(local_type_unit_count * reader.offset_size + foreign_type_unit_count * 8)?;
+    // The hash table is only there to look names up, which we don't need
+    reader.skip(bucket_count * 4 + if bucket_count > 0 { name_count * 4 } else { 0 })?;
+    let mut name_offsets = Vec::with_capacity(name_count);
+    for _ in 0..name_count {
+        name_offsets.push(reader.offset()?);
+    }
+    let mut entry_offsets = Vec::with_capacity(name_count);
+    for _ in 0..name_count {
+        entry_offsets.push(reader.offset()?);
+    }
+
+    // Abbreviations: code, tag and the (index attribute, form) pairs of entries using them. The
+    // entries follow them.
+    let entry_pool = reader.pos.checked_add(abbrev_table_size)?;
+    let mut abbrevs = Vec::new();
+    loop {
+        let code = reader.uleb()?;
+        if code == 0 {
+            break;
+        }
+        let tag = reader.uleb()? as u16;
+        let mut attrs = Vec::new();
+        loop {
+            let (attr, form) = (reader.uleb()?, reader.uleb()?);
+            if attr == 0 && form == 0 {
+                break;
+            }
+            attrs.push((attr, form));
+        }
+        abbrevs.push((code, tag, attrs));
+    }
+
+    for (name_offset, entry_offset) in name_offsets.into_iter().zip(entry_offsets) {
+        reader.pos = entry_pool.checked_add(entry_offset)?;
+        loop {
+            let code = reader.uleb()?;
+            if code == 0 {
+                break;
+            }
+            let (_, tag, attrs) = abbrevs.iter().find(|(abbrev_code, _, _)| *abbrev_code == code)?;
+            // A unit needn't be named if the index only covers one
+            let mut unit = if comp_unit_count == 1 { Some(0) } else { None };
+            for (attr, form) in attrs {
+                let value = reader.form(*form)?;
+                if gimli::DwIdx(*attr as u16) == gimli::DW_IDX_compile_unit {
+                    unit = Some(value as usize);
+                }
+            }
+            // Entries of type units have no compilation unit
+            if let Some(unit_offset) = unit.and_then(|unit| units.get(unit)) {
+                entries.push(NameEntry { unit_offset: *unit_offset, tag: *tag, name_offset });
+            }
+        }
+    }
+    Some(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+    /// 4 for 32-bit DWARF, 8 for 64-bit DWARF
+    offset_size: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(N)?)?.try_into().ok()?;
+        self.pos += N;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let bytes = self.bytes()?;
+        Some(if self.little_endian { u64::from_le_bytes(bytes) } else { u64::from_be_bytes(bytes) })
+    }
+
+    fn offset(&mut self) -> Option<usize> {
+        if self.offset_size == 8 {
+            Some(self.u64()? as usize)
+        } else {
+            Some(self.u32()? as usize)
+        }
+    }
+
+    fn uleb(&mut self) -> Option<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.pos = self.pos.checked_add(len)?;
+        if self.pos > self.data.len() {
+            return None;
+        }
+        Some(())
+    }
+
+    /// Reads a value of the given form, as the index attributes use them.
+    fn form(&mut self, form: u64) -> Option<u64> {
+        Some(match gimli::DwForm(form as u16) {
+            gimli::DW_FORM_data1 | gimli::DW_FORM_ref1 | gimli::DW_FORM_flag => self.u8()? as u64,
+            gimli::DW_FORM_data2 | gimli::DW_FORM_ref2 => self.u16()? as u64,
+            gimli::DW_FORM_data4 | gimli::DW_FORM_ref4 => self.u32()? as u64,
+            gimli::DW_FORM_data8 | gimli::DW_FORM_ref8 | gimli::DW_FORM_ref_sig8 => self.u64()?,
+            gimli::DW_FORM_udata | gimli::DW_FORM_ref_udata => self.uleb()?,
+            gimli::DW_FORM_flag_present => 1,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn parses_names_of_each_unit() {
+        // Two units, no hash table, and names 0x10 (a function) and 0x20 (a variable)
+        let mut body = vec![5, 0, 0, 0];
+        body.extend(u32s(&[2, 0, 0, 0, 2]));
+        let abbrevs = vec![
+            1, 0x2e, 1, 0x0b, 3, 0x13, 0, 0, // DW_TAG_subprogram: unit as data1, DIE as ref4
+            2, 0x34, 1, 0x0b, 3, 0x13, 0, 0, // DW_TAG_variable: the same
+            0,
+        ];
+        body.extend(u32s(&[abbrevs.len() as u32, 0]));
+        body.extend(u32s(&[0x0, 0x40])); // unit offsets
+        body.extend(u32s(&[0x10, 0x20])); // name offsets
+        body.extend(u32s(&[0, 7])); // entry offsets
+        body.extend(abbrevs);
+        body.extend(vec![1, 1, 0x2a, 0, 0, 0, 0]);
+        body.extend(vec![2, 0, 0x2b, 0, 0, 0, 0]);
+        let mut data = u32s(&[body.len() as u32]);
+        data.extend(body);
+
+        assert_eq!(
+            parse(&data, true),
+            Some(vec![
+                NameEntry { unit_offset: 0x40, tag: 0x2e, name_offset: 0x10 },
+                NameEntry { unit_offset: 0x0, tag: 0x34, name_offset: 0x20 },
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_index() {
+        assert_eq!(parse(&[8, 0, 0, 0, 5, 0], true), None);
+    }
+}
@@ -1,156 +1,918 @@
-use crate::debugger_command::DebuggerCommand;
-use crate::inferior::Inferior;
+use crate::completion::CommandCompleter;
+use crate::debugger_command::{self, DebuggerCommand};
+use deet::arch::{Arch, Native};
+use deet::inferior::{Event, Redirections};
+use deet::shared_library;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use crate::inferior::Status;
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use std::collections::HashMap;
+use deet::expression::{self, Expr, Value};
+use deet::error::DeetError;
+use deet::target::{Stepping, StopEvent, Target};
+use crate::info;
+use crate::printf::Format;
+use crate::process_info;
+use crate::session;
+use crate::tui::{Key, Snapshot, Tui};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
 
 pub struct Debugger {
-    target: String,
+    target: Target,
     history_path: String,
-    readline: Editor<()>,
-    inferior: Option<Inferior>,
-    debug_data: DwarfData,
-    break_points: HashMap<usize, u8>,
+    readline: Editor<CommandCompleter>,
+    /// The last command typed, when it's one that an empty line repeats
+    repeat_line: Option<String>,
+    temp_break_points: HashSet<usize>,
+    /// The command that set each breakpoint, by address, which is what sessions save
+    break_commands: HashMap<usize, String>,
+    /// Breakpoints that print their format and arguments and continue, by address
+    dprintfs: HashMap<usize, (Format, Vec<Expr>)>,
+    /// The full-screen interface, when started with --tui
+    tui: Option<Tui>,
+    /// Expressions shown at every stop, by display number
+    displays: Vec<(usize, String)>,
+    next_display_number: usize,
+    /// Where the target's session is kept
+    session_path: Option<PathBuf>,
+    /// Whether to save the session on quitting, which is until it's deleted
+    save_session_on_quit: bool,
+    /// Commands to run before reading any, such as those restoring the session
+    queued_commands: VecDeque<String>,
+}
+
+fn parse_int(val: &str) -> Option<i64> {
+    if let Some(val) = val.strip_prefix('-') {
+        return parse_int(val).map(|v| -v);
+    }
+    if val.to_lowercase().starts_with("0x") {
+        return i64::from_str_radix(&val[2..], 16).ok();
+    }
+    val.parse().ok()
 }
 
 impl Debugger {
-    /// Initializes the debugger.
-    pub fn new(target: &str) -> Debugger {
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => val,
-            Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
-            }
-            Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
-            }
-        };
+    /// Initializes the debugger, loading the target's debugging information.
+    pub fn new(path: &str) -> Result<Debugger, DeetError> {
+        let mut target = Target::open(path)?;
 
-        let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap());
-        let mut readline = Editor::<()>::new();
+        let history_path = format!("{}/.deet_history", std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+        let mut readline = Editor::<CommandCompleter>::new();
+        readline.set_helper(Some(CommandCompleter::new(target.debug_data())));
         // Attempt to load history from ~/.deet_history if it exists
         let _ = readline.load_history(&history_path);
-        
-        debug_data.print();
-        Debugger {
-            target: target.to_string(),
+
+        // Stop when a Rust program panics, before its stack is unwound.
+        if let Some(addr) = ["rust_begin_unwind", "rust_panic"]
+            .iter()
+            .find_map(|name| target.debug_data().get_addr_for_function(None, name))
+        {
+            println!("Breakpoint set on Rust panics at {:#x}", addr);
+            target.set_breakpoint(addr)?;
+        }
+        let session_path = session::session_path(path);
+        let queued_commands: VecDeque<String> = session_path.as_ref().map(|path| session::load(path)).unwrap_or_default().into();
+        if !queued_commands.is_empty() {
+            println!("Restoring session from {}", session_path.as_ref().unwrap().display());
+        }
+        Ok(Debugger {
+            target,
             history_path,
             readline,
-            inferior: None,
-            debug_data: debug_data,
-            break_points: HashMap::new(),
+            repeat_line: None,
+            temp_break_points: HashSet::new(),
+            break_commands: HashMap::new(),
+            dprintfs: HashMap::new(),
+            tui: None,
+            displays: Vec::new(),
+            next_display_number: 1,
+            session_path,
+            save_session_on_quit: true,
+            queued_commands,
+        })
+    }
+
+    /// Switches to the full-screen interface, which lasts until the debugger exits.
+    pub fn enable_tui(&mut self) {
+        match Tui::new() {
+            Ok(tui) => self.tui = Some(tui),
+            Err(err) => println!("Could not start the full-screen interface: {}", err),
         }
     }
 
     pub fn run(&mut self) {
         loop {
-            match self.get_next_command() {
-                DebuggerCommand::Run(args) => {
-                    if self.inferior.is_some() {
-                        self.inferior.as_mut().unwrap().kill();
-                        self.inferior = None;
-                    }
-                    if let Some(inferior) = Inferior::new(&self.target, &args, &mut self.break_points) {
-                        // Create the inferior
-                        self.inferior = Some(inferior);
-                        self.with_continue();
-                    } else {
-                        println!("Error starting subprocess");
-                    }
+            for warning in self.target.debug_data().take_warnings() {
+                println!("{}", warning);
+            }
+            let cmd = self.get_next_command();
+            let quit = matches!(cmd, DebuggerCommand::Quit);
+            if let Err(err) = self.execute(cmd) {
+                self.report_error(err);
+            }
+            if quit {
+                return;
+            }
+        }
+    }
+
+    /// Prints an error a command ended with. If the process went away under the command, it's
+    /// reaped to tell how it ended.
+    fn report_error(&mut self, err: DeetError) {
+        println!("{}", err);
+        if let DeetError::ProcessExited = err {
+            if let Some(event) = self.target.reap() {
+                self.report_stop(event);
+            }
+        }
+    }
+
+    fn execute(&mut self, cmd: DebuggerCommand) -> Result<(), DeetError> {
+        match cmd {
+            DebuggerCommand::Run(args) => {
+                let (args, redirections) = Redirections::parse(&args)?;
+                self.kill();
+                for addr in self.target.start(&args, &redirections)? {
+                    println!("Invalid breakpoint address {:#x}", addr);
                 }
-                DebuggerCommand::Continue => {
-                    if self.inferior.is_none() {
-                        println!("No process is running");
-                    }
-                    else {
-                        self.with_continue();
-                    }
+                self.with_continue()?;
+            }
+            DebuggerCommand::SetEnv(name, value) => {
+                self.target.launch_options_mut().env.insert(name, Some(value));
+            }
+            DebuggerCommand::UnsetEnv(name) => {
+                self.target.launch_options_mut().env.insert(name, None);
+            }
+            DebuggerCommand::SetCwd(cwd) => {
+                if let Some(dir) = cwd.as_ref().filter(|dir| !std::path::Path::new(dir).is_dir()) {
+                    return Err(DeetError::Input(format!("{} is not a directory", dir)));
+                }
+                self.target.launch_options_mut().cwd = cwd;
+            }
+            DebuggerCommand::SetInferiorTty(tty) => {
+                self.target.launch_options_mut().tty = tty;
+            }
+            DebuggerCommand::Step => self.step(false)?,
+            DebuggerCommand::Next => self.step(true)?,
+            DebuggerCommand::Finish => self.finish()?,
+            DebuggerCommand::Stepi => {
+                match self.target.step_instruction()? {
+                    StopEvent::Stepped(addr) | StopEvent::Breakpoint(addr) => self.print_location(addr),
+                    event => self.report_stop(event),
+                }
+            }
+            DebuggerCommand::Catch(catch) => {
+                if self.target.set_catch(catch)? {
+                    println!("Catchpoint set on {}", catch);
+                } else {
+                    println!("Already catching {}", catch);
                 }
-                DebuggerCommand::Backtrace => {
-                    if self.inferior.is_none() {
-                        println!("No process is running");
+            }
+            DebuggerCommand::DeleteCatch(catch) => {
+                if self.target.remove_catch(catch)? {
+                    println!("Deleted the catchpoint on {}", catch);
+                } else {
+                    println!("No catchpoint on {}.", catch);
+                }
+            }
+            DebuggerCommand::Clear(args) => {
+                let addr = self.target.resolve_location(&args)?;
+                if self.target.breakpoints().contains_key(&addr) {
+                    self.delete_breakpoint(addr);
+                    println!("Deleted breakpoint at {:#x}", addr);
+                } else {
+                    println!("No breakpoint at {}.", args);
+                }
+            }
+            DebuggerCommand::Continue => self.with_continue()?,
+            DebuggerCommand::Backtrace => {
+                for frame in self.target.backtrace()? {
+                    println!("{}", frame);
+                }
+            }
+            DebuggerCommand::Break(args) => {
+                // Functions also get a breakpoint in each copy the compiler inlined
+                let inlined: Vec<usize> =
+                    self.target.debug_data().find_inlined_calls(&args).iter().map(|call| call.entry).collect();
+                match self.target.resolve_location(&args) {
+                    Ok(addr) => {
+                        if self.set_breakpoint(addr) {
+                            self.break_commands.insert(addr, format!("break {}", args));
+                        }
                     }
-                    else {
-                        self.inferior.as_mut().unwrap().print_backtrace(&self.debug_data).ok();
+                    // A function that only exists inlined has no address of its own
+                    Err(DeetError::Input(_)) if !inlined.is_empty() => {}
+                    Err(err) => return Err(err),
+                }
+                if !inlined.is_empty() {
+                    let set: Vec<usize> = inlined.into_iter().filter(|addr| self.set_breakpoint(*addr)).collect();
+                    println!("Breakpoint set at {} inlined copies of {}", set.len(), args);
+                    for addr in set {
+                        self.break_commands.insert(addr, format!("break {}", args));
                     }
                 }
-                DebuggerCommand::Break(args) => {
-                    fn parse_address(addr: &str, radix: u32) -> Option<usize> {
-                        let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
-                            &addr[2..]
-                        } else {
-                            &addr
-                        };
-                        usize::from_str_radix(addr_without_0x, radix).ok()
+            }
+            DebuggerCommand::TempBreak(args) => {
+                let addr = self.target.resolve_location(&args)?;
+                if self.set_breakpoint(addr) {
+                    self.temp_break_points.insert(addr);
+                }
+            }
+            DebuggerCommand::Dprintf(location, format_text, args) => {
+                let addr = self.target.resolve_location(&location)?;
+                let format = Format::parse(&format_text)?;
+                if format.conversions() != args.len() {
+                    return Err(DeetError::Input("Wrong number of arguments for specified format-string".to_string()));
+                }
+                let command = format!("dprintf {},{}", location, session::quote(&format_text))
+                    + &args.iter().map(|arg| format!(", {}", arg)).collect::<String>();
+                let args = args.iter().map(|arg| expression::parse(arg)).collect::<Result<Vec<Expr>, String>>()?;
+                if self.set_breakpoint(addr) {
+                    self.break_commands.insert(addr, command);
+                    self.dprintfs.insert(addr, (format, args));
+                    println!("Dprintf set at {}", self.describe_location(addr));
+                }
+            }
+            DebuggerCommand::Until(args) => {
+                self.target.inferior().ok_or(DeetError::NoProcess)?;
+                let addr = self.target.resolve_location(&args)?;
+                self.run_until(addr)?;
+            }
+            DebuggerCommand::Jump(args) => {
+                self.target.inferior().ok_or(DeetError::NoProcess)?;
+                let addr = self.target.resolve_location(&args)?;
+                self.jump(addr)?;
+            }
+            DebuggerCommand::Return(value) => {
+                self.target.inferior().ok_or(DeetError::NoProcess)?;
+                let value = match value {
+                    Some(v) => Some(parse_int(&v).ok_or_else(|| DeetError::Input(format!("illegal return value {}", v)))?),
+                    None => None,
+                };
+                // The caller carries on from where the call returns to
+                self.target.pop_frame(value)?;
+                self.with_continue()?;
+            }
+            DebuggerCommand::InfoFunctions(pattern) => info::print_functions(self.target.debug_data(), pattern.as_deref())?,
+            DebuggerCommand::InfoVariables(pattern) => info::print_variables(self.target.debug_data(), pattern.as_deref())?,
+            DebuggerCommand::InfoTypes(pattern) => info::print_types(self.target.debug_data(), pattern.as_deref())?,
+            DebuggerCommand::InfoScope(loc) => {
+                let addr = self.target.resolve_location(&loc)?;
+                match self.target.debug_data().get_function_at(addr) {
+                    Some(func) => info::print_scope(func, addr),
+                    None => println!("No function contains specified address."),
+                }
+            }
+            DebuggerCommand::InfoLine(loc) => {
+                let addr = match loc {
+                    Some(loc) => Some(self.target.resolve_location(&loc)?),
+                    None => self.target.inferior().and_then(|inferior| inferior.pc().ok()),
+                };
+                match addr {
+                    Some(addr) => self.info_line(addr),
+                    None => println!("No line number information available"),
+                }
+            }
+            DebuggerCommand::InfoAddress(name) => self.info_address(&name),
+            DebuggerCommand::InfoSymbol(expr) => {
+                // Function names stand for their address
+                let addr = match self.target.debug_data().get_addr_for_function(None, &expr) {
+                    Some(addr) => addr as i64,
+                    None => {
+                        let value = self.evaluate(&expr)?;
+                        value.as_int().ok_or_else(|| DeetError::Input(format!("{} is not an address", value)))?
                     }
-                    let mut addr = 0;
-                    if args.starts_with('*') {
-                        addr = parse_address(&args[1..], 16).unwrap();
+                };
+                self.info_symbol(addr as usize);
+            }
+            DebuggerCommand::InfoProc => {
+                process_info::print_process(self.target.inferior().ok_or(DeetError::NoProcess)?)?;
+            }
+            DebuggerCommand::InfoProcMappings => {
+                process_info::print_mappings(self.target.inferior().ok_or(DeetError::NoProcess)?)?;
+            }
+            DebuggerCommand::InfoSharedLibrary => {
+                process_info::print_shared_libraries(self.target.inferior().ok_or(DeetError::NoProcess)?)?;
+            }
+            DebuggerCommand::Print(expr) | DebuggerCommand::Call(expr) => {
+                match self.evaluate(&expr)? {
+                    Value::Void => {}
+                    value => {
+                        let text = value.to_string();
+                        println!("${} = {}", self.target.record_value(value), text);
                     }
-                    else {
-                        if let Some(line_number) = parse_address(&args, 10) {
-                            println!("{}", line_number);
-                            addr = self.debug_data.get_addr_for_line(None, line_number).unwrap();
-                        }
-                        else {
-                            if let Some(af) = self.debug_data.get_addr_for_function(None, &args) {
-                                addr = af;
-                            }
-                            else {
-                                println!("illegal args {} for breakpoint", args)
-                            }
+                }
+            }
+            DebuggerCommand::SetVariable(name, expr) => {
+                let value = self.evaluate(&expr)?;
+                self.target.set_convenience_variable(&name[1..], value);
+            }
+            DebuggerCommand::Display(Some(expr)) => {
+                expression::parse(&expr)?;
+                let number = self.next_display_number;
+                self.next_display_number += 1;
+                self.displays.push((number, expr.clone()));
+                if self.target.inferior().is_some() {
+                    self.show_display(number, &expr);
+                }
+            }
+            DebuggerCommand::Display(None) => self.show_displays(),
+            DebuggerCommand::Undisplay(numbers) => {
+                if numbers.is_empty() {
+                    self.displays.clear();
+                }
+                for number in numbers {
+                    match self.displays.iter().position(|(n, _)| *n == number) {
+                        Some(idx) => {
+                            self.displays.remove(idx);
                         }
+                        None => println!("No display number {}.", number),
                     }
-                    
-                    if self.inferior.is_some() {
-                        match self.inferior.as_mut().unwrap().write_byte(addr, 0xcc) {
-                            Err(_) => { println!("Invalid breakpoint address {:#x}", addr) }
-                            Ok(orig_byte) => { self.break_points.insert(addr, orig_byte); }
-                        }
+                }
+            }
+            DebuggerCommand::InfoDisplay => {
+                if self.displays.is_empty() {
+                    println!("There are no auto-display expressions now.");
+                } else {
+                    println!("Auto-display expressions now in effect:");
+                    println!("Num Expression");
+                    for (number, expr) in &self.displays {
+                        println!("{}:   {}", number, expr);
                     }
-                    else {
-                        self.break_points.insert(addr, 0);
+                }
+            }
+            DebuggerCommand::Checkpoint => {
+                let id = self.target.checkpoint()?;
+                println!("Checkpoint {} saved", id);
+            }
+            DebuggerCommand::Restart(id) => self.restart(id)?,
+            DebuggerCommand::DeleteCheckpoint(id) => {
+                let deleted = self
+                    .target
+                    .checkpointed_inferior_mut()
+                    .is_some_and(|inferior| inferior.delete_checkpoint(id));
+                if !deleted {
+                    println!("No checkpoint number {}.", id);
+                }
+            }
+            DebuggerCommand::InfoCheckpoints => {
+                let checkpoints = self
+                    .target
+                    .checkpointed_inferior()
+                    .map_or(&[][..], |inferior| inferior.checkpoints());
+                if checkpoints.is_empty() {
+                    println!("No checkpoints.");
+                }
+                for checkpoint in checkpoints {
+                    let rip = checkpoint.stop_address();
+                    println!("  {} process {} at {}", checkpoint.id, checkpoint.pid, self.describe_location(rip));
+                }
+            }
+            DebuggerCommand::Record => {
+                let inferior = self.target.inferior_mut().ok_or(DeetError::NoProcess)?;
+                if inferior.recording().is_some() {
+                    return Err(DeetError::Input("The process is already being recorded.".to_string()));
+                }
+                inferior.start_recording()?;
+            }
+            DebuggerCommand::RecordStop => {
+                match self.target.inferior_mut() {
+                    Some(inferior) if inferior.recording().is_some() => {
+                        inferior.stop_recording();
+                        println!("Process record is stopped and all execution logs are deleted.");
                     }
+                    _ => println!("No recording is currently active."),
+                }
+            }
+            DebuggerCommand::InfoRecord => {
+                match self.target.inferior().and_then(|inferior| inferior.recording()) {
+                    Some(recording) => println!("Recorded {} instructions.", recording.len()),
+                    None => println!("No recording is currently active."),
+                }
+            }
+            DebuggerCommand::ReverseStepi => {
+                match self.target.reverse_step_instruction()? {
+                    StopEvent::Stepped(addr) | StopEvent::Breakpoint(addr) => self.print_location(addr),
+                    event => self.report_stop(event),
+                }
+            }
+            DebuggerCommand::ReverseStep => {
+                let event = self.target.reverse_step_line()?;
+                self.report_stop(event);
+            }
+            DebuggerCommand::ReverseNext => {
+                let event = self.target.reverse_next_line()?;
+                self.report_stop(event);
+            }
+            DebuggerCommand::ReverseContinue => {
+                let event = self.target.reverse_continue()?;
+                self.report_stop(event);
+            }
+            DebuggerCommand::SaveSession => {
+                self.save_session_on_quit = true;
+                self.save_session()?;
+                println!("Saved session to {}", self.session_path.as_ref().unwrap().display());
+            }
+            DebuggerCommand::DeleteSession => {
+                self.save_session_on_quit = false;
+                let deleted = self.session_path.as_ref().map(|path| session::save(path, self.target.path(), &[]));
+                match deleted {
+                    Some(Ok(())) => println!("Deleted the session; it won't be saved again unless you \"save session\""),
+                    Some(Err(err)) => println!("Could not delete the session: {}", err),
+                    None => {}
+                }
+            }
+            DebuggerCommand::Help(topic) => debugger_command::print_help(topic.as_deref())?,
+            DebuggerCommand::Quit => {
+                if self.save_session_on_quit {
+                    if let Err(err) = self.save_session() {
+                        println!("{}", err);
+                    }
+                }
+                self.kill();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the commands that recreate the breakpoints, displays and settings set so far.
+    fn session_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        let mut env: Vec<_> = self.target.launch_options().env.iter().collect();
+        env.sort();
+        for (name, value) in env {
+            commands.push(match value {
+                Some(value) => format!("set env {}={}", name, value),
+                None => format!("unset env {}", name),
+            });
+        }
+        if let Some(cwd) = &self.target.launch_options().cwd {
+            commands.push(format!("set cwd {}", cwd));
+        }
+        if let Some(tty) = &self.target.launch_options().tty {
+            commands.push(format!("set inferior-tty {}", tty));
+        }
+        let mut variables: Vec<_> = self.target.convenience_variables().iter().collect();
+        variables.sort_by_key(|(name, _)| name.as_str());
+        for (name, value) in variables {
+            // Values that are only text can't be read back in
+            if let Value::Int(_) | Value::Float(_) | Value::Pointer(_) = value {
+                commands.push(format!("set ${} = {}", name, value));
+            }
+        }
+        let mut breaks: Vec<(&usize, &String)> = self.break_commands.iter().collect();
+        breaks.sort();
+        for (_, command) in breaks {
+            // Inlined copies of a function all come from the one command
+            if !commands.contains(command) {
+                commands.push(command.clone());
+            }
+        }
+        let mut catches: Vec<String> = self.target.catches().iter().map(|catch| format!("catch {}", catch)).collect();
+        catches.sort();
+        commands.extend(catches);
+        commands.extend(self.displays.iter().map(|(_, expr)| format!("display {}", expr)));
+        commands
+    }
+
+    fn save_session(&self) -> Result<(), String> {
+        let path = self.session_path.as_ref().ok_or("Could not find where to keep sessions")?;
+        session::save(path, self.target.path(), &self.session_commands())
+            .map_err(|err| format!("Could not save the session: {}", err))
+    }
+
+    /// Installs a breakpoint at addr, returning false if it could not be written into the
+    /// running inferior.
+    fn set_breakpoint(&mut self, addr: usize) -> bool {
+        match self.target.set_breakpoint(addr) {
+            Ok(()) => true,
+            Err(err) => {
+                println!("{}", err);
+                false
+            }
+        }
+    }
+
+    fn delete_breakpoint(&mut self, addr: usize) {
+        self.temp_break_points.remove(&addr);
+        self.break_commands.remove(&addr);
+        self.dprintfs.remove(&addr);
+        self.target.remove_breakpoint(addr);
+    }
+
+    /// Runs until addr is reached or the current frame returns, whichever happens first.
+    fn run_until(&mut self, addr: usize) -> Result<(), DeetError> {
+        let mut stops = vec![addr];
+        self.target.inferior().ok_or(DeetError::NoProcess)?;
+        if let Ok(ret_addr) = self.target.return_address() {
+            stops.push(ret_addr);
+        }
+        self.continue_to(&stops)
+    }
+
+    /// Resumes execution at addr, which must lie in the current function.
+    fn jump(&mut self, addr: usize) -> Result<(), DeetError> {
+        let rip = self.target.inferior().ok_or(DeetError::NoProcess)?.pc()?;
+        let curr_func = self.target.debug_data().get_function_from_addr(rip);
+        if curr_func.is_none() || curr_func != self.target.debug_data().get_function_from_addr(addr) {
+            return Err(DeetError::Input(format!("{:#x} is not in the current function", addr)));
+        }
+        self.target.inferior_mut().unwrap().jump_to(addr).map_err(|_| DeetError::Input(format!("Could not jump to {:#x}", addr)))?;
+        self.with_continue()
+    }
+
+    /// Parses and evaluates an expression in the context of the current frame of the inferior.
+    fn evaluate(&mut self, expr: &str) -> Result<Value, DeetError> {
+        let expr = expression::parse(expr)?;
+        self.target.evaluate(&expr)
+    }
+
+    /// Kills the inferior, saying so if one was running.
+    fn kill(&mut self) {
+        if let Some(pid) = self.target.kill() {
+            println!("Killing running inferior (pid {})", pid);
+        }
+    }
+
+    /// Prints the symbol containing addr, like `main + 28`.
+    fn info_symbol(&self, addr: usize) {
+        let symbol = match self.target.debug_data().get_symbol_at(addr) {
+            Some((symbol, 0)) => Some(symbol.name.clone()),
+            Some((symbol, offset)) => Some(format!("{} + {}", symbol.name, offset)),
+            None => match self.target.debug_data().get_function_at(addr) {
+                Some(func) => Some(format!("{} + {}", func.qualified_name, addr - func.address)),
+                None => self.library_symbol(addr),
+            },
+        };
+        let region = self.describe_region(addr);
+        match symbol {
+            Some(symbol) => println!("{}{}", symbol, region),
+            None => println!("No symbol matches {:#x}.", addr),
+        }
+    }
+
+    /// Formats addr relative to the function of a shared library containing it.
+    fn library_symbol(&self, addr: usize) -> Option<String> {
+        let libraries = shared_library::loaded_libraries(self.target.inferior()?.pid()).ok()?;
+        match shared_library::library_at(&libraries, addr)?.get_symbol_at(addr)? {
+            (symbol, 0) => Some(symbol.name.clone()),
+            (symbol, offset) => Some(format!("{} + {}", symbol.name, offset)),
+        }
+    }
+
+    /// Describes the module and section addr is in, as in ` in section .text of /lib/libc.so.6`,
+    /// when there's a process to look in.
+    fn describe_region(&self, addr: usize) -> String {
+        self.target
+            .inferior()
+            .and_then(|inferior| inferior.describe_region(addr))
+            .map_or_else(String::new, |region| format!(" {}", region))
+    }
+
+    /// Formats addr relative to the symbol containing it, as in `<main+28>`.
+    fn symbolize(&self, addr: usize) -> String {
+        match self.target.debug_data().get_symbol_at(addr) {
+            Some((symbol, 0)) => format!(" <{}>", symbol.name),
+            Some((symbol, offset)) => format!(" <{}+{}>", symbol.name, offset),
+            None => match self.target.debug_data().get_function_at(addr) {
+                Some(func) => format!(" <{}+{}>", func.qualified_name, addr - func.address),
+                None => String::new(),
+            },
+        }
+    }
+
+    /// Prints the range of addresses the code of the line containing addr occupies.
+    fn info_line(&self, addr: usize) {
+        match self.target.debug_data().get_line_range(addr) {
+            Some((line, end)) => println!(
+                "Line {} of \"{}\" starts at address {:#x}{} and ends at {:#x}{}.",
+                line.number,
+                line.file,
+                line.address,
+                self.symbolize(line.address),
+                end,
+                self.symbolize(end)
+            ),
+            None => println!("No line number information available for address {:#x}{}", addr, self.symbolize(addr)),
+        }
+    }
+
+    /// Prints where the named variable or function lives.
+    fn info_address(&self, name: &str) {
+        let rip = self.target.inferior().and_then(|inferior| inferior.pc().ok());
+        let func = rip.and_then(|rip| self.target.debug_data().get_function_at(rip));
+        if let (Some(func), Some(rip)) = (func, rip) {
+            if let Some(var) = func.variables_at(rip).into_iter().find(|var| var.name == name) {
+                println!("Symbol \"{}\" is {}.", name, info::describe_variable(var, func.frame_base_register));
+                return;
+            }
+        }
+        if let Some(var) = self.target.debug_data().get_global_variable(name) {
+            println!("Symbol \"{}\" is {}.", name, info::describe_variable(var, None));
+        } else if let Some(addr) = self.target.debug_data().find_functions(None, name).first().map(|func| func.address) {
+            println!("Symbol \"{}\" is a function at address {:#x}.", name, addr);
+        } else if let Some(symbol) = self.target.debug_data().get_symbol(name) {
+            println!("Symbol \"{}\" is at {:#x} in a file compiled without debugging.", name, symbol.address);
+        } else {
+            println!("No symbol \"{}\" in current context.", name);
+        }
+    }
+
+    /// Describes the source location of addr, as in `file.c:10`.
+    fn describe_location(&self, addr: usize) -> String {
+        match self.target.debug_data().get_line_from_addr(addr) {
+            None => match self.target.debug_data().get_function_from_addr(addr) {
+                Some(func) => format!("{:#x} in {} (no source information)", addr, func),
+                None => format!("{:#x}{}", addr, self.describe_region(addr)),
+            },
+            Some(line) => format!("{}:{}", line.file, line.number),
+        }
+    }
+
+    fn print_location(&mut self, rip: usize) {
+        println!("Stopped at {}", self.describe_location(rip));
+        self.show_displays();
+    }
+
+    fn show_display(&mut self, number: usize, expr: &str) {
+        match self.evaluate(expr) {
+            Ok(value) => println!("{}: {} = {}", number, expr, value),
+            Err(err) => println!("{}: {} = <{}>", number, expr, err),
+        }
+    }
+
+    /// Prints the value of each auto-display expression.
+    fn show_displays(&mut self) {
+        for (number, expr) in self.displays.clone() {
+            self.show_display(number, &expr);
+        }
+    }
+
+    /// Continues debugging from checkpoint id, discarding the current state of the inferior.
+    fn restart(&mut self, id: usize) -> Result<(), DeetError> {
+        let rip = self.target.restart(id)?;
+        println!("Switching to checkpoint {} (pid {})", id, self.target.inferior().unwrap().pid());
+        self.print_location(self.target.stop_address(rip));
+        Ok(())
+    }
+
+    /// Steps to the start of the next source line. With over_calls, functions called along the
+    /// way run to completion, as for `next`; otherwise stepping stops in the first of them that
+    /// has source information.
+    fn step(&mut self, over_calls: bool) -> Result<(), DeetError> {
+        let stepping = self.target.begin_step(over_calls)?;
+        self.advance(stepping)
+    }
+
+    /// Runs until the current function returns, or if the inferior is in code that was inlined,
+    /// until it leaves that code.
+    fn finish(&mut self) -> Result<(), DeetError> {
+        let stepping = self.target.begin_finish()?;
+        let start = self.target.stop_address(self.target.inferior().ok_or(DeetError::NoProcess)?.pc()?);
+        match self.target.debug_data().get_inlined_calls_at(start).last() {
+            Some(call) => println!("Run till exit from {} [inlined]", call.name),
+            None => {
+                let name = self.target.debug_data().get_function_from_addr(start).unwrap_or_else(|| "??".to_string());
+                println!("Run till exit from {}", name);
+            }
+        }
+        self.advance(stepping)
+    }
+
+    /// Carries out a step or finish, carrying on past the dprintfs it hits.
+    fn advance(&mut self, mut stepping: Stepping) -> Result<(), DeetError> {
+        loop {
+            match self.target.advance(&mut stepping)? {
+                StopEvent::Breakpoint(addr) if self.run_dprintf(addr) => continue,
+                event => {
+                    self.report_stop(event);
+                    return Ok(());
                 }
-                DebuggerCommand::Quit => {
-                    self.inferior.as_mut().unwrap().kill();
-                    self.inferior = None;
-                    return;
+            }
+        }
+    }
+
+    pub fn with_continue(&mut self) -> Result<(), DeetError> {
+        self.continue_to(&[])
+    }
+
+    /// Continues until one of stops or a breakpoint is reached, carrying on past the dprintfs it
+    /// hits.
+    fn continue_to(&mut self, stops: &[usize]) -> Result<(), DeetError> {
+        loop {
+            match self.target.run_to(stops)? {
+                StopEvent::Breakpoint(addr) if self.run_dprintf(addr) => continue,
+                event => {
+                    self.report_stop(event);
+                    return Ok(());
                 }
             }
         }
     }
 
-    pub fn with_continue(&mut self) {
-        match self.inferior.as_mut().unwrap().continue_run(&self.break_points).unwrap() {
-            Status::Exited(exit_code) => {
-                println!("Child exited (status {})", exit_code);
-                self.inferior = None;
+    /// Prints the dprintf at addr, if there is one, for the inferior having stopped there.
+    /// Returns whether it should continue rather than stop, which is unless a temporary
+    /// breakpoint is there as well.
+    fn run_dprintf(&mut self, addr: usize) -> bool {
+        // Taken out while its arguments are evaluated, which needs the debugger
+        let (format, args) = match self.dprintfs.remove(&addr) {
+            Some(dprintf) => dprintf,
+            None => return false,
+        };
+        let values: Result<Vec<Value>, DeetError> = args.iter().map(|arg| self.target.evaluate(arg)).collect();
+        let inferior = self.target.inferior().unwrap();
+        let text = values
+            .map_err(|err| err.to_string())
+            .and_then(|values| format.format(&values, |ptr| inferior.read_c_string(ptr as usize)));
+        match text {
+            Ok(text) => {
+                print!("{}", text);
+                std::io::stdout().flush().ok();
             }
-            Status::Signaled(signal) => {
-                println!("Child exited due to signal {}", signal);
-                self.inferior = None;
+            Err(err) => println!("dprintf at {}: {}", self.describe_location(addr), err),
+        }
+        self.dprintfs.insert(addr, (format, args));
+        !self.temp_break_points.contains(&addr)
+    }
+
+    /// Prints why the inferior stopped and where.
+    fn report_stop(&mut self, event: StopEvent) {
+        match event {
+            StopEvent::Exited(exit_code) => println!("Child exited (status {})", exit_code),
+            StopEvent::Signaled(signal) => println!("Child exited due to signal {}", signal),
+            StopEvent::Breakpoint(addr) => {
+                println!("Child stopped (signal SIGTRAP)");
+                if self.temp_break_points.contains(&addr) {
+                    self.delete_breakpoint(addr);
+                }
+                self.print_location(addr);
             }
-            Status::Stopped(signal, rip) => {
+            StopEvent::Signal(signal, rip) => {
                 println!("Child stopped (signal {})", signal);
-                let line = self.debug_data.get_line_from_addr(rip);
-                match line {
-                    None => { println!("Legacy bugs, rip is {:#x}", rip); }
-                    Some(_line) => { println!("Stopped at {}:{}", _line.file, _line.number); }
+                self.print_location(rip);
+            }
+            StopEvent::Stepped(addr) => self.print_location(addr),
+            StopEvent::Caught(event, rip) => {
+                match event {
+                    Event::Fork(child) => println!("Catchpoint (forked process {})", child),
+                    Event::Vfork(child) => println!("Catchpoint (vforked process {})", child),
+                    Event::Exec => self.report_exec(),
+                    Event::Exit(exit_code) => println!("Catchpoint (exiting with status {})", exit_code),
                 }
+                self.print_location(rip);
             }
-        }   
+            StopEvent::CaughtSignal(signal, rip) => {
+                println!("Catchpoint (signal {})", signal);
+                self.print_location(rip);
+            }
+            StopEvent::HistoryEnd(rip) => {
+                println!("No more reverse-execution history.");
+                self.print_location(rip);
+            }
+        }
     }
 
-    /// This function prompts the user to enter a command, and continues re-prompting until the user
-    /// enters a valid command. It uses DebuggerCommand::from_tokens to do the command parsing.
-    ///
-    /// You don't need to read, understand, or modify this function.
+    /// Reports the program the process replaced its own with. The target drops the breakpoints
+    /// when it's a different program, and so are the commands that set them.
+    fn report_exec(&mut self) {
+        let exe = self.target.inferior().and_then(|inferior| fs::read_link(format!("/proc/{}/exe", inferior.pid())).ok());
+        match exe {
+            Some(exe) => println!("Catchpoint (exec'd {})", exe.display()),
+            None => println!("Catchpoint (exec)"),
+        }
+        if self.target.breakpoints().is_empty() && !self.break_commands.is_empty() {
+            println!("Deleted the breakpoints, which were in the program that was replaced");
+            self.break_commands.clear();
+            self.temp_break_points.clear();
+            self.dprintfs.clear();
+        }
+    }
+
+    /// Gathers what the full-screen interface shows about the current state of the inferior.
+    fn snapshot(&mut self) -> Snapshot {
+        let line_at = |addr: usize| self.target.debug_data().get_line_from_addr(addr).map(|line| (line.file, line.number));
+        let mut snapshot = Snapshot {
+            entry: self.target.debug_data().get_addr_for_function(None, "main").and_then(line_at),
+            breakpoint_lines: self.target.breakpoints().keys().filter_map(|addr| line_at(*addr)).collect(),
+            ..Snapshot::default()
+        };
+        let inferior = match self.target.inferior() {
+            Some(inferior) => inferior,
+            None => return snapshot,
+        };
+        if let Ok(regs) = inferior.registers() {
+            let rip = self.target.stop_address(Native::pc(&regs));
+            snapshot.location = line_at(rip);
+            snapshot.registers = Some(regs);
+            if let Some(func) = self.target.debug_data().get_function_at(rip) {
+                snapshot.locals = func
+                    .variables_at(rip)
+                    .into_iter()
+                    .map(|var| match self.target.read_variable(&var.name) {
+                        Ok(value) => format!("{} = {}", var.name, value),
+                        Err(err) => format!("{} = <{}>", var.name, err),
+                    })
+                    .collect();
+            }
+        }
+        snapshot.backtrace = inferior.backtrace(self.target.debug_data()).unwrap_or_default();
+        for (_, expr) in self.displays.clone() {
+            snapshot.watches.push(match self.evaluate(&expr) {
+                Ok(value) => format!("{} = {}", expr, value),
+                Err(err) => format!("{} = <{}>", expr, err),
+            });
+        }
+        snapshot
+    }
+
+    /// Redraws the full-screen interface and waits for a key bound to a command. Returns None
+    /// when the user asks for the command line instead.
+    fn get_tui_command(&mut self) -> Option<DebuggerCommand> {
+        let snapshot = self.snapshot();
+        let running = self.target.inferior().is_some();
+        let tui = self.tui.as_mut()?;
+        loop {
+            if let Err(err) = tui.draw(&snapshot) {
+                println!("Could not draw the full-screen interface: {}", err);
+                return None;
+            }
+            let command = match tui.read_key() {
+                Key::Char(':') | Key::Enter => return None,
+                Key::Char('q') => DebuggerCommand::Quit,
+                Key::Char('r') => DebuggerCommand::Run(Vec::new()),
+                Key::Char('c') | Key::F(5) if running => DebuggerCommand::Continue,
+                Key::Char('c') | Key::F(5) => DebuggerCommand::Run(Vec::new()),
+                Key::Char('n') | Key::F(10) => DebuggerCommand::Next,
+                Key::Char('s') | Key::F(11) => DebuggerCommand::Step,
+                Key::Char('i') => DebuggerCommand::Stepi,
+                Key::Char('b') | Key::F(9) => {
+                    let (file, line) = match tui.cursor() {
+                        Some(cursor) => cursor.clone(),
+                        None => continue,
+                    };
+                    let location = format!("{}:{}", file, line);
+                    match self.target.debug_data().get_addr_for_line(Some(&file), line) {
+                        Some(addr) if self.target.breakpoints().contains_key(&addr) => DebuggerCommand::Clear(location),
+                        _ => DebuggerCommand::Break(location),
+                    }
+                }
+                Key::Up => {
+                    tui.move_cursor(-1);
+                    continue;
+                }
+                Key::Down => {
+                    tui.move_cursor(1);
+                    continue;
+                }
+                Key::PageUp => {
+                    tui.move_cursor(-10);
+                    continue;
+                }
+                Key::PageDown => {
+                    tui.move_cursor(10);
+                    continue;
+                }
+                // Resizing lays the panes out again when they are redrawn
+                _ => continue,
+            };
+            return Some(command);
+        }
+    }
+
+    /// Names of the variables in the function the program is stopped in, for completion.
+    fn local_names(&self) -> Vec<String> {
+        let rip = match self.target.inferior().and_then(|inferior| inferior.pc().ok()) {
+            Some(rip) => self.target.stop_address(rip),
+            None => return Vec::new(),
+        };
+        match self.target.debug_data().get_function_at(rip) {
+            Some(func) => func.variables_at(rip).into_iter().map(|var| var.name.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the next command to run. Commands queued from a restored session come first; then,
+    /// in the full-screen interface, a key bound to a command. Otherwise the user is prompted
+    /// with readline, with local variables offered for completion, until they enter a command
+    /// DebuggerCommand::from_tokens recognizes. An empty line repeats the last command if it is
+    /// one that repeats, such as `next`, and other lines are added to the history, which is
+    /// saved to disk each time. Ctrl+D quits.
     fn get_next_command(&mut self) -> DebuggerCommand {
+        while let Some(line) = self.queued_commands.pop_front() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match DebuggerCommand::from_tokens(&tokens) {
+                Some(cmd) => return cmd,
+                None => println!("Unrecognized command in session: {}", line),
+            }
+        }
+        if let Some(cmd) = self.get_tui_command() {
+            return cmd;
+        }
+        let locals = self.local_names();
+        if let Some(helper) = self.readline.helper_mut() {
+            helper.set_locals(locals);
+        }
         loop {
             // Print prompt and get next line of user input
             match self.readline.readline("(deet) ") {
@@ -163,10 +925,18 @@ impl Debugger {
                     return DebuggerCommand::Quit;
                 }
                 Err(err) => {
-                    panic!("Unexpected I/O error: {:?}", err);
+                    println!("Could not read the command: {}", err);
+                    return DebuggerCommand::Quit;
                 }
                 Ok(line) => {
-                    if line.trim().len() == 0 {
+                    if line.trim().is_empty() {
+                        // An empty line repeats commands such as `next`, as in gdb
+                        if let Some(cmd) = self.repeat_line.as_ref().and_then(|line| {
+                            let tokens: Vec<&str> = line.split_whitespace().collect();
+                            DebuggerCommand::from_tokens(&tokens)
+                        }) {
+                            return cmd;
+                        }
                         continue;
                     }
                     self.readline.add_history_entry(line.as_str());
@@ -177,6 +947,8 @@ impl Debugger {
                         );
                     }
                     let tokens: Vec<&str> = line.split_whitespace().collect();
+                    let repeats = debugger_command::find_command(&tokens).is_some_and(|(spec, _)| spec.repeats);
+                    self.repeat_line = if repeats { Some(line.clone()) } else { None };
                     if let Some(cmd) = DebuggerCommand::from_tokens(&tokens) {
                         return cmd;
                     } else {
@@ -1,26 +1,451 @@
+use deet::target::Catch;
+
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
+    SetEnv(String, String),
+    UnsetEnv(String),
+    SetCwd(Option<String>),
+    SetInferiorTty(Option<String>),
     Continue,
+    Step,
+    Next,
+    Stepi,
+    Finish,
+    Clear(String),
     Backtrace,
     Break(String),
+    TempBreak(String),
+    Dprintf(String, String, Vec<String>),
+    Catch(Catch),
+    DeleteCatch(Catch),
+    Until(String),
+    Jump(String),
+    Return(Option<String>),
+    Print(String),
+    Call(String),
+    SetVariable(String, String),
+    Display(Option<String>),
+    Undisplay(Vec<usize>),
+    InfoDisplay,
+    InfoFunctions(Option<String>),
+    InfoVariables(Option<String>),
+    InfoTypes(Option<String>),
+    InfoScope(String),
+    InfoLine(Option<String>),
+    InfoAddress(String),
+    InfoSymbol(String),
+    InfoProc,
+    InfoProcMappings,
+    InfoSharedLibrary,
+    Checkpoint,
+    Restart(usize),
+    DeleteCheckpoint(usize),
+    InfoCheckpoints,
+    Record,
+    RecordStop,
+    InfoRecord,
+    ReverseStepi,
+    ReverseStep,
+    ReverseNext,
+    ReverseContinue,
+    SaveSession,
+    DeleteSession,
+    Help(Option<String>),
+}
+
+/// What completion offers for the arguments of a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Args {
+    None,
+    /// Free text, such as numbers or patterns, which nothing is offered for
+    Text,
+    Location,
+    Expression,
+    Path,
+    Command,
+}
+
+/// A command, as `help` describes it and completion offers it. `from_tokens` finds commands by
+/// their names here, so this is the one list of what deet accepts.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub usage: &'static str,
+    pub args: Args,
+    /// Whether Enter on an empty line runs it again, as for stepping
+    pub repeats: bool,
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec { name: "run", aliases: &["r"], usage: "[ARGS...] [< IN] [> OUT]", args: Args::Path, repeats: false,
+        help: "Start the program with ARGS, redirecting its input and output as a shell would" },
+    CommandSpec { name: "continue", aliases: &["c", "cont"], usage: "", args: Args::None, repeats: true,
+        help: "Continue running the program until it stops" },
+    CommandSpec { name: "step", aliases: &["s"], usage: "", args: Args::None, repeats: true,
+        help: "Step to the next source line, into the functions it calls" },
+    CommandSpec { name: "next", aliases: &["n"], usage: "", args: Args::None, repeats: true,
+        help: "Step to the next source line, over the functions it calls" },
+    CommandSpec { name: "stepi", aliases: &["si"], usage: "", args: Args::None, repeats: true,
+        help: "Execute a single instruction" },
+    CommandSpec { name: "finish", aliases: &["fin"], usage: "", args: Args::None, repeats: false,
+        help: "Run until the current function returns" },
+    CommandSpec { name: "until", aliases: &["u", "advance"], usage: "LOCATION", args: Args::Location, repeats: false,
+        help: "Run until LOCATION is reached or the current function returns" },
+    CommandSpec { name: "jump", aliases: &["j"], usage: "LOCATION", args: Args::Location, repeats: false,
+        help: "Resume execution at LOCATION, in the current function" },
+    CommandSpec { name: "return", aliases: &["ret"], usage: "[VALUE]", args: Args::Text, repeats: false,
+        help: "Return from the current function at once, with VALUE" },
+    CommandSpec { name: "break", aliases: &["b"], usage: "LOCATION", args: Args::Location, repeats: false,
+        help: "Set a breakpoint at a FUNCTION, LINE, FILE:LINE or *ADDRESS" },
+    CommandSpec { name: "tbreak", aliases: &["tb"], usage: "LOCATION", args: Args::Location, repeats: false,
+        help: "Set a breakpoint that is deleted once it's hit" },
+    CommandSpec { name: "dprintf", aliases: &[], usage: "LOCATION,\"FORMAT\",ARG,...", args: Args::Location,
+        repeats: false, help: "Set a breakpoint that prints FORMAT with the ARGs and continues" },
+    CommandSpec { name: "catch", aliases: &[], usage: "fork|vfork|exec|exit|signal SIGNAL", args: Args::Text,
+        repeats: false, help: "Stop when the program forks, runs another program, is about to exit or gets SIGNAL" },
+    CommandSpec { name: "delete catch", aliases: &["d catch"], usage: "fork|vfork|exec|exit|signal SIGNAL",
+        args: Args::Text, repeats: false, help: "Delete a catchpoint" },
+    CommandSpec { name: "clear", aliases: &[], usage: "LOCATION", args: Args::Location, repeats: false,
+        help: "Delete the breakpoint at LOCATION" },
+    CommandSpec { name: "backtrace", aliases: &["bt", "back"], usage: "", args: Args::None, repeats: false,
+        help: "Print the call stack" },
+    CommandSpec { name: "print", aliases: &["p"], usage: "EXPRESSION", args: Args::Expression, repeats: false,
+        help: "Print the value of EXPRESSION" },
+    CommandSpec { name: "call", aliases: &[], usage: "FUNCTION(ARGS...)", args: Args::Expression, repeats: false,
+        help: "Call a function of the program and print what it returns" },
+    CommandSpec { name: "display", aliases: &[], usage: "[EXPRESSION]", args: Args::Expression, repeats: false,
+        help: "Print EXPRESSION each time the program stops, or all such expressions now" },
+    CommandSpec { name: "undisplay", aliases: &[], usage: "[NUMBER...]", args: Args::Text, repeats: false,
+        help: "Stop displaying the expressions with the given numbers, or all of them" },
+    CommandSpec { name: "set", aliases: &[], usage: "$NAME = EXPRESSION", args: Args::Expression, repeats: false,
+        help: "Set a convenience variable" },
+    CommandSpec { name: "set env", aliases: &["set environment"], usage: "NAME=VALUE", args: Args::Text,
+        repeats: false, help: "Set an environment variable of the program for the next run" },
+    CommandSpec { name: "unset env", aliases: &["unset environment"], usage: "NAME", args: Args::Text,
+        repeats: false, help: "Remove an environment variable of the program for the next run" },
+    CommandSpec { name: "set cwd", aliases: &[], usage: "[DIRECTORY]", args: Args::Path, repeats: false,
+        help: "Set the directory the program runs in, or go back to deet's" },
+    CommandSpec { name: "set inferior-tty", aliases: &[], usage: "[TTY]", args: Args::Path, repeats: false,
+        help: "Give the program terminal TTY, or go back to sharing deet's" },
+    CommandSpec { name: "info functions", aliases: &["i functions"], usage: "[REGEX]", args: Args::Text,
+        repeats: false, help: "List the functions, or those matching REGEX" },
+    CommandSpec { name: "info variables", aliases: &["i variables"], usage: "[REGEX]", args: Args::Text,
+        repeats: false, help: "List the global variables, or those matching REGEX" },
+    CommandSpec { name: "info types", aliases: &["i types"], usage: "[REGEX]", args: Args::Text, repeats: false,
+        help: "List the types, or those matching REGEX" },
+    CommandSpec { name: "info scope", aliases: &["i scope"], usage: "LOCATION", args: Args::Location,
+        repeats: false, help: "List the variables of the function at LOCATION" },
+    CommandSpec { name: "info line", aliases: &["i line"], usage: "[LOCATION]", args: Args::Location,
+        repeats: false, help: "Show where the code of a source line starts and ends" },
+    CommandSpec { name: "info address", aliases: &["i address"], usage: "NAME", args: Args::Expression,
+        repeats: false, help: "Show where a variable or function is" },
+    CommandSpec { name: "info symbol", aliases: &["i symbol"], usage: "ADDRESS", args: Args::Expression,
+        repeats: false, help: "Show the symbol at ADDRESS" },
+    CommandSpec { name: "info proc", aliases: &["i proc"], usage: "[mappings]", args: Args::Text, repeats: false,
+        help: "Show the process's id, command line, directories and status, or with mappings its memory map" },
+    CommandSpec { name: "info sharedlibrary", aliases: &["i sharedlibrary", "info shared", "info dll"], usage: "",
+        args: Args::None, repeats: false, help: "List the shared libraries the program has loaded" },
+    CommandSpec { name: "info display", aliases: &["i display"], usage: "", args: Args::None, repeats: false,
+        help: "List the expressions being displayed" },
+    CommandSpec { name: "info checkpoints", aliases: &["i checkpoints"], usage: "", args: Args::None,
+        repeats: false, help: "List the checkpoints" },
+    CommandSpec { name: "info record", aliases: &["i record", "info rec", "i rec"], usage: "", args: Args::None,
+        repeats: false, help: "Show how many instructions have been recorded" },
+    CommandSpec { name: "checkpoint", aliases: &[], usage: "", args: Args::None, repeats: false,
+        help: "Save a copy of the process to go back to with restart" },
+    CommandSpec { name: "restart", aliases: &[], usage: "NUMBER", args: Args::Text, repeats: false,
+        help: "Go back to a checkpoint" },
+    CommandSpec { name: "delete checkpoint", aliases: &["d checkpoint"], usage: "NUMBER", args: Args::Text,
+        repeats: false, help: "Delete a checkpoint" },
+    CommandSpec { name: "record", aliases: &["rec"], usage: "[full]", args: Args::None, repeats: false,
+        help: "Record the instructions executed from here on, so that they can be undone" },
+    CommandSpec { name: "record stop", aliases: &["rec stop"], usage: "", args: Args::None, repeats: false,
+        help: "Stop recording and discard what was recorded" },
+    CommandSpec { name: "reverse-stepi", aliases: &["rsi"], usage: "", args: Args::None, repeats: true,
+        help: "Undo the last instruction executed" },
+    CommandSpec { name: "reverse-step", aliases: &["rs"], usage: "", args: Args::None, repeats: true,
+        help: "Go back to the start of the previous source line, into the functions it called" },
+    CommandSpec { name: "reverse-next", aliases: &["rn"], usage: "", args: Args::None, repeats: true,
+        help: "Go back to the start of the previous source line, over the functions it called" },
+    CommandSpec { name: "reverse-continue", aliases: &["rc"], usage: "", args: Args::None, repeats: false,
+        help: "Run backwards to a breakpoint or the start of the recording" },
+    CommandSpec { name: "save session", aliases: &[], usage: "", args: Args::None, repeats: false,
+        help: "Save the breakpoints, displays and settings, to restore when deet next debugs the program" },
+    CommandSpec { name: "delete session", aliases: &["d session"], usage: "", args: Args::None, repeats: false,
+        help: "Delete the saved session and stop saving it on quitting" },
+    CommandSpec { name: "help", aliases: &["h"], usage: "[COMMAND]", args: Args::Command, repeats: false,
+        help: "Describe COMMAND, or list the commands" },
+    CommandSpec { name: "quit", aliases: &["q"], usage: "", args: Args::None, repeats: false,
+        help: "Kill the program and exit" },
+];
+
+impl CommandSpec {
+    fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.contains(&name)
+    }
+}
+
+/// Finds the command the tokens start with. Returns it and the number of tokens its name takes,
+/// which is two for commands such as `info line`.
+pub fn find_command(tokens: &[&str]) -> Option<(&'static CommandSpec, usize)> {
+    if tokens.len() >= 2 {
+        let name = format!("{} {}", tokens[0], tokens[1]);
+        if let Some(spec) = COMMANDS.iter().find(|spec| spec.is_named(&name)) {
+            return Some((spec, 2));
+        }
+    }
+    let name = *tokens.first()?;
+    COMMANDS.iter().find(|spec| spec.is_named(name)).map(|spec| (spec, 1))
+}
+
+/// Lists the commands, or describes the one topic names. A topic that starts the names of
+/// several commands, such as `info`, lists those.
+pub fn print_help(topic: Option<&str>) -> Result<(), String> {
+    let specs: Vec<&CommandSpec> = match topic {
+        None => COMMANDS.iter().collect(),
+        Some(topic) => {
+            let tokens: Vec<&str> = topic.split_whitespace().collect();
+            if let Some((spec, _)) = find_command(&tokens).filter(|(_, words)| *words == tokens.len()) {
+                let usage = format!("{} {}", spec.name, spec.usage);
+                println!("{}", usage.trim_end());
+                println!("{}.", spec.help);
+                if !spec.aliases.is_empty() {
+                    println!("Aliases: {}", spec.aliases.join(", "));
+                }
+                return Ok(());
+            }
+            let prefix = format!("{} ", tokens.join(" "));
+            COMMANDS.iter().filter(|spec| spec.name.starts_with(&prefix)).collect()
+        }
+    };
+    if specs.is_empty() {
+        return Err(format!("Undefined command: \"{}\". Try \"help\".", topic.unwrap_or("")));
+    }
+    if topic.is_none() {
+        println!("List of commands; \"help COMMAND\" describes one:");
+    }
+    for spec in specs {
+        let usage = format!("{} {}", spec.name, spec.usage);
+        println!("  {:<36}{}", usage.trim_end(), spec.help);
+    }
+    Ok(())
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
-        match tokens[0] {
-            "q" | "quit" => Some(DebuggerCommand::Quit),
-            "r" | "run" => {
-                let args = tokens[1..].to_vec();
-                Some(DebuggerCommand::Run(
-                    args.iter().map(|s| s.to_string()).collect(),
-                ))
+    pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
+        let (command, words) = find_command(tokens)?;
+        let args = &tokens[words..];
+        let rest = args.join(" ");
+        let first = || args.first().map(|s| s.to_string());
+        match command.name {
+            "quit" => Some(DebuggerCommand::Quit),
+            "run" => Some(DebuggerCommand::Run(args.iter().map(|s| s.to_string()).collect())),
+            // Accepts both `set env NAME=VALUE` and `set env NAME VALUE`
+            "set env" => {
+                let (name, value) = match rest.find(['=', ' ']) {
+                    Some(idx) => (&rest[..idx], rest[idx + 1..].trim_start_matches(['=', ' '])),
+                    None => (rest.as_str(), ""),
+                };
+                if name.is_empty() {
+                    return None;
+                }
+                Some(DebuggerCommand::SetEnv(name.to_string(), value.to_string()))
+            }
+            "set cwd" => Some(DebuggerCommand::SetCwd(first())),
+            "set inferior-tty" => Some(DebuggerCommand::SetInferiorTty(first())),
+            // Convenience variables, as in `set $x = 2 * sum` or `set $x=1`
+            "set" if rest.starts_with('$') => {
+                let idx = rest.find('=')?;
+                let name = rest[..idx].trim();
+                if name.len() < 2 || !name[1..].chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return None;
+                }
+                Some(DebuggerCommand::SetVariable(name.to_string(), rest[idx + 1..].trim().to_string()))
+            }
+            "unset env" => Some(DebuggerCommand::UnsetEnv(first()?)),
+            "step" => Some(DebuggerCommand::Step),
+            "next" => Some(DebuggerCommand::Next),
+            "stepi" => Some(DebuggerCommand::Stepi),
+            "finish" => Some(DebuggerCommand::Finish),
+            "clear" if !args.is_empty() => Some(DebuggerCommand::Clear(rest)),
+            "continue" => Some(DebuggerCommand::Continue),
+            "backtrace" => Some(DebuggerCommand::Backtrace),
+            // Locations may be C++ signatures containing spaces, such as `f(int, char)`
+            "break" if !args.is_empty() => Some(DebuggerCommand::Break(rest)),
+            "tbreak" if !args.is_empty() => Some(DebuggerCommand::TempBreak(rest)),
+            "dprintf" if !args.is_empty() => parse_dprintf(&rest),
+            "catch" => Some(DebuggerCommand::Catch(parse_catch(args)?)),
+            "delete catch" => Some(DebuggerCommand::DeleteCatch(parse_catch(args)?)),
+            "until" => Some(DebuggerCommand::Until(first()?)),
+            "jump" => Some(DebuggerCommand::Jump(first()?)),
+            "return" => Some(DebuggerCommand::Return(first())),
+            "print" if !args.is_empty() => Some(DebuggerCommand::Print(rest)),
+            "call" if !args.is_empty() => Some(DebuggerCommand::Call(rest)),
+            "display" if !args.is_empty() => Some(DebuggerCommand::Display(Some(rest))),
+            "display" => Some(DebuggerCommand::Display(None)),
+            "undisplay" => {
+                let numbers: Option<Vec<usize>> = args.iter().map(|n| n.parse().ok()).collect();
+                Some(DebuggerCommand::Undisplay(numbers?))
             }
-            "c" | "cont" | "continue" => { Some(DebuggerCommand::Continue) }
-            "bt" | "back" | "backtrace" => { Some(DebuggerCommand::Backtrace) }
-            "b" | "break" => { Some(DebuggerCommand::Break(tokens[1].to_string())) }
-            // Default case:
+            "info functions" => Some(DebuggerCommand::InfoFunctions(first())),
+            "info variables" => Some(DebuggerCommand::InfoVariables(first())),
+            "info types" => Some(DebuggerCommand::InfoTypes(first())),
+            "info scope" if !args.is_empty() => Some(DebuggerCommand::InfoScope(rest)),
+            "info line" if !args.is_empty() => Some(DebuggerCommand::InfoLine(Some(rest))),
+            "info line" => Some(DebuggerCommand::InfoLine(None)),
+            "info address" => Some(DebuggerCommand::InfoAddress(first()?)),
+            "info checkpoints" => Some(DebuggerCommand::InfoCheckpoints),
+            "info display" => Some(DebuggerCommand::InfoDisplay),
+            "info record" => Some(DebuggerCommand::InfoRecord),
+            "info symbol" if !args.is_empty() => Some(DebuggerCommand::InfoSymbol(rest)),
+            "info proc" => match args.first() {
+                None => Some(DebuggerCommand::InfoProc),
+                Some(&"mappings") => Some(DebuggerCommand::InfoProcMappings),
+                _ => None,
+            },
+            "info sharedlibrary" => Some(DebuggerCommand::InfoSharedLibrary),
+            "checkpoint" => Some(DebuggerCommand::Checkpoint),
+            "record" => match args.first() {
+                None | Some(&"full") => Some(DebuggerCommand::Record),
+                _ => None,
+            },
+            "record stop" => Some(DebuggerCommand::RecordStop),
+            "reverse-stepi" => Some(DebuggerCommand::ReverseStepi),
+            "reverse-step" => Some(DebuggerCommand::ReverseStep),
+            "reverse-next" => Some(DebuggerCommand::ReverseNext),
+            "reverse-continue" => Some(DebuggerCommand::ReverseContinue),
+            "save session" => Some(DebuggerCommand::SaveSession),
+            "restart" => Some(DebuggerCommand::Restart(first()?.parse().ok()?)),
+            "delete checkpoint" => Some(DebuggerCommand::DeleteCheckpoint(first()?.parse().ok()?)),
+            "delete session" => Some(DebuggerCommand::DeleteSession),
+            "help" if !args.is_empty() => Some(DebuggerCommand::Help(Some(rest))),
+            "help" => Some(DebuggerCommand::Help(None)),
             _ => None,
         }
     }
 }
+
+/// Parses what a catchpoint catches, as in `fork` or `signal SIGINT`. Signals may be given
+/// without their `SIG`.
+fn parse_catch(args: &[&str]) -> Option<Catch> {
+    match args {
+        ["fork"] => Some(Catch::Fork),
+        ["vfork"] => Some(Catch::Vfork),
+        ["exec"] => Some(Catch::Exec),
+        ["exit"] => Some(Catch::Exit),
+        ["signal", name] => {
+            let name = name.to_uppercase();
+            let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+            name.parse().ok().map(Catch::Signal)
+        }
+        _ => None,
+    }
+}
+
+/// Parses the `LOCATION,"format",ARG,...` of a dprintf into its location, format and arguments.
+/// The escapes of C string literals are allowed in the format.
+fn parse_dprintf(spec: &str) -> Option<DebuggerCommand> {
+    let (location, rest) = spec.split_at(spec.find(',')?);
+    let quoted = rest[1..].trim_start().strip_prefix('"')?;
+    let mut chars = quoted.char_indices();
+    let mut format = String::new();
+    let end = loop {
+        match chars.next()? {
+            (idx, '"') => break idx,
+            (_, '\\') => format.push(match chars.next()?.1 {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'a' => '\x07',
+                'e' => '\x1b',
+                '0' => '\0',
+                c => c,
+            }),
+            (_, c) => format.push(c),
+        }
+    };
+    let rest = quoted[end + 1..].trim();
+    // Arguments are separated by the commas that aren't inside parentheses, as in `f(a, b)`
+    let mut args = Vec::new();
+    if !rest.is_empty() {
+        let mut depth = 0;
+        let mut arg = String::new();
+        for c in rest.strip_prefix(',')?.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    args.push(std::mem::take(&mut arg).trim().to_string());
+                    continue;
+                }
+                _ => {}
+            }
+            arg.push(c);
+        }
+        args.push(arg.trim().to_string());
+    }
+    if location.trim().is_empty() || args.iter().any(|arg| arg.is_empty()) {
+        return None;
+    }
+    Some(DebuggerCommand::Dprintf(location.trim().to_string(), format, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::signal::Signal;
+
+    fn dprintf(spec: &str) -> Option<(String, String, Vec<String>)> {
+        match parse_dprintf(spec)? {
+            DebuggerCommand::Dprintf(location, format, args) => Some((location, format, args)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_dprintf_location_format_and_arguments() {
+        assert_eq!(
+            dprintf("func2, \"a=%d b=%d\\n\", a, f(a, 2)"),
+            Some(("func2".to_string(), "a=%d b=%d\n".to_string(), vec!["a".to_string(), "f(a, 2)".to_string()]))
+        );
+        assert_eq!(dprintf("main.c:12,\"here\""), Some(("main.c:12".to_string(), "here".to_string(), vec![])));
+    }
+
+    #[test]
+    fn unescapes_dprintf_format() {
+        let (_, format, args) = dprintf("*0x401000,\"\\\"%s\\\"\\t, \\\\\", name").unwrap();
+        assert_eq!(format, "\"%s\"\t, \\");
+        assert_eq!(args, vec!["name".to_string()]);
+    }
+
+    #[test]
+    fn parses_catchpoints() {
+        assert_eq!(parse_catch(&["fork"]), Some(Catch::Fork));
+        assert_eq!(parse_catch(&["vfork"]), Some(Catch::Vfork));
+        assert_eq!(parse_catch(&["exec"]), Some(Catch::Exec));
+        assert_eq!(parse_catch(&["exit"]), Some(Catch::Exit));
+        assert_eq!(parse_catch(&["signal", "SIGINT"]), Some(Catch::Signal(Signal::SIGINT)));
+        assert_eq!(parse_catch(&["signal", "segv"]), Some(Catch::Signal(Signal::SIGSEGV)));
+    }
+
+    #[test]
+    fn rejects_unknown_catchpoints() {
+        assert_eq!(parse_catch(&[]), None);
+        assert_eq!(parse_catch(&["throw"]), None);
+        assert_eq!(parse_catch(&["signal"]), None);
+        assert_eq!(parse_catch(&["signal", "SIGNOPE"]), None);
+        assert_eq!(parse_catch(&["fork", "now"]), None);
+    }
+
+    #[test]
+    fn rejects_malformed_dprintf() {
+        assert!(dprintf("func2").is_none());
+        assert!(dprintf(",\"x\"").is_none());
+        assert!(dprintf("func2, a").is_none());
+        assert!(dprintf("func2, \"unterminated").is_none());
+        assert!(dprintf("func2, \"%d\" a").is_none());
+        assert!(dprintf("func2, \"%d %d\", a,").is_none());
+    }
+}
@@ -1,7 +1,15 @@
+use crate::debug_file;
+use crate::dwarf_index::{self, UnitIndex};
 use crate::gimli_wrapper;
+use crate::unwind::{CallFrames, FrameRule};
 use addr2line::Context;
+use cpp_demangle::{DemangleOptions, Symbol};
 use object::Object;
+use once_cell::unsync::OnceCell;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::path::Path;
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -11,13 +19,34 @@ pub enum Error {
 }
 
 pub struct DwarfData {
-    files: Vec<File>,
-    addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    sections: gimli_wrapper::DwarfSections,
+    // Compilation units in .debug_info order, parsed the first time something in them is needed
+    units: Vec<Unit>,
+    // Function symbols from the ELF symbol table, sorted by address, for code without DWARF
+    symbols: Vec<ElfSymbol>,
+    call_frames: CallFrames,
+    // Address of the program's first instruction, from the ELF header
+    entry_point: usize,
+    // Built the first time a location or frame is looked up through it
+    addr2line: OnceCell<Option<Context<gimli_wrapper::DwarfReader>>>,
+    // Problems loading parts of the debugging information, reported by `take_warnings`
+    warnings: RefCell<Vec<String>>,
+}
+
+struct Unit {
+    index: UnitIndex,
+    loaded: OnceCell<LoadedUnit>,
+}
+
+struct LoadedUnit {
+    file: File,
+    types: HashMap<usize, TypeInfo>,
 }
 
 impl fmt::Debug for DwarfData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DwarfData {{files: {:?}}}", self.files)
+        let names: Vec<&str> = self.units.iter().map(|unit| unit.index.name.as_str()).collect();
+        write!(f, "DwarfData {{units: {:?}}}", names)
     }
 }
 
@@ -31,138 +60,560 @@ impl DwarfData {
     pub fn from_file(path: &str) -> Result<DwarfData, Error> {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
-        let object = object::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+        let object = object::File::parse(&mmap).map_err(|e| gimli_wrapper::Error::Object(e.to_string()))?;
+
+        let mut symbols = load_symbols(&object);
+        let call_frames = CallFrames::load(&object);
+        let entry_point = object.entry() as usize;
+        let build_id = debug_file::build_id(&object);
+
+        // Stripped executables keep their debugging information in a separate file
+        let debug_path = if debug_file::has_debug_info(&object) {
+            None
+        } else {
+            debug_file::find_debug_file(Path::new(path), &object)
+        };
+        let debug_mmap = match debug_path {
+            Some(ref debug_path) => {
+                let file = fs::File::open(debug_path).or(Err(Error::ErrorOpeningFile))?;
+                Some(unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? })
+            }
+            None => None,
+        };
+        let object = match debug_mmap {
+            Some(ref debug_mmap) => object::File::parse(debug_mmap).map_err(|e| gimli_wrapper::Error::Object(e.to_string()))?,
+            None => object,
+        };
+
+        if symbols.is_empty() {
+            symbols = load_symbols(&object);
+        }
+
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        let sections = gimli_wrapper::DwarfSections::load(&object, endian, Path::new(path))?;
+        // The index cached on an earlier run is complete, so it beats the accelerator tables
+        let source_path = debug_path.as_deref().unwrap_or_else(|| Path::new(path));
+        let cached = build_id.as_ref().and_then(|build_id| dwarf_index::load_cached(build_id, source_path));
+        let index = match cached {
+            Some(index) => index,
+            None => match sections.index_from_accelerator_tables()? {
+                Some(index) => index,
+                None => {
+                    let index = sections.index_units()?;
+                    if let Some(ref build_id) = build_id {
+                        dwarf_index::save(build_id, source_path, &index);
+                    }
+                    index
+                }
+            },
+        };
         Ok(DwarfData {
-            files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            sections,
+            units: index
+                .into_iter()
+                .map(|index| Unit {
+                    index,
+                    loaded: OnceCell::new(),
+                })
+                .collect(),
+            symbols,
+            call_frames,
+            entry_point,
+            addr2line: OnceCell::new(),
+            warnings: RefCell::new(Vec::new()),
         })
     }
 
-    #[allow(dead_code)]
-    fn get_target_file(&self, file: &str) -> Option<&File> {
-        self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
-        })
+    /// Returns how to find the caller's frame from the instruction at addr, from the call frame
+    /// information.
+    pub fn frame_rule(&self, addr: usize) -> Option<FrameRule> {
+        self.call_frames.frame_rule(addr)
+    }
+
+    /// Returns the address of the program's first instruction.
+    pub fn entry_point(&self) -> usize {
+        self.entry_point
+    }
+
+    /// Returns the problems met loading the debugging information since the last call, such as
+    /// units that couldn't be parsed, whose functions and variables are left out of lookups.
+    pub fn take_warnings(&self) -> Vec<String> {
+        let mut warnings = self.sections.take_warnings();
+        warnings.append(&mut self.warnings.take());
+        warnings
+    }
+
+    /// Keeps warning for `take_warnings`, unless it's already waiting there, since units that
+    /// failed to load are tried again by every lookup.
+    fn warn(&self, warning: String) {
+        let mut warnings = self.warnings.borrow_mut();
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+
+    /// Returns addr2line's context, building it if this is the first time it's needed.
+    fn addr2line(&self) -> Option<&Context<gimli_wrapper::DwarfReader>> {
+        self.addr2line
+            .get_or_init(|| match self.sections.addr2line_context() {
+                Ok(context) => Some(context),
+                Err(err) => {
+                    self.warn(format!("Could not load line tables: {:?}", err));
+                    None
+                }
+            })
+            .as_ref()
+    }
+
+    /// Returns the contents of unit, parsing it if this is the first time it's needed.
+    fn load<'a>(&'a self, unit: &'a Unit) -> Option<&'a File> {
+        let loaded = unit.loaded.get_or_try_init(|| {
+            self.sections.load_unit(&unit.index).map(|(mut file, types)| {
+                // Sorted once here so that lookups by address can binary search. The sort is
+                // stable, keeping rows that share an address in line program order.
+                file.lines.sort_by_key(|line| line.address);
+                LoadedUnit { file, types }
+            })
+        });
+        match loaded {
+            Ok(loaded) => Some(&loaded.file),
+            Err(err) => {
+                self.warn(format!("Could not load debugging symbols for {}: {:?}", unit.index.name, err));
+                None
+            }
+        }
+    }
+
+    /// Returns the contents of the units for which pred holds on their index entries.
+    fn load_where(&self, pred: impl Fn(&UnitIndex) -> bool) -> Vec<&File> {
+        self.units
+            .iter()
+            .filter(|unit| pred(&unit.index))
+            .filter_map(|unit| self.load(unit))
+            .collect()
+    }
+
+    fn get_unit_at(&self, addr: usize) -> Option<&File> {
+        self.load_where(|index| index.contains_addr(addr)).into_iter().next()
+    }
+
+    /// Returns every compilation unit along with the types it defines, loading all of them.
+    pub fn units(&self) -> Vec<(&File, Vec<&TypeInfo>)> {
+        self.units
+            .iter()
+            .filter_map(|unit| {
+                let file = self.load(unit)?;
+                Some((file, unit.loaded.get()?.types.values().collect()))
+            })
+            .collect()
+    }
+
+    /// Returns the names of the functions with debugging information, both plain and qualified,
+    /// from the index, loading only the units it doesn't list the functions of.
+    pub fn function_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for unit in &self.units {
+            if unit.index.has_names {
+                for (name, qualified_name, _) in &unit.index.functions {
+                    names.extend(vec![name.as_str(), qualified_name.as_str()]);
+                }
+            } else if let Some(file) = self.load(unit) {
+                for func in file.functions.iter().filter(|func| func.address != 0) {
+                    names.extend(vec![func.name.as_str(), func.qualified_name.as_str()]);
+                }
+            }
+        }
+        names
+    }
+
+    /// Returns the names of the global variables, from the index where it lists them.
+    pub fn global_variable_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for unit in &self.units {
+            if unit.index.has_names {
+                names.extend(unit.index.variables.iter().map(String::as_str));
+            } else if let Some(file) = self.load(unit) {
+                names.extend(file.global_variables.iter().map(|var| var.name.as_str()));
+            }
+        }
+        names
+    }
+
+    /// Returns the source files the line tables refer to, from the index where it lists them.
+    pub fn source_files(&self) -> Vec<&str> {
+        let mut files = Vec::new();
+        for unit in &self.units {
+            if unit.index.complete {
+                files.extend(unit.index.files.iter().map(String::as_str));
+            } else if let Some(file) = self.load(unit) {
+                let mut names: Vec<&str> = file.lines.iter().map(|line| line.file.as_str()).collect();
+                names.sort_unstable();
+                names.dedup();
+                files.extend(names);
+            }
+        }
+        files
+    }
+
+    /// Returns the function symbols of the ELF symbol table, sorted by address.
+    pub fn symbols(&self) -> &[ElfSymbol] {
+        &self.symbols
+    }
+
+    /// Returns the line table row whose code contains addr, along with the address the code of
+    /// the next line starts at.
+    pub fn get_line_range(&self, addr: usize) -> Option<(&Line, usize)> {
+        let lines = &self.get_unit_at(addr)?.lines;
+        let start = lines.partition_point(|line| line.address <= addr).checked_sub(1)?;
+        let line = &lines[start];
+        let end = lines[start..]
+            .iter()
+            .find(|other| other.address > line.address && (other.number != line.number || other.file != line.file))
+            .map_or(addr + 1, |other| other.address);
+        Some((line, end))
     }
 
-    #[allow(dead_code)]
     pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+        let filename = match file {
+            Some(filename) => filename.to_string(),
+            None => self.get_default_source_file()?,
         };
-        Some(
-            target_file
-                .lines
-                .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+        let suffix = format!("/{}", filename);
+        let matches = |path: &String| *path == filename || (!filename.contains("/") && path.ends_with(&suffix));
+        self.load_where(|index| !index.complete || index.files.iter().any(matches))
+            .into_iter()
+            .flat_map(|file| file.lines.iter())
+            .filter(|line| matches(&line.file))
+            .filter(|line| line.number >= line_number)
+            .min_by_key(|line| (line.number, line.address))
+            .map(|line| line.address)
+    }
+
+    /// Returns the source file line numbers refer to when no file is given: the one containing
+    /// main, or else the first compilation unit.
+    fn get_default_source_file(&self) -> Option<String> {
+        self.get_addr_for_function(None, "main")
+            .and_then(|addr| self.get_line_from_addr(addr))
+            .map(|line| line.file)
+            .or_else(|| Some(self.units.first()?.index.name.clone()))
     }
 
-    #[allow(dead_code)]
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
-        match file {
-            Some(filename) => Some(
-                self.get_target_file(filename)?
-                    .functions
-                    .iter()
-                    .find(|func| func.name == func_name)?
-                    .address,
-            ),
-            None => {
-                for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
-                        return Some(func.address);
-                    }
+        match self.find_functions(file, func_name).first() {
+            Some(func) => Some(func.address),
+            None if file.is_none() => self.get_symbol(func_name).map(|symbol| symbol.address),
+            None => None,
+        }
+    }
+
+    /// Looks up a function in the ELF symbol table by its demangled or raw name.
+    pub fn get_symbol(&self, name: &str) -> Option<&ElfSymbol> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name || symbol.raw_name == name)
+    }
+
+    /// Returns the function symbol containing addr and the offset of addr into it. Symbols with
+    /// no size are taken to extend to the next symbol.
+    pub fn get_symbol_at(&self, addr: usize) -> Option<(&ElfSymbol, usize)> {
+        let index = match self.symbols.binary_search_by_key(&addr, |symbol| symbol.address) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let symbol = &self.symbols[index];
+        let end = match symbol.size {
+            0 => self.symbols.get(index + 1)?.address,
+            size => symbol.address + size,
+        };
+        if addr < end {
+            Some((symbol, addr - symbol.address))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the functions named func_name, which may be a plain name, a qualified name such as
+    /// `ns::Class::method` or `crate::module::fn`, a C++ signature such as `ns::f(int)`, or a
+    /// linkage name. More than one function is returned if the name is overloaded.
+    pub fn find_functions(&self, file: Option<&str>, func_name: &str) -> Vec<&Function> {
+        let in_file = |index: &UnitIndex| match file {
+            Some(file) => index.name == file || (!file.contains("/") && index.name.ends_with(&format!("/{}", file))),
+            None => true,
+        };
+        let collect = |matches: &dyn Fn(&str, &str, Option<&str>) -> bool| {
+            let mut found: Vec<&Function> = Vec::new();
+            let units = self.load_where(|index| {
+                in_file(index)
+                    && (!index.has_names
+                        || index.functions.iter().any(|(name, qualified_name, linkage_name)| {
+                            matches(name, qualified_name, linkage_name.as_deref())
+                        }))
+            });
+            for func in units.into_iter().flat_map(|file| file.functions.iter()) {
+                if func.address != 0
+                    && matches(&func.name, &func.qualified_name, func.linkage_name.as_deref())
+                    && !found.iter().any(|other| other.address == func.address)
+                {
+                    found.push(func);
                 }
-                None
             }
+            found
+        };
+        // Prefer exact matches, then fall back to a trailing part of a qualified name so that
+        // `module::fn` finds `crate::module::fn` and `Class::method` finds `ns::Class::method`.
+        let found = collect(&|name, qualified_name, linkage_name| {
+            name == func_name
+                || qualified_name == func_name
+                || linkage_name == Some(func_name)
+                || linkage_name.map(demangle).as_deref() == Some(func_name)
+        });
+        if !found.is_empty() {
+            return found;
         }
+        let suffix = format!("::{}", func_name);
+        collect(&|_, qualified_name, linkage_name| {
+            qualified_name.ends_with(&suffix) || linkage_name.is_some_and(|name| demangle(name).ends_with(&suffix))
+        })
+    }
+
+    pub fn get_function(&self, func_name: &str) -> Option<&Function> {
+        self.load_where(|index| !index.has_names || index.functions.iter().any(|(name, _, _)| name == func_name))
+            .into_iter()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.name == func_name && func.address != 0)
+    }
+
+    pub fn get_type(&self, offset: usize) -> Option<&TypeInfo> {
+        let unit = self.units.iter().find(|unit| unit.index.contains_die(offset))?;
+        self.load(unit)?;
+        unit.loaded.get()?.types.get(&offset)
+    }
+
+    /// Looks up a type, following typedefs and cv-qualifiers to the underlying type.
+    pub fn resolve_type(&self, offset: usize) -> Option<&TypeInfo> {
+        let mut info = self.get_type(offset)?;
+        for _ in 0..16 {
+            match info.layout {
+                TypeLayout::Alias(target) => info = self.get_type(target)?,
+                _ => return Some(info),
+            }
+        }
+        None
+    }
+
+    /// Returns the function whose code contains addr.
+    pub fn get_function_at(&self, addr: usize) -> Option<&Function> {
+        self.get_unit_at(addr)?
+            .functions
+            .iter()
+            .find(|func| func.address <= addr && addr < func.address + func.text_length)
+    }
+
+    /// Returns the inlined calls whose code contains addr, outermost first.
+    pub fn get_inlined_calls_at(&self, addr: usize) -> Vec<&InlinedCall> {
+        let mut calls: Vec<&InlinedCall> = match self.get_function_at(addr) {
+            Some(func) => func.inlined.iter().filter(|call| call.contains_addr(addr)).collect(),
+            None => return Vec::new(),
+        };
+        calls.sort_by_key(|call| call.depth);
+        calls
+    }
+
+    /// Returns every copy of the function func_name that was inlined somewhere, named as in
+    /// find_functions.
+    pub fn find_inlined_calls(&self, func_name: &str) -> Vec<&InlinedCall> {
+        let suffix = format!("::{}", func_name);
+        let matches = |name: &str| name == func_name || name.ends_with(&suffix);
+        self.load_where(|index| !index.complete || index.inlined.iter().any(|name| matches(name)))
+            .into_iter()
+            .flat_map(|file| file.functions.iter())
+            .flat_map(|func| func.inlined.iter())
+            .filter(|call| matches(&call.name) && call.entry != 0)
+            .collect()
+    }
+
+    pub fn get_global_variable(&self, var_name: &str) -> Option<&Variable> {
+        self.load_where(|index| !index.has_names || index.variables.iter().any(|name| name == var_name))
+            .into_iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == var_name)
+    }
+
+    /// Returns the address of the first line after the prologue of the function starting at
+    /// func_addr, so that the frame pointer is already set up when a breakpoint there is hit.
+    pub fn skip_prologue(&self, func_addr: usize) -> usize {
+        if let Some(file) = self.get_unit_at(func_addr) {
+            if let Some(func) = file.functions.iter().find(|func| func.address == func_addr) {
+                return file
+                    .lines
+                    .iter()
+                    .map(|line| line.address)
+                    .filter(|addr| *addr > func.address && *addr < func.address + func.text_length)
+                    .min()
+                    .unwrap_or(func_addr);
+            }
+        }
+        func_addr
     }
 
-    #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
-            .addr2line
-            .find_location(curr_addr.try_into().unwrap())
-            .ok()??;
-        Some(Line {
-            file: location.file?.to_string(),
-            number: location.line?.try_into().unwrap(),
-            address: curr_addr,
-        })
+            .addr2line()
+            .and_then(|addr2line| addr2line.find_location(curr_addr.try_into().unwrap()).ok())
+            .flatten();
+        match location {
+            Some(location) => Some(Line {
+                file: location.file?.to_string(),
+                number: location.line?.try_into().unwrap(),
+                address: curr_addr,
+            }),
+            // addr2line doesn't read split units, so fall back to our own line table, taking the
+            // last row at or before curr_addr within the same function
+            None => {
+                let func = self.get_function_at(curr_addr)?;
+                let line = self
+                    .get_unit_at(curr_addr)?
+                    .lines
+                    .iter()
+                    .filter(|line| line.address >= func.address && line.address <= curr_addr)
+                    .max_by_key(|line| line.address)?;
+                Some(Line {
+                    address: curr_addr,
+                    ..line.clone()
+                })
+            }
+        }
     }
 
-    #[allow(dead_code)]
+    /// Returns the name of the function whose code contains curr_addr. Code inlined into a
+    /// function counts as that function's; see get_inlined_calls_at for the inlined calls.
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
-        let frame = self
-            .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
-    }
-
-    #[allow(dead_code)]
-    pub fn print(&self) {
-        for file in &self.files {
-            println!("------");
-            println!("{}", file.name);
-            println!("------");
-
-            println!("Global variables:");
-            for var in &file.global_variables {
-                println!(
-                    "  * {} ({}, located at {}, declared at line {})",
-                    var.name, var.entity_type.name, var.location, var.line_number
-                );
+        // Frames come innermost first, so the last one is the function the code was inlined into
+        let mut outermost = None;
+        if let Some(Ok(mut frames)) = self.addr2line().map(|addr2line| addr2line.find_frames(curr_addr.try_into().unwrap())) {
+            while let Ok(Some(frame)) = frames.next() {
+                outermost = Some(frame);
             }
+        }
+        let function = outermost.and_then(|frame| frame.function);
+        match function {
+            Some(function) => Some(demangle(&function.raw_name().ok()?)),
+            // addr2line doesn't read split units, but we do. Failing that, name the code after
+            // the ELF symbol containing it, as in `func+0x1c`.
+            None => match self.get_function_at(curr_addr) {
+                Some(func) => Some(func.linkage_name.as_deref().map_or(func.qualified_name.clone(), demangle)),
+                None => match self.get_symbol_at(curr_addr)? {
+                    (symbol, 0) => Some(symbol.name.clone()),
+                    (symbol, offset) => Some(format!("{}+{:#x}", symbol.name, offset)),
+                },
+            },
+        }
+    }
+}
 
-            println!("Functions:");
-            for func in &file.functions {
-                println!(
-                    "  * {} (declared on line {}, located at {:#x}, {} bytes long)",
-                    func.name, func.line_number, func.address, func.text_length
-                );
-                for var in &func.variables {
-                    println!(
-                        "    * Variable: {} ({}, located at {}, declared at line {})",
-                        var.name, var.entity_type.name, var.location, var.line_number
-                    );
-                }
-            }
+/// Returns the name a function is listed and matched under, such as `ns::Class::method(int)`.
+pub fn function_name(func: &Function) -> String {
+    func.linkage_name.as_deref().map_or(func.qualified_name.clone(), demangle)
+}
 
-            println!("Line numbers:");
-            for line in &file.lines {
-                println!("  * {} (at {:#x})", line.number, line.address);
-            }
+/// Demangles a Rust (legacy or v0) or C++ (Itanium ABI) symbol name, leaving other names
+/// untouched. The hash suffix of legacy Rust symbols is dropped.
+pub fn demangle(name: &str) -> String {
+    demangle_with_options(name, DemangleOptions::new())
+}
+
+/// Like demangle, but leaves out the parameter list and return type of C++ functions, giving a
+/// name such as `ns::Class::method` that can be matched against what the user typed.
+pub fn demangle_without_params(name: &str) -> String {
+    demangle_with_options(name, DemangleOptions::new().no_params().no_return_type())
+}
+
+fn demangle_with_options(name: &str, options: DemangleOptions) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        let symbol = Symbol::new(name).ok();
+        if let Some(demangled) = symbol.and_then(|symbol| symbol.demangle(&options).ok()) {
+            return demangled;
         }
     }
+    name.to_string()
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub offset: usize, // Offset of the type's DIE, for looking up its layout
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeInfo {
+    pub name: String,
+    pub size: usize,
+    pub layout: TypeLayout,
+    pub template_params: Vec<usize>, // Offsets of template type parameters, in order
+    pub rust: bool,                  // Declared in a compilation unit written in Rust
 }
 
-impl Type {
-    pub fn new(name: String, size: usize) -> Self {
-        Type {
-            name: name,
-            size: size,
+impl TypeInfo {
+    /// Returns how the bytes of a base type are read. Values of other types are read as
+    /// unsigned integers.
+    pub fn encoding(&self) -> Encoding {
+        match self.layout {
+            TypeLayout::Base(encoding) => encoding,
+            _ => Encoding::Unsigned,
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum TypeLayout {
+    /// Numbers, characters and booleans, and void
+    Base(Encoding),
+    /// Pointers and references, with the offset of the pointee type (None for void)
+    Pointer(Option<usize>),
+    /// Typedefs and cv-qualified types, with the offset of the underlying type
+    Alias(usize),
+    Struct(Vec<Member>),
+    /// Structs with a variant part, such as Rust enums
+    Variants {
+        discriminant: Option<Member>,
+        variants: Vec<Variant>,
+    },
+    Enumeration(Vec<(String, i64)>),
+    /// Element type offset and number of elements
+    Array(usize, usize),
+}
+
+/// How the bytes of a base type are read, from its DW_AT_encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Signed,
+    /// Also booleans, Unicode characters and types without an encoding
+    Unsigned,
+    Float,
+}
+
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub offset: usize,      // Offset from the start of the enclosing struct
+    pub type_offset: usize, // Offset of the member's type DIE
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub discr_value: Option<u64>, // None for the default variant
+    pub member: Member,
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
@@ -193,13 +644,85 @@ pub struct Variable {
     pub line_number: usize, // Line number in source file
 }
 
+#[derive(Debug, Clone)]
+pub struct ElfSymbol {
+    pub name: String, // Demangled
+    pub raw_name: String,
+    pub address: usize,
+    pub size: usize,
+}
+
+/// Collects the function symbols of the object's symbol table, and its dynamic symbol table in
+/// case the former was stripped.
+fn load_symbols(object: &object::File) -> Vec<ElfSymbol> {
+    let mut symbols: Vec<ElfSymbol> = object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .filter(|(_, symbol)| symbol.kind() == object::SymbolKind::Text && symbol.address() != 0)
+        .filter_map(|(_, symbol)| {
+            let raw_name = symbol.name()?;
+            Some(ElfSymbol {
+                name: demangle(raw_name),
+                raw_name: raw_name.to_string(),
+                address: symbol.address().try_into().ok()?,
+                size: symbol.size().try_into().ok()?,
+            })
+        })
+        .filter(|symbol| !symbol.raw_name.is_empty())
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.address);
+    symbols.dedup_by_key(|symbol| symbol.address);
+    symbols
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Function {
     pub name: String,
+    pub linkage_name: Option<String>,
+    pub qualified_name: String, // e.g. crate::module::name
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
     pub variables: Vec<Variable>,
+    pub params: Vec<Type>,
+    pub return_type: Option<Type>,
+    pub frame_base_register: Option<u16>, // None if the frame base is the CFA
+    pub inlined: Vec<InlinedCall>,         // Copies of other functions inlined into this one
+}
+
+impl Function {
+    /// Returns the variables in scope at addr, innermost first: those of the inlined calls whose
+    /// code contains addr, then the function's own. Variables shadowed by an inner one of the
+    /// same name are left out.
+    pub fn variables_at(&self, addr: usize) -> Vec<&Variable> {
+        let mut calls: Vec<&InlinedCall> = self.inlined.iter().filter(|call| call.contains_addr(addr)).collect();
+        calls.sort_by_key(|call| std::cmp::Reverse(call.depth));
+        let mut variables: Vec<&Variable> = Vec::new();
+        for var in calls.iter().flat_map(|call| call.variables.iter()).chain(self.variables.iter()) {
+            if !variables.iter().any(|other| other.name == var.name) {
+                variables.push(var);
+            }
+        }
+        variables
+    }
+}
+
+/// A copy of a function that the compiler inlined into another.
+#[derive(Debug, Default, Clone)]
+pub struct InlinedCall {
+    pub name: String,                // Qualified name of the inlined function
+    pub entry: usize,                // Address the inlined code starts executing at
+    pub ranges: Vec<(usize, usize)>, // Address ranges of the inlined code
+    pub call_file: String,           // Where the call that was inlined is
+    pub call_line: usize,
+    pub depth: usize, // Number of inlined calls this one is nested in
+    pub variables: Vec<Variable>, // Parameters and locals of the inlined copy
+}
+
+impl InlinedCall {
+    pub fn contains_addr(&self, addr: usize) -> bool {
+        self.ranges.iter().any(|(start, end)| *start <= addr && addr < *end)
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -207,7 +730,7 @@ pub struct File {
     pub name: String,
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
-    pub lines: Vec<Line>,
+    pub lines: Vec<Line>, // Sorted by address once the unit is loaded
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -223,4 +746,32 @@ impl fmt::Display for Line {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangles_rust_symbols() {
+        assert_eq!(demangle("_ZN4deet8debugger8Debugger3run17h0123456789abcdefE"), "deet::debugger::Debugger::run");
+        assert_eq!(demangle("_RNvCs1234_7mycrate3foo"), "mycrate::foo");
+    }
+
+    #[test]
+    fn demangles_cpp_symbols() {
+        assert_eq!(demangle("_ZN2ns5Class6methodEi"), "ns::Class::method(int)");
+        assert_eq!(demangle("_Z5func1v"), "func1()");
+    }
+
+    #[test]
+    fn leaves_out_cpp_parameters() {
+        assert_eq!(demangle_without_params("_ZN2ns5Class6methodEi"), "ns::Class::method");
+        assert_eq!(demangle_without_params("_ZN2ns3maxIiEET_S1_S1_"), "ns::max<int>");
+        assert_eq!(demangle_without_params("_ZN4deet3run17h0123456789abcdefE"), "deet::run");
+    }
 
+    #[test]
+    fn leaves_other_names_alone() {
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(demangle("_Znot_mangled"), "_Znot_mangled");
+    }
+}
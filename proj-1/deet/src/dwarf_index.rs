@@ -0,0 +1,284 @@
+//! An index of what each compilation unit holds, so that units are only parsed once something in
+//! them is needed. When the program has accelerator tables (`.debug_names`, `.debug_aranges`), a
+//! partial index is built from them. Otherwise building the index means loading every unit once,
+//! so it is cached on disk under the executable's build ID.
+
+use crate::dwarf_data::File;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const CACHE_VERSION: &str = "deet-index 4";
+
+#[derive(Debug, Clone, Default)]
+pub struct UnitIndex {
+    pub offset: usize,             // Offset of the unit in .debug_info
+    pub die_range: (usize, usize), // Range of its DIE offsets, which its type offsets fall in
+    pub name: String,
+    pub ranges: Vec<(usize, usize)>,                      // Address ranges of its functions
+    pub functions: Vec<(String, String, Option<String>)>, // Name, qualified and linkage name
+    pub variables: Vec<String>,                           // Global variables
+    pub inlined: Vec<String>,                             // Functions inlined somewhere in it
+    pub files: Vec<String>,                               // Source files in its line table
+    // Whether functions and variables hold every name the unit defines, and whether inlined and
+    // files are complete too. Indexes built from the accelerator tables leave out what those
+    // don't record, and lookups by it have to load the unit instead.
+    pub has_names: bool,
+    pub complete: bool,
+}
+
+impl UnitIndex {
+    pub fn new(offset: usize, die_range: (usize, usize), file: &File) -> UnitIndex {
+        let functions = file.functions.iter().filter(|func| func.address != 0);
+        let ranges: Vec<(usize, usize)> = functions
+            .clone()
+            .map(|func| (func.address, func.address + func.text_length))
+            .collect();
+        let mut files: Vec<String> = file.lines.iter().map(|line| line.file.clone()).collect();
+        files.sort();
+        files.dedup();
+        let mut inlined: Vec<String> = file
+            .functions
+            .iter()
+            .flat_map(|func| func.inlined.iter().map(|call| call.name.clone()))
+            .filter(|name| !name.is_empty())
+            .collect();
+        inlined.sort();
+        inlined.dedup();
+        UnitIndex {
+            offset,
+            die_range,
+            name: file.name.clone(),
+            ranges: merge_ranges(ranges),
+            functions: functions
+                .map(|func| (func.name.clone(), func.qualified_name.clone(), func.linkage_name.clone()))
+                .collect(),
+            variables: file.global_variables.iter().map(|var| var.name.clone()).collect(),
+            inlined,
+            files,
+            has_names: true,
+            complete: true,
+        }
+    }
+
+    pub fn contains_addr(&self, addr: usize) -> bool {
+        self.ranges.iter().any(|(start, end)| *start <= addr && addr < *end)
+    }
+
+    pub fn contains_die(&self, offset: usize) -> bool {
+        self.die_range.0 <= offset && offset < self.die_range.1
+    }
+}
+
+/// Sorts ranges and merges those that overlap or touch.
+pub fn merge_ranges(mut ranges: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    ranges.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn cache_path(build_id: &[u8]) -> Option<PathBuf> {
+    let cache_dir = match std::env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".cache"),
+    };
+    let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+    Some(cache_dir.join("deet").join(format!("{}.index", hex)))
+}
+
+/// Identifies the file the debugging information was read from, the executable or its separate
+/// debug file, by path and modification time, so that the cache is rebuilt when it changes even
+/// if the build ID stays the same.
+fn source_key(debug_path: &Path) -> String {
+    let mtime = fs::metadata(debug_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |mtime| mtime.as_nanos());
+    format!("{}\t{}", escape(&debug_path.to_string_lossy()), mtime)
+}
+
+/// Escapes the characters that separate fields and records of the cache.
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            unescaped.push(match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'n' => '\n',
+                _ => return None,
+            });
+        } else {
+            unescaped.push(c);
+        }
+    }
+    Some(unescaped)
+}
+
+/// Reads the index cached for the executable with the given build ID, whose debugging
+/// information was read from debug_path.
+pub fn load_cached(build_id: &[u8], debug_path: &Path) -> Option<Vec<UnitIndex>> {
+    let contents = fs::read_to_string(cache_path(build_id)?).ok()?;
+    parse_index(&contents, &source_key(debug_path))
+}
+
+fn parse_index(contents: &str, source_key: &str) -> Option<Vec<UnitIndex>> {
+    let mut lines = contents.lines();
+    if lines.next()? != CACHE_VERSION || lines.next()? != source_key {
+        return None;
+    }
+    let mut units: Vec<UnitIndex> = Vec::new();
+    for line in lines {
+        let fields = line.split('\t').map(unescape).collect::<Option<Vec<String>>>()?;
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        match fields[..] {
+            ["unit", offset, start, end, name] => units.push(UnitIndex {
+                offset: offset.parse().ok()?,
+                die_range: (start.parse().ok()?, end.parse().ok()?),
+                name: name.to_string(),
+                has_names: true,
+                complete: true,
+                ..Default::default()
+            }),
+            ["range", start, end] => units.last_mut()?.ranges.push((start.parse().ok()?, end.parse().ok()?)),
+            ["func", name, qualified_name, linkage_name] => units.last_mut()?.functions.push((
+                name.to_string(),
+                qualified_name.to_string(),
+                Some(linkage_name.to_string()).filter(|name| !name.is_empty()),
+            )),
+            ["var", name] => units.last_mut()?.variables.push(name.to_string()),
+            ["inline", name] => units.last_mut()?.inlined.push(name.to_string()),
+            ["file", name] => units.last_mut()?.files.push(name.to_string()),
+            _ => return None,
+        }
+    }
+    Some(units)
+}
+
+/// Caches the index for the executable with the given build ID, whose debugging information was
+/// read from debug_path. Failing to do so only costs time on the next run, so errors are ignored.
+pub fn save(build_id: &[u8], debug_path: &Path, units: &[UnitIndex]) {
+    let path = match cache_path(build_id) {
+        Some(path) => path,
+        None => return,
+    };
+    let contents = format_index(units, &source_key(debug_path));
+    // Write to a temporary file first so that concurrent sessions never see half an index
+    let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::File::create(&tmp_path))
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|_| fs::rename(&tmp_path, &path));
+    if written.is_err() {
+        fs::remove_file(&tmp_path).ok();
+    }
+}
+
+/// Formats the index as a line per record of tab-separated, escaped fields.
+fn format_index(units: &[UnitIndex], source_key: &str) -> String {
+    let mut contents = format!("{}\n{}\n", CACHE_VERSION, source_key);
+    let mut record = |fields: &[&str]| {
+        let fields: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+        contents += &fields.join("\t");
+        contents.push('\n');
+    };
+    for unit in units {
+        let (offset, start, end) = (unit.offset.to_string(), unit.die_range.0.to_string(), unit.die_range.1.to_string());
+        record(&["unit", &offset, &start, &end, &unit.name]);
+        for (start, end) in &unit.ranges {
+            record(&["range", &start.to_string(), &end.to_string()]);
+        }
+        for (name, qualified_name, linkage_name) in &unit.functions {
+            record(&["func", name, qualified_name, linkage_name.as_deref().unwrap_or("")]);
+        }
+        for name in &unit.variables {
+            record(&["var", name]);
+        }
+        for name in &unit.inlined {
+            record(&["inline", name]);
+        }
+        for name in &unit.files {
+            record(&["file", name]);
+        }
+    }
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_units() -> Vec<UnitIndex> {
+        vec![
+            UnitIndex {
+                offset: 0,
+                die_range: (0xb, 0x120),
+                name: "/src/main.c".to_string(),
+                ranges: vec![(0x401000, 0x401080)],
+                functions: vec![
+                    ("main".to_string(), "main".to_string(), None),
+                    ("method".to_string(), "ns::Class::method".to_string(), Some("_ZN2ns5Class6methodEi".to_string())),
+                ],
+                variables: vec!["counter".to_string()],
+                inlined: vec!["helper".to_string()],
+                files: vec!["/src/main.c".to_string(), "/src/util.h".to_string()],
+                has_names: true,
+                complete: true,
+            },
+            UnitIndex {
+                offset: 0x120,
+                die_range: (0x12b, 0x200),
+                name: "odd\tname\\with\nbreaks".to_string(),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trips_through_the_cache_format() {
+        let units = sample_units();
+        let contents = format_index(&units, "/bin/prog\t42");
+        let parsed = parse_index(&contents, "/bin/prog\t42").unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].die_range, units[0].die_range);
+        assert_eq!(parsed[0].ranges, units[0].ranges);
+        assert_eq!(parsed[0].functions, units[0].functions);
+        assert_eq!(parsed[0].variables, units[0].variables);
+        assert_eq!(parsed[0].inlined, units[0].inlined);
+        assert_eq!(parsed[0].files, units[0].files);
+        assert_eq!((parsed[1].offset, parsed[1].name.as_str()), (0x120, "odd\tname\\with\nbreaks"));
+        assert!(parsed.iter().all(|unit| unit.has_names && unit.complete));
+        assert_eq!(format_index(&parsed, "/bin/prog\t42"), contents);
+    }
+
+    #[test]
+    fn rejects_stale_or_corrupt_caches() {
+        let contents = format_index(&sample_units(), "/bin/prog\t42");
+        assert!(parse_index(&contents, "/bin/prog\t43").is_none());
+        assert!(parse_index(&contents.replacen(CACHE_VERSION, "deet-index 0", 1), "/bin/prog\t42").is_none());
+        assert!(parse_index(&(contents.clone() + "func\tonly two\n"), "/bin/prog\t42").is_none());
+        assert!(parse_index(&(contents + "var\tbad\\escape\n"), "/bin/prog\t42").is_none());
+        let orphan = format!("{}\n{}\nvar\tx\n", CACHE_VERSION, "/bin/prog\t42");
+        assert!(parse_index(&orphan, "/bin/prog\t42").is_none());
+    }
+
+    #[test]
+    fn merges_overlapping_and_touching_ranges() {
+        assert_eq!(merge_ranges(vec![(30, 40), (0, 10), (10, 20), (35, 50), (60, 70)]), vec![(0, 20), (30, 50), (60, 70)]);
+    }
+}
@@ -0,0 +1,80 @@
+//! The errors deet reports. Commands return them to the prompt, which prints them and carries on,
+//! so that no command can bring the debugger down, and programs using deet get them from `Target`.
+
+use crate::dwarf_data;
+use nix::errno::Errno;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum DeetError {
+    /// The debugging information of the file at the path could not be loaded
+    Dwarf(String, dwarf_data::Error),
+    /// A ptrace or waitpid call on the inferior failed
+    Ptrace(nix::Error),
+    /// The command needs a process, and none is running
+    NoProcess,
+    /// The process went away while a command was working with it, as when it's killed from
+    /// outside
+    ProcessExited,
+    /// The command was given something it can't use, such as an unknown location
+    Input(String),
+    /// Process record can't log the instruction at the address, so recording was stopped before
+    /// it ran. Contains the reason
+    RecordStopped(String, usize),
+    /// The location names more than one function. Contains the name and a description of each
+    AmbiguousLocation(String, Vec<String>),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for DeetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeetError::Dwarf(path, dwarf_data::Error::ErrorOpeningFile) => write!(f, "Could not open file {}", path),
+            DeetError::Dwarf(path, dwarf_data::Error::DwarfFormatError(err)) => {
+                write!(f, "Could not read debugging symbols from {}: {:?}", path, err)
+            }
+            DeetError::Ptrace(err) => write!(f, "Could not control the process: {}", err),
+            DeetError::NoProcess => write!(f, "No process is running"),
+            DeetError::ProcessExited => write!(f, "The process is no longer running"),
+            DeetError::Input(message) => write!(f, "{}", message),
+            DeetError::RecordStopped(reason, addr) => {
+                write!(f, "{} at {:#x}. Recording stopped; the process is stopped before it.", reason, addr)
+            }
+            DeetError::AmbiguousLocation(name, candidates) => {
+                writeln!(f, "Function \"{}\" is ambiguous. Candidates are:", name)?;
+                for candidate in candidates {
+                    writeln!(f, "  {}", candidate)?;
+                }
+                write!(f, "Use the full signature or *address to pick one")
+            }
+            DeetError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<nix::Error> for DeetError {
+    fn from(err: nix::Error) -> Self {
+        match err {
+            nix::Error::Sys(Errno::ESRCH) => DeetError::ProcessExited,
+            err => DeetError::Ptrace(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for DeetError {
+    fn from(err: std::io::Error) -> Self {
+        DeetError::Io(err)
+    }
+}
+
+impl From<String> for DeetError {
+    fn from(message: String) -> Self {
+        DeetError::Input(message)
+    }
+}
+
+impl From<&str> for DeetError {
+    fn from(message: &str) -> Self {
+        DeetError::Input(message.to_string())
+    }
+}
@@ -0,0 +1,406 @@
+//! A small expression language for `print` and `call`: integer and floating point literals,
+//! variables, function calls and the usual arithmetic operators.
+
+use crate::dwarf_data::Encoding;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Int(i64),
+    Float(f64),
+    Var(String),
+    Call(String, Vec<Expr>),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Pointer(u64),
+    /// A struct, enum, string or other value that can only be displayed, already formatted
+    Aggregate(String),
+    Void,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Pointer(v) => write!(f, "{:#x}", v),
+            Value::Aggregate(ref v) => write!(f, "{}", v),
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Value::Int(v) => Some(v),
+            Value::Float(v) => Some(v as i64),
+            Value::Pointer(v) => Some(v as i64),
+            Value::Aggregate(_) | Value::Void => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match *self {
+            Value::Int(v) => Some(v as f64),
+            Value::Float(v) => Some(v),
+            Value::Pointer(_) | Value::Aggregate(_) | Value::Void => None,
+        }
+    }
+
+    /// Decodes a value of a base type with the given encoding from its little-endian in-memory
+    /// representation. Floating point types we don't know how to interpret are shown as
+    /// unsigned integers.
+    pub fn from_bytes(encoding: Encoding, bytes: &[u8]) -> Value {
+        let mut raw = [0u8; 8];
+        let len = bytes.len().min(8);
+        raw[..len].copy_from_slice(&bytes[..len]);
+        let bits = u64::from_le_bytes(raw);
+        match (encoding, len) {
+            (_, 0) => Value::Void,
+            (Encoding::Float, 4) => Value::Float(f32::from_bits(bits as u32) as f64),
+            (Encoding::Float, 8) => Value::Float(f64::from_bits(bits)),
+            // Sign-extend from the type's width
+            (Encoding::Signed, len) => Value::Int(((bits << (64 - 8 * len)) as i64) >> (64 - 8 * len)),
+            _ => Value::Int(bits as i64),
+        }
+    }
+
+    /// Applies a binary arithmetic operator, promoting to floating point if either side is.
+    pub fn binary(op: char, lhs: Value, rhs: Value) -> Result<Value, String> {
+        match (lhs, rhs) {
+            (Value::Void, _) | (_, Value::Void) => Err("void value in expression".to_string()),
+            (Value::Aggregate(_), _) | (_, Value::Aggregate(_)) => {
+                Err("arithmetic on a non-scalar value".to_string())
+            }
+            (Value::Pointer(a), Value::Int(b)) if op == '+' => Ok(Value::Pointer(a.wrapping_add(b as u64))),
+            (Value::Int(b), Value::Pointer(a)) if op == '+' => Ok(Value::Pointer(a.wrapping_add(b as u64))),
+            (Value::Pointer(a), Value::Int(b)) if op == '-' => Ok(Value::Pointer(a.wrapping_sub(b as u64))),
+            (Value::Pointer(a), Value::Pointer(b)) if op == '-' => Ok(Value::Int(a.wrapping_sub(b) as i64)),
+            (Value::Pointer(_), _) | (_, Value::Pointer(_)) => {
+                Err(format!("invalid operator {} on a pointer", op))
+            }
+            (Value::Int(a), Value::Int(b)) => match op {
+                '+' => Ok(Value::Int(a.wrapping_add(b))),
+                '-' => Ok(Value::Int(a.wrapping_sub(b))),
+                '*' => Ok(Value::Int(a.wrapping_mul(b))),
+                '/' | '%' if b == 0 => Err("division by zero".to_string()),
+                '/' => Ok(Value::Int(a.wrapping_div(b))),
+                '%' => Ok(Value::Int(a.wrapping_rem(b))),
+                _ => Err(format!("unknown operator {}", op)),
+            },
+            (a, b) => {
+                let (a, b) = (a.as_float().unwrap(), b.as_float().unwrap());
+                match op {
+                    '+' => Ok(Value::Float(a + b)),
+                    '-' => Ok(Value::Float(a - b)),
+                    '*' => Ok(Value::Float(a * b)),
+                    '/' => Ok(Value::Float(a / b)),
+                    '%' => Ok(Value::Float(a % b)),
+                    _ => Err(format!("unknown operator {}", op)),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Float(f64),
+    Ident(String),
+    Punct(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Int(v) => write!(f, "{}", v),
+            Token::Float(v) => write!(f, "{}", v),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Punct(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit()) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let lower = text.to_lowercase();
+            if let Some(hex) = lower.strip_prefix("0x") {
+                tokens.push(Token::Int(
+                    i64::from_str_radix(hex, 16).or(Err(format!("bad number {}", text)))?,
+                ));
+            } else if text.contains('.') || lower.contains('e') {
+                tokens.push(Token::Float(text.parse().or(Err(format!("bad number {}", text)))?));
+            } else {
+                tokens.push(Token::Int(text.parse().or(Err(format!("bad number {}", text)))?));
+            }
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if "+-*/%(),".contains(c) {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct(c)) if *c == '+' || *c == '-' => *c,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct(c)) if *c == '*' || *c == '/' || *c == '%' => *c,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Int(v)) => Ok(Expr::Int(v)),
+            Some(Token::Float(v)) => Ok(Expr::Float(v)),
+            Some(Token::Ident(name)) => {
+                if !self.eat('(') {
+                    return Ok(Expr::Var(name));
+                }
+                let mut args = Vec::new();
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(')') {
+                            break;
+                        }
+                        if !self.eat(',') {
+                            return Err("expected ',' or ')' in argument list".to_string());
+                        }
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            Some(Token::Punct('(')) => {
+                let inner = self.expr()?;
+                if !self.eat(')') {
+                    return Err("expected ')'".to_string());
+                }
+                Ok(inner)
+            }
+            Some(token) => Err(format!("unexpected token '{}'", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Looks up `$`, `$$`, `$N`, `$$N` in the value history, or else a convenience variable.
+/// Convenience variables that were never set are void.
+pub fn history_value(history: &[Value], variables: &HashMap<String, Value>, name: &str) -> Result<Value, String> {
+    let len = history.len();
+    let is_number = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_digit());
+    let index = match name.strip_prefix("$$") {
+        // `$$N` counts back from the last value, `$$` being the one before it
+        Some("") => len.checked_sub(1),
+        Some(back) if is_number(back) => back.parse().ok().and_then(|back| len.checked_sub(back)),
+        _ if name == "$" => Some(len),
+        _ if is_number(&name[1..]) => name[1..].parse().ok(),
+        _ => return Ok(variables.get(&name[1..]).cloned().unwrap_or(Value::Void)),
+    };
+    match index {
+        Some(idx) if idx >= 1 && idx <= len => Ok(history[idx - 1].clone()),
+        // Like an unset variable, the last value is void while the history is empty
+        Some(0) | None if len == 0 => Ok(Value::Void),
+        _ => Err(format!("History has not yet reached {}.", name)),
+    }
+}
+
+/// Parses an expression such as `sum * 2` or `func2(a, 1.5)`.
+pub fn parse(input: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let expr = parser.expr()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!("unexpected token '{}'", parser.tokens[parser.pos]));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(v: i64) -> Box<Expr> {
+        Box::new(Expr::Int(v))
+    }
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Var(name.to_string()))
+    }
+
+    #[test]
+    fn parses_literals() {
+        assert_eq!(parse("42"), Ok(Expr::Int(42)));
+        assert_eq!(parse("0x1F"), Ok(Expr::Int(31)));
+        assert_eq!(parse("1.5"), Ok(Expr::Float(1.5)));
+        assert_eq!(parse(".5"), Ok(Expr::Float(0.5)));
+        assert_eq!(parse("2e3"), Ok(Expr::Float(2000.0)));
+        assert_eq!(parse("$1"), Ok(Expr::Var("$1".to_string())));
+    }
+
+    #[test]
+    fn follows_precedence_and_associativity() {
+        assert_eq!(parse("a + b * 2"), Ok(Expr::Binary('+', var("a"), Box::new(Expr::Binary('*', var("b"), int(2))))));
+        assert_eq!(parse("10 - 3 - 2"), Ok(Expr::Binary('-', Box::new(Expr::Binary('-', int(10), int(3))), int(2))));
+        assert_eq!(parse("(a + 1) % 4"), Ok(Expr::Binary('%', Box::new(Expr::Binary('+', var("a"), int(1))), int(4))));
+        assert_eq!(parse("--x"), Ok(Expr::Neg(Box::new(Expr::Neg(var("x"))))));
+        assert_eq!(parse("-a * b"), Ok(Expr::Binary('*', Box::new(Expr::Neg(var("a"))), var("b"))));
+    }
+
+    #[test]
+    fn parses_calls() {
+        assert_eq!(parse("f()"), Ok(Expr::Call("f".to_string(), vec![])));
+        assert_eq!(
+            parse("func2(a, 1.5 + g(2))"),
+            Ok(Expr::Call(
+                "func2".to_string(),
+                vec![
+                    Expr::Var("a".to_string()),
+                    Expr::Binary('+', Box::new(Expr::Float(1.5)), Box::new(Expr::Call("g".to_string(), vec![Expr::Int(2)]))),
+                ]
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert_eq!(parse(""), Err("unexpected end of expression".to_string()));
+        assert_eq!(parse("1 +"), Err("unexpected end of expression".to_string()));
+        assert_eq!(parse("(1"), Err("expected ')'".to_string()));
+        assert_eq!(parse("f(1 2)"), Err("expected ',' or ')' in argument list".to_string()));
+        assert_eq!(parse("1 2"), Err("unexpected token '2'".to_string()));
+        assert_eq!(parse("a & b"), Err("unexpected character '&'".to_string()));
+        assert_eq!(parse("0xzz"), Err("bad number 0xzz".to_string()));
+    }
+
+    #[test]
+    fn evaluates_operators() {
+        assert_eq!(Value::binary('/', Value::Int(7), Value::Int(2)), Ok(Value::Int(3)));
+        assert_eq!(Value::binary('+', Value::Int(1), Value::Float(0.5)), Ok(Value::Float(1.5)));
+        assert_eq!(Value::binary('+', Value::Pointer(0x1000), Value::Int(8)), Ok(Value::Pointer(0x1008)));
+        assert_eq!(Value::binary('-', Value::Pointer(0x1008), Value::Pointer(0x1000)), Ok(Value::Int(8)));
+        assert_eq!(Value::binary('%', Value::Int(1), Value::Int(0)), Err("division by zero".to_string()));
+        assert!(Value::binary('*', Value::Pointer(1), Value::Int(2)).is_err());
+    }
+
+    #[test]
+    fn decodes_bytes_by_encoding() {
+        assert_eq!(Value::from_bytes(Encoding::Signed, &[0xfb]), Value::Int(-5));
+        assert_eq!(Value::from_bytes(Encoding::Unsigned, &[0xc8]), Value::Int(200));
+        assert_eq!(Value::from_bytes(Encoding::Signed, &(-300i16).to_le_bytes()), Value::Int(-300));
+        assert_eq!(Value::from_bytes(Encoding::Float, &2.5f32.to_le_bytes()), Value::Float(2.5));
+        assert_eq!(Value::from_bytes(Encoding::Float, &1.5f64.to_le_bytes()), Value::Float(1.5));
+        assert_eq!(Value::from_bytes(Encoding::Unsigned, &[]), Value::Void);
+    }
+
+    #[test]
+    fn looks_up_value_history() {
+        let history = vec![Value::Int(10), Value::Int(20), Value::Int(30)];
+        let variables = HashMap::new();
+        let lookup = |name| history_value(&history, &variables, name);
+        assert_eq!(lookup("$"), Ok(Value::Int(30)));
+        assert_eq!(lookup("$$"), Ok(Value::Int(20)));
+        assert_eq!(lookup("$$0"), Ok(Value::Int(30)));
+        assert_eq!(lookup("$$2"), Ok(Value::Int(10)));
+        assert_eq!(lookup("$1"), Ok(Value::Int(10)));
+        assert_eq!(lookup("$3"), Ok(Value::Int(30)));
+        assert_eq!(lookup("$4"), Err("History has not yet reached $4.".to_string()));
+        assert_eq!(lookup("$0"), Err("History has not yet reached $0.".to_string()));
+        assert_eq!(lookup("$$3"), Err("History has not yet reached $$3.".to_string()));
+    }
+
+    #[test]
+    fn empty_history_is_void() {
+        let variables = HashMap::new();
+        assert_eq!(history_value(&[], &variables, "$"), Ok(Value::Void));
+        assert_eq!(history_value(&[], &variables, "$$"), Ok(Value::Void));
+        assert_eq!(history_value(&[], &variables, "$1"), Err("History has not yet reached $1.".to_string()));
+    }
+
+    #[test]
+    fn falls_back_to_convenience_variables() {
+        let mut variables = HashMap::new();
+        variables.insert("count".to_string(), Value::Int(3));
+        assert_eq!(history_value(&[Value::Int(1)], &variables, "$count"), Ok(Value::Int(3)));
+        assert_eq!(history_value(&[Value::Int(1)], &variables, "$unset"), Ok(Value::Void));
+    }
+}
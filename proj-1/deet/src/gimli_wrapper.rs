@@ -5,242 +5,1007 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
-use gimli::{UnitOffset, UnitSectionOffset};
-use object::Object;
+use gimli::{Section, UnitOffset, UnitSectionOffset};
 use std::borrow;
-//use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use std::cell::RefCell;
+use crate::debug_file::{self, DwarfPackage};
+use crate::debug_names;
+use crate::dwarf_index::{self, UnitIndex};
+use crate::dwarf_data::{
+    demangle_without_params, Encoding, File, Function, InlinedCall, Line, Location, Member, Type, TypeInfo,
+    TypeLayout, Variable, Variant,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
-use std::{io, path};
-
-pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
-    // Load a section and return as `Cow<[u8]>`.
-    let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-        Ok(object
-            .section_data_by_name(id.name())
-            .unwrap_or(borrow::Cow::Borrowed(&[][..])))
-    };
-    // Load a supplementary section. We don't have a supplementary object file,
-    // so always return an empty slice.
-    let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
-
-    // Load all of the sections.
-    let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
-
-    // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-    let borrow_section: &dyn for<'a> Fn(
-        &'a borrow::Cow<[u8]>,
-    ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
-
-    // Create `EndianSlice`s for all of the sections.
-    let dwarf = dwarf_cow.borrow(&borrow_section);
-
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
-
-    let mut compilation_units: Vec<File> = Vec::new();
-
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    while let Some(header) = iter.next()? {
-        let unit = dwarf.unit(header)?;
-
-        // Iterate over the Debugging Information Entries (DIEs) in the unit.
-        let mut depth = 0;
-        let mut entries = unit.entries();
-        while let Some((delta_depth, entry)) = entries.next_dfs()? {
-            depth += delta_depth;
-            // Update the offset_to_type mapping for types
-            // Update the variable list for formal params/variables
-            match entry.tag() {
-                gimli::DW_TAG_compile_unit => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
+use std::rc::Rc;
+use std::{fs, io, path};
+
+/// The reader the executable's sections are kept in, so units can be parsed on demand
+pub type DwarfReader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+/// A parsed unit, the types it defines and, for split units, the length of their .debug_info
+type ParsedUnit = (File, HashMap<usize, TypeInfo>, Option<usize>);
+
+/// The DWARF sections of an executable, from which compilation units are loaded one at a time.
+pub struct DwarfSections {
+    dwarf: gimli::Dwarf<DwarfReader>,
+    // The sections dwarf was loaded from, for building addr2line's view of them
+    readers: Vec<(gimli::SectionId, DwarfReader)>,
+    // Accelerator tables, empty if the program has none
+    debug_names: Rc<[u8]>,
+    debug_aranges: Rc<[u8]>,
+    split_dwarf: SplitDwarf,
+}
+
+impl DwarfSections {
+    pub fn load(
+        object: &object::File,
+        endian: gimli::RunTimeEndian,
+        path: &path::Path,
+    ) -> Result<DwarfSections, Error> {
+        // Copy each section into a reference-counted buffer so that it outlives the mapping of
+        // the file.
+        let mut readers = Vec::new();
+        let mut load_section = |id: gimli::SectionId| -> Result<DwarfReader, gimli::Error> {
+            let data = debug_file::section_data(object, id.name()).unwrap_or(borrow::Cow::Borrowed(&[][..]));
+            let reader = gimli::EndianRcSlice::new(Rc::from(&*data), endian);
+            readers.push((id, reader.clone()));
+            Ok(reader)
+        };
+        // Load a supplementary section. We don't have a supplementary object file,
+        // so always return an empty slice.
+        let load_section_sup =
+            |_| -> Result<DwarfReader, gimli::Error> { Ok(gimli::EndianRcSlice::new(Rc::from(&[][..]), endian)) };
+
+        // Load all of the sections.
+        let dwarf = gimli::Dwarf::load(&mut load_section, &load_section_sup)?;
+        let raw_section = |name| debug_file::section_data(object, name).map_or(Rc::from(&[][..]), |data| Rc::from(&*data));
+        Ok(DwarfSections {
+            dwarf,
+            readers,
+            debug_names: raw_section(".debug_names"),
+            debug_aranges: raw_section(".debug_aranges"),
+            split_dwarf: SplitDwarf {
+                endian,
+                path: path.to_path_buf(),
+                debug_addr: raw_section(".debug_addr"),
+                package: DwarfPackage::open(path),
+                warnings: RefCell::new(Vec::new()),
+            },
+        })
+    }
+
+    /// Returns the problems met loading units that didn't stop them loading, such as missing
+    /// split units, and forgets them.
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.split_dwarf.warnings.take()
+    }
+
+    /// Builds addr2line's context over the same sections.
+    pub fn addr2line_context(&self) -> Result<addr2line::Context<DwarfReader>, Error> {
+        let empty = || gimli::EndianRcSlice::new(Rc::from(&[][..]), self.split_dwarf.endian);
+        let load_section = |id: gimli::SectionId| -> Result<DwarfReader, gimli::Error> {
+            Ok(self.readers.iter().find(|(section, _)| *section == id).map_or_else(empty, |(_, reader)| reader.clone()))
+        };
+        let dwarf = gimli::Dwarf::load(load_section, |_| -> Result<DwarfReader, gimli::Error> { Ok(empty()) })?;
+        Ok(addr2line::Context::from_dwarf(dwarf)?)
+    }
+
+    /// Indexes the compilation units from the accelerator tables, reading only the first DIE of
+    /// each: names from `.debug_names` and addresses from `.debug_aranges`, or from the unit's
+    /// own ranges if the program has no `.debug_aranges`. Returns None if there is no
+    /// `.debug_names` and no `.debug_aranges` to build it from, or if there are split units,
+    /// whose DIE offsets are only known once they're loaded.
+    pub fn index_from_accelerator_tables(&self) -> Result<Option<Vec<UnitIndex>>, Error> {
+        if self.debug_names.is_empty() && self.debug_aranges.is_empty() {
+            return Ok(None);
+        }
+        let little_endian = self.split_dwarf.endian == gimli::RunTimeEndian::Little;
+        let names = if self.debug_names.is_empty() {
+            None
+        } else {
+            match debug_names::parse(&self.debug_names, little_endian) {
+                Some(names) => Some(names),
+                None => return Ok(None),
+            }
+        };
+
+        let mut index = Vec::new();
+        let mut iter = self.dwarf.units();
+        while let Some(header) = iter.next()? {
+            let offset = header.offset().0;
+            let length = header.length_including_self();
+            let unit = self.dwarf.unit(header)?;
+            let mut ranges = Vec::new();
+            let mut entries = unit.entries();
+            if let Some((_, root)) = entries.next_dfs()? {
+                if root.attr(gimli::DW_AT_dwo_name)?.is_some() || root.attr(gimli::DW_AT_GNU_dwo_name)?.is_some() {
+                    return Ok(None);
+                }
+            }
+            if self.debug_aranges.is_empty() {
+                let mut unit_ranges = self.dwarf.unit_ranges(&unit)?;
+                while let Some(range) = unit_ranges.next()? {
+                    ranges.push((range.begin as usize, range.end as usize));
+                }
+            }
+            let name = match unit.name {
+                Some(ref name) => gimli::Reader::to_string_lossy(name)?.into_owned(),
+                None => "<unknown>".to_string(),
+            };
+            index.push(UnitIndex {
+                offset,
+                die_range: (offset, offset + length),
+                name,
+                ranges,
+                has_names: names.is_some(),
+                complete: false,
+                ..Default::default()
+            });
+        }
+
+        let unit_at = |index: &mut Vec<UnitIndex>, offset: usize| index.iter_mut().position(|unit| unit.offset == offset);
+        if !self.debug_aranges.is_empty() {
+            let aranges = gimli::DebugAranges::new(&self.debug_aranges, self.split_dwarf.endian);
+            let mut entries = aranges.items();
+            while let Some(entry) = entries.next()? {
+                if let Some(unit) = unit_at(&mut index, entry.debug_info_offset().0) {
+                    let start = entry.address() as usize;
+                    index[unit].ranges.push((start, start + entry.length() as usize));
+                }
+            }
+        }
+        for unit in index.iter_mut() {
+            unit.ranges = dwarf_index::merge_ranges(std::mem::take(&mut unit.ranges));
+        }
+
+        for entry in names.unwrap_or_default() {
+            let unit = match unit_at(&mut index, entry.unit_offset) {
+                Some(unit) => &mut index[unit],
+                None => continue,
+            };
+            let name = self.dwarf.debug_str.get_str(gimli::DebugStrOffset(entry.name_offset))?;
+            let name = gimli::Reader::to_string_lossy(&name)?.into_owned();
+            match gimli::DwTag(entry.tag) {
+                gimli::DW_TAG_subprogram => {
+                    // Functions are listed under both their name and their linkage name, which
+                    // can't be told apart without reading the DIE but for the mangling.
+                    if name.starts_with("_Z") || name.starts_with("_R") {
+                        let qualified_name = demangle_without_params(&name);
+                        let plain_name = qualified_name.rsplit("::").next().unwrap_or_default().to_string();
+                        unit.functions.push((plain_name, qualified_name, Some(name)));
                     } else {
-                        "<unknown>".to_string()
-                    };
-                    compilation_units.push(File {
-                        name,
-                        global_variables: Vec::new(),
-                        functions: Vec::new(),
-                        lines: Vec::new(),
-                    });
-                }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
+                        unit.functions.push((name.clone(), name, None));
+                    }
+                }
+                gimli::DW_TAG_variable => unit.variables.push(name),
+                _ => {}
+            }
+        }
+        Ok(Some(index))
+    }
+
+    /// Loads every compilation unit once to index which functions, variables, source files and
+    /// addresses each of them holds.
+    pub fn index_units(&self) -> Result<Vec<UnitIndex>, Error> {
+        // Split units come from files of their own, whose DIE offsets overlap the executable's.
+        // They are shifted past the end of .debug_info to keep type offsets unique.
+        let mut split_base = self.dwarf.debug_info.reader().len();
+        let mut index = Vec::new();
+        let mut iter = self.dwarf.units();
+        while let Some(header) = iter.next()? {
+            let offset = header.offset().0;
+            let length = header.length_including_self();
+            let unit = self.dwarf.unit(header)?;
+            let (file, _, split_len) = self.parse(&unit, split_base)?;
+            let die_range = match split_len {
+                Some(len) => {
+                    split_base += len;
+                    (split_base - len, split_base)
+                }
+                None => (offset, offset + length),
+            };
+            index.push(UnitIndex::new(offset, die_range, &file));
+        }
+        Ok(index)
+    }
+
+    /// Loads the functions, variables, types and lines of the unit indexed as unit_index.
+    pub fn load_unit(&self, unit_index: &UnitIndex) -> Result<(File, HashMap<usize, TypeInfo>), Error> {
+        let header = self
+            .dwarf
+            .debug_info
+            .header_from_offset(gimli::DebugInfoOffset(unit_index.offset))?;
+        let unit = self.dwarf.unit(header)?;
+        let (file, types, _) = self.parse(&unit, unit_index.die_range.0)?;
+        Ok((file, types))
+    }
+
+    /// Loads unit, or the split unit it refers to with its DIE offsets shifted to start at
+    /// split_base. For split units, also returns the length of their .debug_info.
+    fn parse(
+        &self,
+        unit: &gimli::Unit<DwarfReader>,
+        split_base: usize,
+    ) -> Result<ParsedUnit, Error> {
+        let mut types = HashMap::new();
+        let mut compilation_units = Vec::new();
+        let split_len = self
+            .split_dwarf
+            .load_unit(unit, &self.dwarf, split_base, &mut types, &mut compilation_units)?;
+        if split_len.is_none() {
+            load_unit(unit, &self.dwarf, &mut types, &mut compilation_units)?;
+        }
+        let mut file = compilation_units
+            .pop()
+            .ok_or(Error::Gimli(gimli::Error::MissingUnitDie))?;
+        // Split units leave the line number program in the skeleton unit.
+        load_lines(unit, &self.dwarf, &mut file)?;
+        Ok((file, types, split_len))
+    }
+}
+
+/// Loads the functions, variables and types of a compilation unit.
+fn load_unit<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    types: &mut HashMap<usize, TypeInfo>,
+    compilation_units: &mut Vec<File>,
+) -> Result<(), Error> {
+    // Types may be defined after the variables that use them, so collect them first.
+    load_types(unit, dwarf, types)?;
+
+    // Iterate over the Debugging Information Entries (DIEs) in the unit.
+    let mut depth = 0;
+    // Enclosing namespaces and classes, used to build qualified function names
+    let mut scopes: Vec<(isize, String)> = Vec::new();
+    // Member function declarations, by DIE offset. Out-of-line definitions refer to these
+    // with DW_AT_specification and carry neither a name nor the class scope themselves.
+    let mut declarations: HashMap<usize, Function> = HashMap::new();
+    // Depth of the subprogram we are inside of, if any
+    let mut subprogram_depth: Option<isize> = None;
+    // Depths of the inlined subroutines we are inside of, with their index in the function's
+    // inlined calls
+    let mut inlined_depths: Vec<(isize, usize)> = Vec::new();
+    // Inlined calls whose function is named by another DIE, which may come later in the unit:
+    // the index of the inlined call's function, the index of the call and the other DIE's offset
+    let mut inlined_origins: Vec<(usize, usize, usize)> = Vec::new();
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while scopes.last().is_some_and(|(scope_depth, _)| *scope_depth >= depth) {
+            scopes.pop();
+        }
+        if subprogram_depth.is_some_and(|func_depth| func_depth >= depth) {
+            subprogram_depth = None;
+        }
+        while inlined_depths.last().is_some_and(|(inlined_depth, _)| *inlined_depth >= depth) {
+            inlined_depths.pop();
+        }
+        // Update the variable list for formal params/variables
+        match entry.tag() {
+            gimli::DW_TAG_compile_unit => {
+                let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
+                    if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, unit, dwarf) {
+                        name
                     } else {
                         "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
+                    }
+                } else {
+                    "<unknown>".to_string()
+                };
+                compilation_units.push(File {
+                    name,
+                    global_variables: Vec::new(),
+                    functions: Vec::new(),
+                    lines: Vec::new(),
+                });
+            }
+            gimli::DW_TAG_namespace => {
+                let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
+                    if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, unit, dwarf) {
+                        name
                     } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
+                        "(anonymous namespace)".to_string()
+                    }
+                } else {
+                    "(anonymous namespace)".to_string()
+                };
+                scopes.push((depth, name));
+            }
+            gimli::DW_TAG_class_type | gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type => {
+                if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
+                    if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, unit, dwarf) {
+                        scopes.push((depth, name));
+                    }
                 }
-                gimli::DW_TAG_subprogram => {
-                    let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(name)) = val {
-                                    func.name = name;
-                                }
+            }
+            gimli::DW_TAG_subprogram => {
+                let mut func: Function = Default::default();
+                let mut declaration: Option<usize> = None;
+                let mut high_pc_addr: Option<u64> = None;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.name = name;
                             }
-                            gimli::DW_AT_high_pc => {
-                                if let Ok(DebugValue::Uint(high_pc)) = val {
-                                    func.text_length = high_pc.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_linkage_name | gimli::DW_AT_MIPS_linkage_name => {
+                            if let Ok(DebugValue::Str(name)) = val {
+                                func.linkage_name = Some(name);
                             }
-                            gimli::DW_AT_low_pc => {
-                                //println!("low pc {:?}", attr.value());
-                                if let Ok(DebugValue::Uint(low_pc)) = val {
-                                    func.address = low_pc.try_into().unwrap();
+                        }
+                        gimli::DW_AT_high_pc => {
+                            // DWARF 4 and later store the length of the function, while older
+                            // producers store its end address
+                            if let Ok(DebugValue::Uint(high_pc)) = val {
+                                match attr.value() {
+                                    gimli::AttributeValue::Addr(_)
+                                    | gimli::AttributeValue::DebugAddrIndex(_) => {
+                                        high_pc_addr = Some(high_pc);
+                                    }
+                                    _ => func.text_length = high_pc.try_into().unwrap(),
                                 }
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(line_number)) = val {
-                                    func.line_number = line_number.try_into().unwrap();
-                                }
+                        }
+                        gimli::DW_AT_low_pc => {
+                            if let Ok(DebugValue::Uint(low_pc)) = val {
+                                func.address = low_pc.try_into().unwrap();
+                            }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(line_number)) = val {
+                                func.line_number = line_number.try_into().unwrap();
                             }
-                            _ => {}
                         }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                func.return_type = Some(get_type(types, offset));
+                            }
+                        }
+                        gimli::DW_AT_frame_base => {
+                            func.frame_base_register = get_frame_base_register(&attr, unit);
+                        }
+                        gimli::DW_AT_specification | gimli::DW_AT_abstract_origin => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                declaration = Some(offset);
+                            }
+                        }
+                        _ => {}
                     }
-                    compilation_units.last_mut().unwrap().functions.push(func);
-                }
-                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
-                    let mut name = String::new();
-                    let mut entity_type: Option<Type> = None;
-                    let mut location: Option<Location> = None;
-                    let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
-                        let val = get_attr_value(&attr, &unit, &dwarf);
-                        //println!("   {}: {:?}", attr.name(), val);
-                        match attr.name() {
-                            gimli::DW_AT_name => {
-                                if let Ok(DebugValue::Str(attr_name)) = val {
-                                    name = attr_name;
-                                }
+                }
+                if let Some(high_pc) = high_pc_addr {
+                    func.text_length = (high_pc as usize).saturating_sub(func.address);
+                }
+                // Functions split into several pieces (e.g. hot and cold parts) list them in
+                // DW_AT_ranges; treat the function as spanning all of them.
+                if entry.attr_value(gimli::DW_AT_ranges)?.is_some() {
+                    let mut ranges = dwarf.die_ranges(unit, entry)?;
+                    let (mut start, mut end) = (u64::MAX, 0);
+                    while let Some(range) = ranges.next()? {
+                        start = start.min(range.begin);
+                        end = end.max(range.end);
+                    }
+                    if start < end {
+                        func.address = start.try_into().unwrap();
+                        func.text_length = (end - start).try_into().unwrap();
+                    }
+                }
+                func.qualified_name = match func.linkage_name {
+                    Some(ref linkage_name) => demangle_without_params(linkage_name),
+                    None => scopes
+                        .iter()
+                        .map(|(_, scope)| scope.as_str())
+                        .chain(std::iter::once(func.name.as_str()))
+                        .collect::<Vec<_>>()
+                        .join("::"),
+                };
+                if let Some(decl) = declaration.and_then(|offset| declarations.get(&offset)) {
+                    if func.name.is_empty() {
+                        func.name = decl.name.clone();
+                    }
+                    if func.linkage_name.is_none() {
+                        func.linkage_name = decl.linkage_name.clone();
+                    }
+                    func.qualified_name = decl.qualified_name.clone();
+                    if func.return_type.is_none() {
+                        func.return_type = decl.return_type.clone();
+                    }
+                }
+                declarations.insert(section_offset(entry.offset(), unit), func.clone());
+                if subprogram_depth.is_none() {
+                    subprogram_depth = Some(depth);
+                }
+                compilation_units.last_mut().unwrap().functions.push(func);
+            }
+            gimli::DW_TAG_inlined_subroutine if subprogram_depth.is_some() => {
+                let mut call = InlinedCall {
+                    depth: inlined_depths.len(),
+                    ..Default::default()
+                };
+                let mut origin = None;
+                let mut entry_pc = None;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    match (attr.name(), attr.value()) {
+                        (gimli::DW_AT_abstract_origin, _) => {
+                            if let Ok(DebugValue::Size(offset)) = get_attr_value(&attr, unit, dwarf) {
+                                origin = Some(offset);
                             }
-                            gimli::DW_AT_type => {
-                                if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
-                                        entity_type = Some(dtype.clone());
-                                    }
-                                }
+                        }
+                        (gimli::DW_AT_call_file, gimli::AttributeValue::FileIndex(index))
+                        | (gimli::DW_AT_call_file, gimli::AttributeValue::Udata(index)) => {
+                            if let Some(path) = file_index_path(index, unit, dwarf)? {
+                                call.call_file = path;
                             }
-                            gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
-                                    location = Some(loc);
-                                }
+                        }
+                        (gimli::DW_AT_call_line, _) => {
+                            if let Ok(DebugValue::Uint(line)) = get_attr_value(&attr, unit, dwarf) {
+                                call.call_line = line.try_into().unwrap();
+                            }
+                        }
+                        (gimli::DW_AT_entry_pc, value) => entry_pc = Some(value),
+                        _ => {}
+                    }
+                }
+                let mut ranges = dwarf.die_ranges(unit, entry)?;
+                while let Some(range) = ranges.next()? {
+                    if range.begin < range.end {
+                        call.ranges.push((range.begin.try_into().unwrap(), range.end.try_into().unwrap()));
+                    }
+                }
+                call.ranges.sort();
+                let low_pc = call.ranges.first().map_or(0, |range| range.0);
+                // DWARF 5 allows the entry point to be given as an offset from the low address
+                call.entry = match entry_pc {
+                    Some(gimli::AttributeValue::Addr(addr)) => addr.try_into().unwrap(),
+                    Some(gimli::AttributeValue::DebugAddrIndex(index)) => dwarf.address(unit, index)?.try_into().unwrap(),
+                    Some(value) => low_pc + value.udata_value().unwrap_or(0) as usize,
+                    None => low_pc,
+                };
+                let functions = &mut compilation_units.last_mut().unwrap().functions;
+                let func_index = functions.len().wrapping_sub(1);
+                if let Some(func) = functions.last_mut() {
+                    inlined_depths.push((depth, func.inlined.len()));
+                    if let Some(origin) = origin {
+                        inlined_origins.push((func_index, func.inlined.len(), origin));
+                    }
+                    func.inlined.push(call);
+                }
+            }
+            gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                let mut name = String::new();
+                let mut entity_type: Option<Type> = None;
+                let mut location: Option<Location> = None;
+                let mut line_number = 0;
+                let mut origin = None;
+                let mut attrs = entry.attrs();
+                while let Some(attr) = attrs.next()? {
+                    let val = get_attr_value(&attr, unit, dwarf);
+                    match attr.name() {
+                        gimli::DW_AT_name => {
+                            if let Ok(DebugValue::Str(attr_name)) = val {
+                                name = attr_name;
                             }
-                            gimli::DW_AT_decl_line => {
-                                if let Ok(DebugValue::Uint(num)) = val {
-                                    line_number = num;
+                        }
+                        gimli::DW_AT_type => {
+                            if let Ok(DebugValue::Size(offset)) = val {
+                                if types.contains_key(&offset) {
+                                    entity_type = Some(get_type(types, offset));
                                 }
                             }
-                            _ => {}
+                        }
+                        gimli::DW_AT_location => {
+                            if let Some(loc) = get_location(&attr, unit, dwarf) {
+                                location = Some(loc);
+                            }
+                        }
+                        gimli::DW_AT_decl_line => {
+                            if let Ok(DebugValue::Uint(num)) = val {
+                                line_number = num;
+                            }
+                        }
+                        gimli::DW_AT_abstract_origin => {
+                            if let gimli::AttributeValue::UnitRef(offset) = attr.value() {
+                                origin = Some(offset);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                // The variables of inlined copies only have a location, and refer to the
+                // declaration in the function's abstract instance for the rest
+                if let Some(origin) = origin {
+                    let origin = unit.entry(origin)?;
+                    if let Some(attr) = origin.attr(gimli::DW_AT_name)? {
+                        if let Ok(DebugValue::Str(attr_name)) = get_attr_value(&attr, unit, dwarf) {
+                            name = attr_name;
+                        }
+                    }
+                    if let Some(attr) = origin.attr(gimli::DW_AT_type)? {
+                        if let Ok(DebugValue::Size(offset)) = get_attr_value(&attr, unit, dwarf) {
+                            if types.contains_key(&offset) {
+                                entity_type = Some(get_type(types, offset));
+                            }
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
-                        let var = Variable {
-                            name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
-                            line_number: line_number.try_into().unwrap(),
-                        };
-                        if depth == 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .global_variables
-                                .push(var);
-                        } else if depth > 1 {
-                            compilation_units
-                                .last_mut()
-                                .unwrap()
-                                .functions
-                                .last_mut()
-                                .unwrap()
-                                .variables
-                                .push(var);
+                    if let Some(attr) = origin.attr(gimli::DW_AT_decl_line)? {
+                        if let Ok(DebugValue::Uint(num)) = get_attr_value(&attr, unit, dwarf) {
+                            line_number = num;
+                        }
+                    }
+                }
+                let in_subprogram = subprogram_depth.is_some();
+                // The parameters of inlined copies belong to the inlined function
+                if entry.tag() == gimli::DW_TAG_formal_parameter && in_subprogram && inlined_depths.is_empty() {
+                    if let Some(func) = compilation_units.last_mut().unwrap().functions.last_mut() {
+                        func.params.push(entity_type.clone().unwrap_or_default());
+                    }
+                }
+                if let (Some(entity_type), Some(location)) = (entity_type, location) {
+                    let var = Variable {
+                        name,
+                        entity_type,
+                        location,
+                        line_number: line_number.try_into().unwrap(),
+                    };
+                    if !in_subprogram {
+                        compilation_units
+                            .last_mut()
+                            .unwrap()
+                            .global_variables
+                            .push(var);
+                    } else if let Some(func) = compilation_units.last_mut().unwrap().functions.last_mut() {
+                        // The locals of inlined copies are only in scope within the copy's code
+                        match inlined_depths.last() {
+                            Some((_, call_index)) => func.inlined[*call_index].variables.push(var),
+                            None => func.variables.push(var),
                         }
                     }
                 }
-                // NOTE: :You may consider supporting other types by extending this
-                // match statement
-                _ => {}
             }
+            // NOTE: :You may consider supporting other types by extending this
+            // match statement
+            _ => {}
         }
+    }
+    if let Some(file) = compilation_units.last_mut() {
+        for (func_index, call_index, origin) in inlined_origins {
+            if let Some(decl) = declarations.get(&origin) {
+                file.functions[func_index].inlined[call_index].name = decl.qualified_name.clone();
+            }
+        }
+    }
+    Ok(())
+}
 
-        // Get line numbers
-        if let Some(program) = unit.line_program.clone() {
-            // Iterate over the line program rows.
-            let mut rows = program.rows();
-            while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
-                    // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+/// Returns the path of the file a DW_AT_call_file or DW_AT_decl_file attribute refers to.
+fn file_index_path<R: Reader>(index: u64, unit: &gimli::Unit<R>, dwarf: &gimli::Dwarf<R>) -> Result<Option<String>, Error> {
+    let header = match unit.line_program {
+        Some(ref program) => program.header(),
+        None => return Ok(None),
+    };
+    match header.file(index) {
+        Some(file) => Ok(Some(file_path(file, header, unit, dwarf)?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the full path of a file of a line number program. Relative directories (always the
+/// case for DWARF 5's directory 0) are relative to the compilation directory.
+fn file_path<R: Reader>(
+    file: &gimli::FileEntry<R>,
+    header: &gimli::LineProgramHeader<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<String, Error> {
+    let mut path = path::PathBuf::new();
+    if let Some(ref comp_dir) = unit.comp_dir {
+        path.push(comp_dir.to_string_lossy()?.as_ref());
+    }
+    if let Some(dir) = file.directory(header) {
+        path.push(dwarf.attr_string(unit, dir)?.to_string_lossy()?.as_ref());
+    }
+    path.push(dwarf.attr_string(unit, file.path_name())?.to_string_lossy()?.as_ref());
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Records the line number rows of a unit in file.
+fn load_lines<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    file: &mut File,
+) -> Result<(), Error> {
+    if let Some(program) = unit.line_program.clone() {
+        // Iterate over the line program rows.
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if !row.end_sequence() {
+                // Determine the path. Real applications should cache this for performance.
+                let path = match row.file(header) {
+                    Some(row_file) => file_path(row_file, header, unit, dwarf)?,
+                    None => match unit.comp_dir {
+                        Some(ref comp_dir) => comp_dir.to_string_lossy()?.into_owned(),
+                        None => String::new(),
+                    },
+                };
+
+                // Determine line/column. DWARF line/column is never 0, so we use that
+                // but other applications may want to display this differently.
+                let line = row.line().unwrap_or(0);
+
+                // Rows may belong to other source files than the compilation unit's own
+                // (headers, or in Rust, the standard library), so record the row's path.
+                file.lines.push(Line {
+                    file: path,
+                    number: line.try_into().unwrap(),
+                    address: row.address().try_into().unwrap(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loads split units, whose DIEs the compiler left in a .dwo file per object (or a .dwp package
+/// bundling them) and replaced with a skeleton unit in the executable.
+struct SplitDwarf {
+    endian: gimli::RunTimeEndian,
+    path: path::PathBuf,
+    // The executable's .debug_addr, which split units keep their addresses in
+    debug_addr: Rc<[u8]>,
+    package: Option<DwarfPackage>,
+    // Split units that couldn't be found, reported by `DwarfSections::take_warnings`
+    warnings: RefCell<Vec<String>>,
+}
+
+impl SplitDwarf {
+    /// Loads the split unit that skeleton refers to, with its DIE offsets shifted to start at
+    /// base, and returns the length of its .debug_info. Returns None if skeleton is an ordinary
+    /// unit, or if its split unit can't be found.
+    fn load_unit<R: Reader>(
+        &self,
+        skeleton: &gimli::Unit<R>,
+        dwarf: &gimli::Dwarf<R>,
+        base: usize,
+        types: &mut HashMap<usize, TypeInfo>,
+        compilation_units: &mut Vec<File>,
+    ) -> Result<Option<usize>, Error> {
+        let mut dwo_name = None;
+        let mut dwo_id = None;
+        let mut addr_base = None;
+        let mut entries = skeleton.entries();
+        if let Some((_, root)) = entries.next_dfs()? {
+            let mut attrs = root.attrs();
+            while let Some(attr) = attrs.next()? {
+                match (attr.name(), attr.value()) {
+                    (gimli::DW_AT_GNU_dwo_name, _) | (gimli::DW_AT_dwo_name, _) => {
+                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, skeleton, dwarf) {
+                            dwo_name = Some(name);
                         }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
-                        );
                     }
+                    (gimli::DW_AT_GNU_dwo_id, gimli::AttributeValue::Data8(id)) => dwo_id = Some(id),
+                    (_, gimli::AttributeValue::DebugAddrBase(base)) => addr_base = Some(base),
+                    (gimli::DW_AT_GNU_addr_base, gimli::AttributeValue::SecOffset(offset)) => {
+                        addr_base = Some(gimli::DebugAddrBase(offset));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let dwo_name = match dwo_name {
+            Some(dwo_name) => dwo_name,
+            None => return Ok(None),
+        };
+
+        let comp_dir = match skeleton.comp_dir {
+            Some(ref comp_dir) => Some(comp_dir.to_string_lossy()?.into_owned()),
+            None => None,
+        };
+        let mmap = match debug_file::find_dwo_file(&dwo_name, comp_dir.as_deref(), &self.path) {
+            Some(dwo_path) => Some(unsafe { memmap::Mmap::map(&fs::File::open(dwo_path)?)? }),
+            None => None,
+        };
+        let dwo_object = match mmap {
+            Some(ref mmap) => Some(
+                object::File::parse(mmap).map_err(|e| Error::Object(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let package = match (self.package.as_ref(), dwo_id) {
+            (Some(package), Some(dwo_id)) if package.contains(dwo_id) => Some((package, dwo_id)),
+            _ => None,
+        };
+        if dwo_object.is_none() && package.is_none() {
+            self.warnings.borrow_mut().push(format!("Could not find split debugging symbols {}", dwo_name));
+            return Ok(None);
+        }
+
+        let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
+            let data = if id == gimli::SectionId::DebugAddr {
+                Some(borrow::Cow::Borrowed(&self.debug_addr[..]))
+            } else {
+                let name = format!("{}.dwo", id.name());
+                match (&dwo_object, package) {
+                    (Some(dwo_object), _) => debug_file::section_data(dwo_object, &name),
+                    (None, Some((package, dwo_id))) => package.section(dwo_id, &name),
+                    (None, None) => None,
+                }
+            };
+            Ok(data.unwrap_or(borrow::Cow::Borrowed(&[][..])))
+        };
+        let load_section_sup = |_| Ok(borrow::Cow::Borrowed(&[][..]));
+        let dwo_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
+        let endian = self.endian;
+        let borrow_section: &dyn for<'b> Fn(
+            &'b borrow::Cow<[u8]>,
+        ) -> gimli::EndianSlice<'b, gimli::RunTimeEndian> =
+            &|section| gimli::EndianSlice::new(section, endian);
+        let dwo = dwo_cow.borrow(&borrow_section);
+
+        let mut iter = dwo.units();
+        while let Some(header) = iter.next()? {
+            let mut unit = dwo.unit(header)?;
+            if let Some(addr_base) = addr_base {
+                unit.addr_base = addr_base;
+            }
+            if let UnitSectionOffset::DebugInfoOffset(offset) = unit.offset {
+                unit.offset = UnitSectionOffset::DebugInfoOffset(gimli::DebugInfoOffset(offset.0 + base));
+            }
+            load_unit(&unit, &dwo, types, compilation_units)?;
+        }
+        Ok(Some(load_section(gimli::SectionId::DebugInfo)?.len()))
+    }
+}
+
+/// Where a DIE sits relative to the type it contributes to.
+#[derive(Clone, Copy)]
+enum TypeParent {
+    Type(usize),
+    // A variant part of the given struct, and the offset of its discriminant member
+    VariantPart(usize, Option<usize>),
+    // A variant of the given struct, and the discriminant value selecting it
+    Variant(usize, Option<u64>),
+}
+
+/// Collects the layouts of all types in a unit, keyed by the section offset of their DIEs.
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    types: &mut HashMap<usize, TypeInfo>,
+) -> Result<(), Error> {
+    let mut new_types = Vec::new();
+    let mut parents: Vec<(isize, TypeParent)> = Vec::new();
+    // Set from the unit's root DIE, which comes first
+    let mut rust = false;
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        while parents.last().is_some_and(|(parent_depth, _)| *parent_depth >= depth) {
+            parents.pop();
+        }
+        let offset = section_offset(entry.offset(), unit);
+
+        let mut name = String::new();
+        let mut size = None;
+        let mut target = None;
+        let mut member_offset = 0;
+        let mut discr = None;
+        let mut discr_value = None;
+        let mut const_value = 0;
+        let mut count = None;
+        let mut encoding = Encoding::Unsigned;
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            let val = get_attr_value(&attr, unit, dwarf);
+            match (attr.name(), val) {
+                (gimli::DW_AT_name, Ok(DebugValue::Str(attr_name))) => name = attr_name,
+                (gimli::DW_AT_byte_size, Ok(DebugValue::Uint(byte_size))) => {
+                    size = Some(byte_size as usize)
+                }
+                (gimli::DW_AT_type, Ok(DebugValue::Size(type_offset))) => target = Some(type_offset),
+                (gimli::DW_AT_data_member_location, Ok(DebugValue::Uint(location))) => {
+                    member_offset = location as usize
+                }
+                (gimli::DW_AT_data_member_location, _) => {
+                    if let Some(Location::Address(location)) = get_location(&attr, unit, dwarf) {
+                        member_offset = location;
+                    }
+                }
+                (gimli::DW_AT_discr, Ok(DebugValue::Size(discr_offset))) => discr = Some(discr_offset),
+                (gimli::DW_AT_discr_value, Ok(DebugValue::Uint(value))) => discr_value = Some(value),
+                (gimli::DW_AT_discr_value, Ok(DebugValue::Int(value))) => {
+                    discr_value = Some(value as u64)
+                }
+                (gimli::DW_AT_const_value, Ok(DebugValue::Uint(value))) => const_value = value as i64,
+                (gimli::DW_AT_const_value, Ok(DebugValue::Int(value))) => const_value = value,
+                (gimli::DW_AT_count, Ok(DebugValue::Uint(value))) => count = Some(value as usize),
+                (gimli::DW_AT_upper_bound, Ok(DebugValue::Uint(value))) => {
+                    count = Some(value as usize + 1)
+                }
+                (gimli::DW_AT_language, _) => {
+                    rust = matches!(attr.value(), gimli::AttributeValue::Language(gimli::DW_LANG_Rust))
+                }
+                (gimli::DW_AT_encoding, _) => {
+                    encoding = match attr.value() {
+                        gimli::AttributeValue::Encoding(gimli::DW_ATE_signed)
+                        | gimli::AttributeValue::Encoding(gimli::DW_ATE_signed_char)
+                        | gimli::AttributeValue::Encoding(gimli::DW_ATE_signed_fixed) => Encoding::Signed,
+                        gimli::AttributeValue::Encoding(gimli::DW_ATE_float) => Encoding::Float,
+                        _ => Encoding::Unsigned,
+                    }
+                }
+                _ => {}
+            }
+        }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
-
-                    // Determine line/column. DWARF line/column is never 0, so we use that
-                    // but other applications may want to display this differently.
-                    let line = row.line().unwrap_or(0);
-
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line.try_into().unwrap(),
-                            address: row.address().try_into().unwrap(),
-                        });
+        let layout = match entry.tag() {
+            gimli::DW_TAG_base_type | gimli::DW_TAG_unspecified_type => Some(TypeLayout::Base(encoding)),
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => Some(TypeLayout::Pointer(target)),
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type => Some(match target {
+                Some(target) => TypeLayout::Alias(target),
+                None => TypeLayout::Base(Encoding::Unsigned),
+            }),
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+                Some(TypeLayout::Struct(Vec::new()))
+            }
+            gimli::DW_TAG_enumeration_type => Some(TypeLayout::Enumeration(Vec::new())),
+            gimli::DW_TAG_array_type => target.map(|target| TypeLayout::Array(target, 0)),
+            _ => None,
+        };
+        if let Some(layout) = layout {
+            if name.is_empty() && entry.tag() == gimli::DW_TAG_const_type {
+                name = "const".to_string();
+            }
+            types.insert(
+                offset,
+                TypeInfo {
+                    name,
+                    size: size.unwrap_or(0),
+                    layout,
+                    template_params: Vec::new(),
+                    rust,
+                },
+            );
+            new_types.push(offset);
+            parents.push((depth, TypeParent::Type(offset)));
+            continue;
+        }
+
+        let member = Member {
+            name: name.clone(),
+            offset: member_offset,
+            type_offset: target.unwrap_or(0),
+        };
+        match (entry.tag(), parents.last().map(|(_, parent)| *parent)) {
+            (gimli::DW_TAG_member, Some(TypeParent::Type(parent))) => {
+                if let Some(TypeLayout::Struct(members)) = types.get_mut(&parent).map(|t| &mut t.layout) {
+                    members.push(member);
+                }
+            }
+            (gimli::DW_TAG_member, Some(TypeParent::VariantPart(parent, discr)))
+                if discr == Some(offset) => {
+                    if let Some(TypeLayout::Variants { discriminant, .. }) =
+                        types.get_mut(&parent).map(|t| &mut t.layout)
+                    {
+                        *discriminant = Some(member);
                     }
                 }
+            (gimli::DW_TAG_member, Some(TypeParent::Variant(parent, discr_value))) => {
+                if let Some(TypeLayout::Variants { variants, .. }) =
+                    types.get_mut(&parent).map(|t| &mut t.layout)
+                {
+                    variants.push(Variant { discr_value, member });
+                }
+            }
+            (gimli::DW_TAG_variant_part, Some(TypeParent::Type(parent))) => {
+                if let Some(info) = types.get_mut(&parent) {
+                    info.layout = TypeLayout::Variants {
+                        discriminant: None,
+                        variants: Vec::new(),
+                    };
+                }
+                parents.push((depth, TypeParent::VariantPart(parent, discr)));
             }
+            (gimli::DW_TAG_variant, Some(TypeParent::VariantPart(parent, _))) => {
+                parents.push((depth, TypeParent::Variant(parent, discr_value)));
+            }
+            (gimli::DW_TAG_enumerator, Some(TypeParent::Type(parent))) => {
+                if let Some(TypeLayout::Enumeration(values)) =
+                    types.get_mut(&parent).map(|t| &mut t.layout)
+                {
+                    values.push((name, const_value));
+                }
+            }
+            (gimli::DW_TAG_subrange_type, Some(TypeParent::Type(parent))) => {
+                if let Some(TypeLayout::Array(_, len)) = types.get_mut(&parent).map(|t| &mut t.layout) {
+                    *len = count.unwrap_or(0);
+                }
+            }
+            (gimli::DW_TAG_template_type_parameter, Some(TypeParent::Type(parent))) => {
+                if let (Some(info), Some(target)) = (types.get_mut(&parent), target) {
+                    info.template_params.push(target);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Fill in names and sizes that are implied by other types, such as those of pointers.
+    for offset in new_types {
+        let name = type_name(types, offset, 0);
+        let size = type_size(types, offset, 0);
+        let info = types.get_mut(&offset).unwrap();
+        info.name = name;
+        info.size = size;
+    }
+    Ok(())
+}
+
+fn type_name(types: &HashMap<usize, TypeInfo>, offset: usize, depth: usize) -> String {
+    let info = match types.get(&offset) {
+        Some(info) => info,
+        None => return "void".to_string(),
+    };
+    if depth > 8 || (!info.name.is_empty() && info.name != "const") {
+        return info.name.clone();
+    }
+    match info.layout {
+        TypeLayout::Pointer(Some(target)) => format!("{} *", type_name(types, target, depth + 1)),
+        TypeLayout::Pointer(None) => "void *".to_string(),
+        TypeLayout::Alias(target) if info.name == "const" => {
+            format!("const {}", type_name(types, target, depth + 1))
         }
+        TypeLayout::Alias(target) => type_name(types, target, depth + 1),
+        TypeLayout::Array(elem, len) => format!("{}[{}]", type_name(types, elem, depth + 1), len),
+        _ => info.name.clone(),
+    }
+}
+
+fn type_size(types: &HashMap<usize, TypeInfo>, offset: usize, depth: usize) -> usize {
+    let info = match types.get(&offset) {
+        Some(info) => info,
+        None => return 0,
+    };
+    if info.size != 0 || depth > 8 {
+        return info.size;
+    }
+    match info.layout {
+        TypeLayout::Pointer(_) => std::mem::size_of::<usize>(),
+        TypeLayout::Alias(target) => type_size(types, target, depth + 1),
+        TypeLayout::Array(elem, len) => type_size(types, elem, depth + 1) * len,
+        _ => 0,
+    }
+}
+
+/// Returns the Type for the type DIE at offset. Types we don't know about are given an empty
+/// name, so they can still be told apart from void.
+fn get_type(types: &HashMap<usize, TypeInfo>, offset: usize) -> Type {
+    match types.get(&offset) {
+        Some(info) => Type {
+            name: info.name.clone(),
+            size: info.size,
+            offset,
+        },
+        None => Default::default(),
+    }
+}
+
+fn section_offset<R: Reader>(offset: UnitOffset, unit: &gimli::Unit<R>) -> usize {
+    match offset.to_unit_section_offset(unit) {
+        UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+        UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
     }
-    Ok(compilation_units)
 }
 
 #[derive(Debug, Clone)]
@@ -254,44 +1019,43 @@ pub enum DebugValue {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
-    GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
-    ObjectError(String),
-    IoError,
+    Gimli(gimli::Error),
+    Object(String),
+    Io,
 }
 
 impl From<gimli::Error> for Error {
     fn from(err: gimli::Error) -> Self {
-        Error::GimliError(err)
-    }
-}
-
-impl From<addr2line::gimli::Error> for Error {
-    fn from(err: addr2line::gimli::Error) -> Self {
-        Error::Addr2lineError(err)
+        Error::Gimli(err)
     }
 }
 
 impl From<io::Error> for Error {
     fn from(_: io::Error) -> Self {
-        Error::IoError
+        Error::Io
     }
 }
 
 impl From<std::fmt::Error> for Error {
     fn from(_: std::fmt::Error) -> Self {
-        Error::IoError
+        Error::Io
     }
 }
 
 impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
-    Endian: gimli::Endianity + Send + Sync
+    Endian: gimli::Endianity
 {
 }
 
-trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
+impl<Endian> Reader for gimli::EndianRcSlice<Endian> where Endian: gimli::Endianity {}
+
+trait Reader: gimli::Reader<Offset = usize> {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<Location> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
         let encoding = unit.encoding();
         let mut pc = data.0.clone();
@@ -304,6 +1068,13 @@ fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) ->
                     gimli::Operation::Address { address } => {
                         return Some(Location::Address(address.try_into().unwrap()));
                     }
+                    gimli::Operation::PlusConstant { value } => {
+                        return Some(Location::Address(value.try_into().unwrap()));
+                    }
+                    gimli::Operation::AddressIndex { index } => {
+                        let address = dwarf.address(unit, index).ok()?;
+                        return Some(Location::Address(address.try_into().unwrap()));
+                    }
                     _ => {}
                 }
             }
@@ -312,6 +1083,23 @@ fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) ->
     None
 }
 
+/// Returns the register a DW_AT_frame_base expression names, or None if the frame base is the
+/// canonical frame address (DW_OP_call_frame_cfa) or something we don't understand.
+fn get_frame_base_register<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+) -> Option<u16> {
+    if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
+        let mut pc = data.0.clone();
+        if let Ok(gimli::Operation::Register { register }) =
+            gimli::Operation::parse(&mut pc, unit.encoding())
+        {
+            return Some(register.0);
+        }
+    }
+    None
+}
+
 // based on dwarf_dump.rs
 fn get_attr_value<R: Reader>(
     attr: &gimli::Attribute<R>,
@@ -344,9 +1132,23 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        // DWARF 5 (and split DWARF) strings and addresses held in other sections
+        gimli::AttributeValue::DebugLineStrRef(_)
+        | gimli::AttributeValue::DebugStrOffsetsIndex(_)
+        | gimli::AttributeValue::DebugStrRefSup(_) => {
+            let s = dwarf.attr_string(unit, value.clone())?;
+            Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+        }
+        gimli::AttributeValue::DebugAddrIndex(index) => {
+            Ok(DebugValue::Uint(dwarf.address(unit, index)?))
+        }
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
+        gimli::AttributeValue::Data1(data) => Ok(DebugValue::Uint(data.into())),
+        gimli::AttributeValue::Data2(data) => Ok(DebugValue::Uint(data.into())),
+        gimli::AttributeValue::Data4(data) => Ok(DebugValue::Uint(data.into())),
+        gimli::AttributeValue::Data8(data) => Ok(DebugValue::Uint(data)),
 
         gimli::AttributeValue::String(s) => {
             Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
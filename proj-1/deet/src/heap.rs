@@ -0,0 +1,315 @@
+//! `deet heap`, a leak checker that needs neither rebuilding nor preloading the program.
+//! Breakpoints on the entry points of the C allocator record every block the program allocates
+//! and frees, along with the call stack that did it. Frees of blocks that were freed already are
+//! reported as they happen, and the blocks still allocated when the program exits are reported
+//! grouped by the stack that allocated them.
+//!
+//! The allocator usually lives in libc, which is only loaded once the program has started, so
+//! tracking starts at the program's entry point, once the dynamic linker has loaded the libraries
+//! and before the constructors that run ahead of main. Blocks the dynamic linker and libc
+//! allocate while starting up aren't seen, so frees of blocks that were never seen are only
+//! counted: they can't be told apart from frees of blocks that were never allocated.
+
+use deet::arch::{Arch, Native};
+use deet::dwarf_data::DwarfData;
+use deet::inferior::{describe_frames, Inferior, LaunchOptions, Redirections, Status};
+use deet::shared_library::{self, SharedLibrary};
+use nix::sys::signal::Signal;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq)]
+enum AllocatorFunction {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+const ALLOCATOR_FUNCTIONS: [(&str, AllocatorFunction); 4] = [
+    ("malloc", AllocatorFunction::Malloc),
+    ("calloc", AllocatorFunction::Calloc),
+    ("realloc", AllocatorFunction::Realloc),
+    ("free", AllocatorFunction::Free),
+];
+
+impl AllocatorFunction {
+    fn name(self) -> &'static str {
+        ALLOCATOR_FUNCTIONS.iter().find(|(_, function)| *function == self).unwrap().0
+    }
+}
+
+struct Block {
+    size: usize,
+    stack: Vec<usize>, // Return addresses of the allocating call, innermost first
+}
+
+/// A call into the allocator that hasn't returned yet. A temporary breakpoint at its return
+/// address catches the pointer it returns.
+struct PendingCall {
+    function: AllocatorFunction,
+    size: usize,
+    old_ptr: usize, // Block being resized by realloc
+    stack: Vec<usize>,
+    return_address: usize,
+    stack_pointer: usize, // Stack pointer once the call has returned
+}
+
+struct HeapTracker<'a> {
+    debug_data: &'a DwarfData,
+    libraries: Vec<SharedLibrary>,
+    live: HashMap<usize, Block>,
+    freed: HashMap<usize, Vec<usize>>, // Freed blocks, with the stack that freed them
+    allocations: usize,
+    frees: usize,
+    bytes_allocated: usize,
+    double_frees: usize,
+    unknown_frees: usize, // Of blocks allocated before tracking started, or never allocated
+}
+
+impl HeapTracker<'_> {
+    /// Describes the frames of a stack recorded at an allocator call. Frames in libraries are
+    /// described by the function they're in.
+    fn describe_stack(&self, stack: &[usize]) -> Vec<String> {
+        // Return addresses may belong to the line after the call, so look up the call itself
+        let calls: Vec<usize> = stack.iter().map(|addr| addr - 1).collect();
+        describe_frames(self.debug_data, &calls, |addr| match shared_library::library_at(&self.libraries, addr) {
+            Some(library) => library.describe_addr(addr),
+            None => format!("{:#x}", addr),
+        })
+    }
+
+    fn print_stack(&self, stack: &[usize]) {
+        for frame in self.describe_stack(stack) {
+            println!("    {}", frame);
+        }
+    }
+
+    fn allocated(&mut self, ptr: usize, size: usize, stack: Vec<usize>) {
+        self.freed.remove(&ptr);
+        self.live.insert(ptr, Block { size, stack });
+        self.allocations += 1;
+        self.bytes_allocated += size;
+    }
+
+    /// Records ptr being freed, which `check_free` allowed. Blocks that weren't seen being
+    /// allocated are remembered too, so that freeing them again is caught.
+    fn freed(&mut self, ptr: usize, stack: Vec<usize>) {
+        if self.live.remove(&ptr).is_some() {
+            self.frees += 1;
+        } else {
+            self.unknown_frees += 1;
+        }
+        self.freed.insert(ptr, stack);
+    }
+
+    /// Reports function being called to free or resize ptr if the block was freed already.
+    /// Returns whether it may be freed: it's a live block, or one that wasn't seen.
+    fn check_free(&mut self, function: &str, ptr: usize, stack: &[usize]) -> bool {
+        let free_stack = match self.freed.get(&ptr) {
+            Some(free_stack) if !self.live.contains_key(&ptr) => free_stack,
+            _ => return true,
+        };
+        self.double_frees += 1;
+        println!("Double free: {} of {:#x} at", function, ptr);
+        self.print_stack(stack);
+        println!("  which was already freed at");
+        self.print_stack(free_stack);
+        false
+    }
+
+    fn print_leaks(&self) {
+        println!(
+            "{} allocations, {} frees, {} bytes allocated",
+            self.allocations, self.frees, self.bytes_allocated
+        );
+        if self.double_frees > 0 {
+            println!("{} double frees", self.double_frees);
+        }
+        if self.unknown_frees > 0 {
+            println!("{} frees of blocks allocated before tracking started", self.unknown_frees);
+        }
+        if self.live.is_empty() {
+            println!("All heap blocks were freed, no leaks are possible");
+            return;
+        }
+        let mut by_stack: HashMap<&[usize], (usize, usize)> = HashMap::new();
+        for block in self.live.values() {
+            let (bytes, blocks) = by_stack.entry(&block.stack).or_insert((0, 0));
+            *bytes += block.size;
+            *blocks += 1;
+        }
+        let mut leaks: Vec<(&[usize], (usize, usize))> = by_stack.into_iter().collect();
+        leaks.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then(a.0.cmp(b.0)));
+        for (stack, (bytes, blocks)) in &leaks {
+            println!("{} bytes in {} block{} leaked, allocated at", bytes, blocks, plural(*blocks));
+            self.print_stack(stack);
+        }
+        let total: usize = self.live.values().map(|block| block.size).sum();
+        println!(
+            "Leaked {} bytes in {} block{} from {} stack{}",
+            total,
+            self.live.len(),
+            plural(self.live.len()),
+            leaks.len(),
+            plural(leaks.len())
+        );
+    }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+/// Continues the program until it stops at a breakpoint or exits, passing on the signals it gets
+/// on the way.
+fn continue_to_breakpoint(inferior: &mut Inferior, break_points: &HashMap<usize, Vec<u8>>) -> Result<Status, String> {
+    let mut status = inferior
+        .continue_run(break_points)
+        .map_err(|err| format!("Could not resume the program: {}", err))?;
+    loop {
+        match status {
+            Status::Stopped(Signal::SIGTRAP, pc, true) if break_points.contains_key(&Native::breakpoint_at(pc)) => return Ok(status),
+            Status::Stopped(other, ..) => {
+                inferior.resume(Some(other)).map_err(|err| format!("Could not resume the program: {}", err))?;
+                status = inferior.wait(None).map_err(|err| format!("Could not wait for the program: {}", err))?;
+            }
+            status => return Ok(status),
+        }
+    }
+}
+
+/// Runs target with args to completion, tracking the blocks it allocates, and reports the ones it
+/// leaks.
+pub fn heap(target: &str, args: &[String]) -> Result<(), String> {
+    let debug_data = DwarfData::from_file(target).map_err(|err| format!("Could not load {}: {:?}", target, err))?;
+    let (args, redirections) = Redirections::parse(args)?;
+    let entry_point = debug_data.entry_point();
+
+    let mut break_points: HashMap<usize, Vec<u8>> = HashMap::new();
+    break_points.insert(entry_point, Vec::new());
+    let mut inferior = Inferior::new(target, &args, &LaunchOptions::default(), &redirections).map_err(|err| err.to_string())?;
+    if !inferior.insert_breakpoints(&mut break_points).is_empty() {
+        return Err("Could not set a breakpoint at the entry point".to_string());
+    }
+    let mut status = continue_to_breakpoint(&mut inferior, &break_points)?;
+    if let Status::Stopped(..) = status {
+        let orig_bytes = break_points.remove(&entry_point).unwrap();
+        inferior
+            .remove_breakpoint(entry_point, &orig_bytes)
+            .map_err(|err| format!("Could not remove the breakpoint at the entry point: {}", err))?;
+    }
+
+    let mut tracker = HeapTracker {
+        debug_data: &debug_data,
+        libraries: shared_library::loaded_libraries(inferior.pid()).unwrap_or_default(),
+        live: HashMap::new(),
+        freed: HashMap::new(),
+        allocations: 0,
+        frees: 0,
+        bytes_allocated: 0,
+        double_frees: 0,
+        unknown_frees: 0,
+    };
+    // A statically linked program has its own allocator; otherwise it's in one of the libraries
+    let executable = SharedLibrary::load(target, 0, 0);
+    let mut functions: HashMap<usize, AllocatorFunction> = HashMap::new();
+    if let Status::Stopped(..) = status {
+        for (name, function) in &ALLOCATOR_FUNCTIONS {
+            let addr = executable
+                .as_ref()
+                .and_then(|executable| executable.get_symbol_addr(name))
+                .or_else(|| tracker.libraries.iter().find_map(|library| library.get_symbol_addr(name)))
+                .ok_or_else(|| format!("Could not find {} in the program or its libraries", name))?;
+            let orig_bytes = inferior
+                .insert_breakpoint(addr)
+                .map_err(|err| format!("Could not set a breakpoint on {}: {}", name, err))?;
+            break_points.insert(addr, orig_bytes);
+            functions.insert(addr, *function);
+        }
+        status = continue_to_breakpoint(&mut inferior, &break_points)?;
+    }
+
+    // Calls that haven't returned, innermost last. More than one is pending when a call is made
+    // while another is under way, as from a signal handler.
+    let mut pending: Vec<PendingCall> = Vec::new();
+    while let Status::Stopped(_, pc, _) = status {
+        let addr = Native::breakpoint_at(pc);
+        let regs = inferior.registers().map_err(|err| format!("Could not read the registers: {}", err))?;
+        let stack_pointer = Native::stack_pointer(&regs);
+        if let Some(index) =
+            pending.iter().rposition(|call| call.return_address == addr && call.stack_pointer == stack_pointer)
+        {
+            // Calls made from inside this one that are still pending were left by a longjmp
+            let call = pending.drain(index..).next().unwrap();
+            let ptr = Native::return_value(&regs) as usize;
+            if call.function == AllocatorFunction::Realloc && call.old_ptr != 0 && (ptr != 0 || call.size == 0) {
+                tracker.freed(call.old_ptr, call.stack.clone());
+            }
+            if ptr != 0 {
+                tracker.allocated(ptr, call.size, call.stack);
+            }
+        } else if let Some(function) = functions.get(&addr) {
+            let stack = inferior
+                .caller_stack_addresses(&debug_data)
+                .map_err(|err| format!("Could not read the stack: {}", err))?;
+            let caller_stack_pointer = stack_pointer + Native::RETURN_POPS;
+            // Calls whose frames are gone were left by a longjmp
+            pending.retain(|call| call.stack_pointer > caller_stack_pointer);
+            // The allocator calling itself, as realloc does, is part of the outer call. Calls from
+            // the program's own code, as from a signal handler, are calls of their own.
+            let internal = !pending.is_empty() && debug_data.get_function_from_addr(stack[0]).is_none();
+            if !internal {
+                let (arg1, arg2) = (Native::int_arg(&regs, 0) as usize, Native::int_arg(&regs, 1) as usize);
+                let (size, old_ptr) = match function {
+                    AllocatorFunction::Malloc => (arg1, 0),
+                    AllocatorFunction::Calloc => (arg1.saturating_mul(arg2), 0),
+                    AllocatorFunction::Realloc => (arg2, arg1),
+                    AllocatorFunction::Free => (0, arg1),
+                };
+                let valid = old_ptr == 0 || tracker.check_free(function.name(), old_ptr, &stack);
+                if *function == AllocatorFunction::Free {
+                    if valid && old_ptr != 0 {
+                        tracker.freed(old_ptr, stack);
+                    }
+                } else {
+                    let return_address = stack[0];
+                    if let Entry::Vacant(entry) = break_points.entry(return_address) {
+                        let orig_bytes = inferior
+                            .insert_breakpoint(return_address)
+                            .map_err(|err| format!("Could not set a breakpoint at {:#x}: {}", return_address, err))?;
+                        entry.insert(orig_bytes);
+                    }
+                    pending.push(PendingCall {
+                        function: *function,
+                        size,
+                        old_ptr: if valid { old_ptr } else { 0 },
+                        stack,
+                        return_address,
+                        stack_pointer: caller_stack_pointer,
+                    });
+                }
+            }
+        }
+        // Temporary breakpoints go once no pending call returns to them
+        let unused: Vec<usize> = break_points
+            .keys()
+            .filter(|addr| !functions.contains_key(addr) && !pending.iter().any(|call| call.return_address == **addr))
+            .copied()
+            .collect();
+        for addr in unused {
+            let orig_bytes = break_points.remove(&addr).unwrap();
+            inferior
+                .remove_breakpoint(addr, &orig_bytes)
+                .map_err(|err| format!("Could not remove the breakpoint at {:#x}: {}", addr, err))?;
+        }
+        status = continue_to_breakpoint(&mut inferior, &break_points)?;
+    }
+    match status {
+        Status::Exited(exit_code) => println!("Child exited (status {})", exit_code),
+        Status::Signaled(signal) => println!("Child exited due to signal {}", signal),
+        Status::Stopped(..) | Status::Event(..) => {}
+    }
+    tracker.print_leaks();
+    Ok(())
+}
@@ -2,17 +2,25 @@ use nix::sys::ptrace;
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
 use std::os::unix::process::CommandExt;
-use std::process::Child;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use crate::arch::{Arch, FpRegisters, Native, Registers};
 use crate::dwarf_data::DwarfData;
+use crate::error::DeetError;
+use crate::record::{self, Entry, Recording};
+use crate::shared_library::ModuleCache;
+use crate::unwind::SavedRegister;
 use std::mem::size_of;
 
 pub enum Status {
-    /// Indicates inferior stopped. Contains the signal that stopped the process, as well as the
-    /// current instruction pointer that it is stopped at.
-    Stopped(signal::Signal, usize),
+    /// Indicates inferior stopped. Contains the signal that stopped the process, the current
+    /// instruction pointer that it is stopped at, and whether it stopped on a breakpoint, with
+    /// the instruction pointer where the breakpoint's trap leaves it.
+    Stopped(signal::Signal, usize, bool),
 
     /// Indicates inferior exited normally. Contains the exit status code.
     Exited(i32),
@@ -20,114 +28,1108 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior stopped at an event `set_ptrace_options` asked for. Contains the
+    /// event and the current instruction pointer.
+    Event(Event, usize),
+}
+
+/// Events of the inferior that ptrace can stop it at, besides signals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The inferior forked the process with the given pid, which is stopped
+    Fork(Pid),
+    /// The inferior vforked the process with the given pid, which is stopped and shares its memory
+    Vfork(Pid),
+    /// The inferior replaced its program with another
+    Exec,
+    /// The inferior is about to exit with the status, and its memory can still be read
+    Exit(i32),
+}
+
+/// Outcome of running a function inside the inferior with Inferior::call_function.
+pub enum CallResult {
+    /// The function returned. Contains the integer return register and the raw low half of the
+    /// floating point return register.
+    Returned(u64, u64),
+
+    /// The function stopped before returning (at a breakpoint or due to a signal). If the
+    /// inferior is still alive, its original registers have been restored.
+    Interrupted(Status),
+}
+
+/// Frames walked at most, in case saved frame pointers form a loop
+const MAX_FRAMES: usize = 1024;
+
+/// Returns the raw bytes of the floating point registers, for comparing them.
+fn fpregs_bytes(fpregs: &FpRegisters) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(fpregs as *const FpRegisters as *const u8, size_of::<FpRegisters>()) }
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
+    ptrace::traceme().or(Err(std::io::Error::other(
         "ptrace TRACEME failed",
     )))
 }
 
+/// Where the inferior runs: settings that persist across runs, changed with `set env`,
+/// `unset env`, `set cwd` and `set inferior-tty`.
+#[derive(Debug, Clone, Default)]
+pub struct LaunchOptions {
+    /// Changes to the debugger's environment, by name. None means the variable is removed.
+    pub env: HashMap<String, Option<String>>,
+    pub cwd: Option<String>,
+    /// Terminal for the inferior's standard streams, so its output doesn't mix with ours
+    pub tty: Option<String>,
+}
+
+/// Where an output stream of the inferior goes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Truncate(String),
+    Append(String),
+    /// The file stdout is redirected to, for `2>&1`
+    Stdout,
+}
+
+/// Redirections of the inferior's standard streams given to a single `run`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redirections {
+    pub stdin: Option<String>,
+    pub stdout: Option<Output>,
+    pub stderr: Option<Output>,
+}
+
+impl Redirections {
+    /// Splits the shell-style redirections (`< in`, `> out`, `>> out`, `2> err`, `2>&1`,
+    /// `&> out`, `&>> out`) out of a run command's arguments. As in a shell, they apply from
+    /// left to right, so `2>&1 > out` leaves stderr on the terminal.
+    pub fn parse(args: &[String]) -> Result<(Vec<String>, Redirections), String> {
+        let mut redirections = Redirections::default();
+        let mut remaining = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "2>&1" {
+                // Stderr goes where stdout goes now, which is the terminal unless redirected
+                redirections.stderr = redirections.stdout.as_ref().map(|_| Output::Stdout);
+                continue;
+            }
+            let operator = ["2>>", "2>", "&>>", "&>", ">>", ">", "<"].iter().find(|op| arg.starts_with(*op));
+            let operator = match operator {
+                Some(operator) => *operator,
+                None => {
+                    remaining.push(arg.clone());
+                    continue;
+                }
+            };
+            // The file name may be attached (`>out`) or the next argument (`> out`)
+            let file = match &arg[operator.len()..] {
+                "" => iter.next().ok_or(format!("Missing file name after {}", operator))?.clone(),
+                file => file.to_string(),
+            };
+            match operator {
+                "<" => redirections.stdin = Some(file),
+                ">" => redirections.redirect_stdout(Output::Truncate(file)),
+                ">>" => redirections.redirect_stdout(Output::Append(file)),
+                "2>" => redirections.stderr = Some(Output::Truncate(file)),
+                "2>>" => redirections.stderr = Some(Output::Append(file)),
+                "&>" => {
+                    redirections.stdout = Some(Output::Truncate(file));
+                    redirections.stderr = Some(Output::Stdout);
+                }
+                _ => {
+                    redirections.stdout = Some(Output::Append(file));
+                    redirections.stderr = Some(Output::Stdout);
+                }
+            }
+        }
+        Ok((remaining, redirections))
+    }
+
+    /// Redirects stdout, leaving a stderr that was sent to stdout's old file with that file.
+    fn redirect_stdout(&mut self, output: Output) {
+        let previous = self.stdout.replace(output);
+        if self.stderr == Some(Output::Stdout) {
+            self.stderr = previous;
+        }
+    }
+}
+
+/// Opens the file an output stream is redirected to. Relative paths are taken from the
+/// inferior's working directory, as a shell started there would.
+fn open_output(output: &Output, cwd: &Path) -> Result<File, std::io::Error> {
+    match output {
+        Output::Truncate(file) => File::create(cwd.join(file)),
+        Output::Append(file) => OpenOptions::new().append(true).create(true).open(cwd.join(file)),
+        Output::Stdout => unreachable!(),
+    }
+}
+
+/// Sets up the standard streams of cmd according to the redirections, sending the streams that
+/// aren't redirected to the inferior's terminal if one was set.
+fn redirect(cmd: &mut Command, options: &LaunchOptions, redirections: &Redirections) -> Result<(), std::io::Error> {
+    let cwd = options.cwd.as_ref().map_or(PathBuf::from("."), PathBuf::from);
+    let tty = match &options.tty {
+        Some(tty) => Some(OpenOptions::new().read(true).write(true).open(tty)?),
+        None => None,
+    };
+    let stdin = match &redirections.stdin {
+        Some(file) => Some(File::open(cwd.join(file))?),
+        None => tty.as_ref().map(File::try_clone).transpose()?,
+    };
+    let stdout = match &redirections.stdout {
+        Some(output) => Some(open_output(output, &cwd)?),
+        None => tty.as_ref().map(File::try_clone).transpose()?,
+    };
+    let stderr = match &redirections.stderr {
+        Some(Output::Stdout) => stdout.as_ref().map(File::try_clone).transpose()?,
+        Some(output) => Some(open_output(output, &cwd)?),
+        None => tty.as_ref().map(File::try_clone).transpose()?,
+    };
+    if let Some(file) = stdin {
+        cmd.stdin(Stdio::from(file));
+    }
+    if let Some(file) = stdout {
+        cmd.stdout(Stdio::from(file));
+    }
+    if let Some(file) = stderr {
+        cmd.stderr(Stdio::from(file));
+    }
+    Ok(())
+}
+
+/// Makes the terminal on the child's stdin its controlling terminal, in a session of its own, so
+/// that job control and ^C on that terminal reach the inferior rather than us.
+fn child_take_tty() -> Result<(), std::io::Error> {
+    nix::unistd::setsid().or(Err(std::io::Error::other("setsid failed")))?;
+    if unsafe { libc::ioctl(0, libc::TIOCSCTTY, 0) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A copy of the inferior saved by `checkpoint`, kept stopped so that it can be restarted.
+pub struct Checkpoint {
+    pub id: usize,
+    pub pid: Pid,
+    pub pc: usize,
+    /// Breakpoints that were inserted in its memory when it was saved, with their original bytes
+    pub break_points: HashMap<usize, Vec<u8>>,
+    /// Whether it was saved stopped on a breakpoint
+    pub trapped: bool,
+}
+
+impl Checkpoint {
+    /// Returns the address of the instruction the checkpoint is at, which is the breakpoint it
+    /// was stopped on if any.
+    pub fn stop_address(&self) -> usize {
+        let addr = Native::breakpoint_at(self.pc);
+        if self.trapped && self.break_points.contains_key(&addr) {
+            addr
+        } else {
+            self.pc
+        }
+    }
+}
+
 pub struct Inferior {
-    child: Child,
+    /// The process being debugged. This is the child we started until a checkpoint is restarted,
+    /// which kills and reaps the process it replaces, so no pid is signalled once it is reaped.
+    pid: Pid,
+    checkpoints: Vec<Checkpoint>,
+    next_checkpoint_id: usize,
+    /// Log of the instructions executed since `record`, if recording
+    recording: Option<Recording>,
+    /// Events to stop at, which copies made by restarting a checkpoint are given too
+    ptrace_options: ptrace::Options,
+    /// Signal to deliver when the inferior next resumes
+    pending_signal: Option<signal::Signal>,
+    /// Whether the process is stopped on a breakpoint, with the program counter where its trap
+    /// leaves it. Moving the program counter clears it.
+    trapped: Cell<bool>,
+    /// The process's memory map and the files in it, read again after each stop
+    modules: ModuleCache,
+}
+
+/// Returns whether the SIGTRAP the process with the given pid stopped with was raised by a
+/// breakpoint instruction, rather than by single-stepping or sent by a process.
+fn breakpoint_trap(pid: Pid) -> bool {
+    // x86-64 reports its breakpoint instruction as coming from the kernel
+    ptrace::getsiginfo(pid).is_ok_and(|info| info.si_code == libc::SI_KERNEL || info.si_code == libc::TRAP_BRKPT)
 }
 
 impl Inferior {
-    /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
-    /// an error is encountered.
-    pub fn new(target: &str, args: &Vec<String>, break_points: &mut HashMap<usize, u8>) -> Option<Inferior> {
+    /// Starts a new inferior process, stopped before its first instruction.
+    pub fn new(
+        target: &str,
+        args: &Vec<String>,
+        options: &LaunchOptions,
+        redirections: &Redirections,
+    ) -> Result<Inferior, DeetError> {
+        // A relative target path would otherwise be looked up in the new working directory
+        let target = std::fs::canonicalize(target).unwrap_or_else(|_| PathBuf::from(target));
         let mut cmd = Command::new(target);
         cmd.args(args);
+        for (name, value) in &options.env {
+            match value {
+                Some(value) => cmd.env(name, value),
+                None => cmd.env_remove(name),
+            };
+        }
+        if let Some(cwd) = &options.cwd {
+            cmd.current_dir(cwd);
+        }
+        redirect(&mut cmd, options, redirections)
+            .map_err(|err| DeetError::Input(format!("Could not set up the program's input and output: {}", err)))?;
+        if options.tty.is_some() {
+            unsafe { cmd.pre_exec(child_take_tty); }
+        }
         unsafe { cmd.pre_exec(child_traceme); }
-        let child = cmd.spawn().ok()?;
-        let mut inferior = Inferior {child: child};
-        if inferior.wait(None).is_ok() {
-            for (addr, ob) in break_points {
-                match inferior.write_byte(*addr, 0xcc) {
-                    Ok(orig_byte) => { *ob = orig_byte }
-                    Err(_) => { println!("Invalid breakpoint address {:#x}", addr) }
-                }
+        let child = cmd
+            .spawn()
+            .map_err(|err| DeetError::Input(format!("Error starting subprocess: {}", err)))?;
+        // The child is reaped with waitpid like any other process we trace, so its handle isn't kept
+        let pid = Pid::from_raw(child.id() as i32);
+        let inferior = Inferior {
+            pid,
+            checkpoints: Vec::new(),
+            next_checkpoint_id: 1,
+            recording: None,
+            ptrace_options: ptrace::Options::empty(),
+            pending_signal: None,
+            trapped: Cell::new(false),
+            modules: ModuleCache::default(),
+        };
+        inferior.wait(None)?;
+        Ok(inferior)
+    }
+
+    /// Writes the breakpoints into the process, keeping the bytes each replaced. Returns the
+    /// addresses that could not be written, in order.
+    pub fn insert_breakpoints(&mut self, break_points: &mut HashMap<usize, Vec<u8>>) -> Vec<usize> {
+        let mut invalid = Vec::new();
+        for (addr, orig_bytes) in break_points.iter_mut() {
+            match self.insert_breakpoint(*addr) {
+                Ok(bytes) => *orig_bytes = bytes,
+                Err(_) => invalid.push(*addr),
             }
         }
-        Some(inferior)
+        invalid.sort_unstable();
+        invalid
     }
 
     /// Returns the pid of this inferior.
     pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+        self.pid
+    }
+
+    /// Sets the events of the process, such as forks and exits, that it stops at, which
+    /// `wait` reports as `Status::Event`.
+    pub fn set_ptrace_options(&mut self, options: ptrace::Options) -> Result<(), nix::Error> {
+        self.ptrace_options = options;
+        ptrace::setoptions(self.pid(), options)
+    }
+
+    /// Has the inferior receive signal when it next resumes, rather than it being discarded as
+    /// the signals it stops with are.
+    pub fn deliver_signal(&mut self, signal: signal::Signal) {
+        self.pending_signal = Some(signal);
     }
 
     /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
     /// after the waitpid call.
     pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
+        let wait_status = waitpid(self.pid(), options)?;
+        if wait_status != WaitStatus::StillAlive {
+            self.trapped.set(false);
+            self.modules.invalidate();
+        }
+        Ok(match wait_status {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
             WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
-                Status::Stopped(signal, regs.rip as usize)
+                let trapped = signal == signal::Signal::SIGTRAP && breakpoint_trap(self.pid());
+                self.trapped.set(trapped);
+                Status::Stopped(signal, self.pc()?, trapped)
+            }
+            WaitStatus::PtraceEvent(_pid, _signal, event) => {
+                let message = ptrace::getevent(self.pid())?;
+                let event = match event {
+                    libc::PTRACE_EVENT_FORK => Event::Fork(Pid::from_raw(message as i32)),
+                    libc::PTRACE_EVENT_VFORK => Event::Vfork(Pid::from_raw(message as i32)),
+                    libc::PTRACE_EVENT_EXEC => Event::Exec,
+                    // The message is the wait status it exits with, which holds the exit code in
+                    // its second byte when its low bits are clear
+                    libc::PTRACE_EVENT_EXIT if message & 0x7f == 0 => Event::Exit(((message >> 8) & 0xff) as i32),
+                    // Processes killed by signals are reported once they're gone, as without
+                    // stopping at exits
+                    libc::PTRACE_EVENT_EXIT => {
+                        ptrace::cont(self.pid(), None)?;
+                        return self.wait(options);
+                    }
+                    _ => return Err(nix::Error::UnsupportedOperation),
+                };
+                Status::Event(event, self.pc()?)
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            // Stops deet doesn't ask for, such as system call stops, aren't expected
+            _ => return Err(nix::Error::UnsupportedOperation),
         })
     }
 
-    pub fn continue_run(&mut self, breakpoints: &HashMap<usize, u8>) -> Result<Status, nix::Error> {
-        let mut regs = ptrace::getregs(self.pid())?;
-        let rip = regs.rip as usize;
-        if let Some(orig_byte) = breakpoints.get(&(rip - 1)) {
-            self.write_byte(rip - 1, *orig_byte).unwrap();
-            regs.rip = (rip - 1) as u64;
-            ptrace::setregs(self.pid(), regs).unwrap();
-            ptrace::step(self.pid(), None).unwrap();
-            match self.wait(None).unwrap() {
+    /// Lets go of a process the inferior forked, once it has stopped, after taking out the
+    /// breakpoints it inherited so that it runs on its own. The child of a vfork shares the
+    /// inferior's memory, so the breakpoints are left in; it runs little more than an exec.
+    pub fn detach_child(&self, child: Pid, breakpoints: &HashMap<usize, Vec<u8>>, shares_memory: bool) -> Result<(), nix::Error> {
+        waitpid(child, None)?;
+        if !shares_memory {
+            for (addr, orig_bytes) in breakpoints {
+                write_bytes_in(child, *addr, orig_bytes)?;
+            }
+        }
+        ptrace::detach(child, None)
+    }
+
+    pub fn continue_run(&mut self, breakpoints: &HashMap<usize, Vec<u8>>) -> Result<Status, DeetError> {
+        if self.recording.is_some() {
+            return self.continue_recording(breakpoints);
+        }
+        if let Some(addr) = self.breakpoint_hit(breakpoints)? {
+            let orig_bytes = &breakpoints[&addr];
+            self.write_bytes(addr, orig_bytes)?;
+            self.jump_to(addr)?;
+            ptrace::step(self.pid(), None)?;
+            match self.wait(None)? {
                 Status::Exited(exit_code) => { return Ok(Status::Exited(exit_code)) }
                 Status::Signaled(signal) => { return Ok(Status::Signaled(signal)) }
-                Status::Stopped(_, _) => { self.write_byte(rip - 1, 0xcc).unwrap(); }
+                Status::Stopped(..) => { self.insert_breakpoint(addr)?; }
+                status @ Status::Event(..) => {
+                    self.insert_breakpoint(addr)?;
+                    return Ok(status);
+                }
             }
         }
-        ptrace::cont(self.pid(), None)?;
-        self.wait(None)
+        ptrace::cont(self.pid(), self.pending_signal.take())?;
+        Ok(self.wait(None)?)
+    }
+
+    /// Executes a single instruction, stepping over the breakpoint the inferior may be stopped
+    /// at. Landing on a breakpoint is reported as hitting it, with the program counter where its
+    /// trap leaves it, so that continuing from there doesn't hit it a second time.
+    pub fn step_instruction(&mut self, breakpoints: &HashMap<usize, Vec<u8>>) -> Result<Status, DeetError> {
+        if let Some(addr) = self.breakpoint_hit(breakpoints)? {
+            self.jump_to(addr)?;
+        }
+        let pc = self.pc()?;
+        self.step_once(pc, breakpoints)
+    }
+
+    /// Returns the address of the breakpoint the inferior is stopped on, if any.
+    pub fn breakpoint_hit(&self, breakpoints: &HashMap<usize, Vec<u8>>) -> Result<Option<usize>, nix::Error> {
+        if !self.trapped.get() {
+            return Ok(None);
+        }
+        let addr = Native::breakpoint_at(self.pc()?);
+        Ok(Some(addr).filter(|addr| breakpoints.contains_key(addr)))
+    }
+
+    /// Whether the inferior is stopped on a breakpoint, with the program counter where its trap
+    /// leaves it.
+    pub fn trapped(&self) -> bool {
+        self.trapped.get()
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid())?;
-        let mut instruction_ptr = regs.rip as usize;
-        let mut base_ptr = regs.rbp as usize;
+    /// Moves the program counter to where the trap of the breakpoint at addr leaves it, as if the
+    /// inferior had hit it.
+    pub fn trap_at(&mut self, addr: usize) -> Result<(), nix::Error> {
+        self.jump_to(Native::pc_after_breakpoint(addr))?;
+        self.trapped.set(true);
+        Ok(())
+    }
+
+    /// Executes the instruction at pc, which may have a breakpoint on it.
+    fn step_once(&mut self, pc: usize, breakpoints: &HashMap<usize, Vec<u8>>) -> Result<Status, DeetError> {
+        let ranges = match self.recording {
+            Some(_) => Some(self.recorded_ranges(pc, breakpoints)?),
+            None => None,
+        };
+        let orig_bytes = breakpoints.get(&pc);
+        if let Some(orig_bytes) = orig_bytes {
+            self.write_bytes(pc, orig_bytes)?;
+        }
+        let status = if let Some(ranges) = ranges {
+            self.record_step(&ranges)?
+        } else {
+            ptrace::step(self.pid(), self.pending_signal.take())?;
+            self.wait(None)?
+        };
+        match status {
+            Status::Stopped(signal, new_pc, _) => {
+                if orig_bytes.is_some() {
+                    self.insert_breakpoint(pc)?;
+                }
+                if breakpoints.contains_key(&new_pc) {
+                    self.trap_at(new_pc)?;
+                    return Ok(Status::Stopped(signal, Native::pc_after_breakpoint(new_pc), true));
+                }
+                Ok(status)
+            }
+            Status::Event(..) => {
+                if orig_bytes.is_some() {
+                    self.insert_breakpoint(pc)?;
+                }
+                Ok(status)
+            }
+            _ => Ok(status),
+        }
+    }
+
+    /// Continues by single-stepping, logging each instruction, until a breakpoint is reached or
+    /// the inferior stops for another reason. Breakpoints are reported as if their trap had been
+    /// executed, with the program counter where it leaves it.
+    fn continue_recording(&mut self, breakpoints: &HashMap<usize, Vec<u8>>) -> Result<Status, DeetError> {
+        if let Some(addr) = self.breakpoint_hit(breakpoints)? {
+            self.jump_to(addr)?;
+        }
+        let mut first = true;
         loop {
-            let line = debug_data.get_line_from_addr(instruction_ptr).unwrap();
-            let func = debug_data.get_function_from_addr(instruction_ptr).unwrap();
-            println!("{} ({}:{})", func, line.file, line.number);
-            if func == "main" { break }
-            instruction_ptr = ptrace::read(self.pid(), (base_ptr + 8) as ptrace::AddressType)? as usize;
-            base_ptr = ptrace::read(self.pid(), base_ptr as ptrace::AddressType)? as usize;
+            let pc = self.pc()?;
+            if !first && breakpoints.contains_key(&pc) {
+                self.trap_at(pc)?;
+                return Ok(Status::Stopped(signal::Signal::SIGTRAP, Native::pc_after_breakpoint(pc), true));
+            }
+            first = false;
+            let ranges = self.recorded_ranges(pc, breakpoints)?;
+            let orig_bytes = breakpoints.get(&pc);
+            if let Some(orig_bytes) = orig_bytes {
+                self.write_bytes(pc, orig_bytes)?;
+            }
+            let status = self.record_step(&ranges)?;
+            if orig_bytes.is_some() {
+                if let Status::Stopped(..) | Status::Event(..) = status {
+                    self.insert_breakpoint(pc)?;
+                }
+            }
+            match status {
+                Status::Stopped(signal::Signal::SIGTRAP, ..) => {}
+                other => return Ok(other),
+            }
+        }
+    }
+
+    /// Returns the memory the instruction at pc may write, reading it as it is without the
+    /// breakpoints. If the instruction can't be recorded, recording stops and the instruction is
+    /// left unexecuted, so that the log has no gaps.
+    fn recorded_ranges(&mut self, pc: usize, breakpoints: &HashMap<usize, Vec<u8>>) -> Result<Vec<(u64, usize)>, DeetError> {
+        let pid = self.pid();
+        let regs = Native::registers(pid)?;
+        let mut code = Vec::new();
+        for addr in (pc..pc + 16).step_by(size_of::<usize>()) {
+            match ptrace::read(pid, addr as ptrace::AddressType) {
+                Ok(word) => code.extend_from_slice(&(word as u64).to_le_bytes()),
+                Err(_) => break,
+            }
         }
+        for (addr, orig_bytes) in breakpoints {
+            for (i, byte) in orig_bytes.iter().enumerate() {
+                if let Some(code_byte) = (addr + i).checked_sub(pc).and_then(|offset| code.get_mut(offset)) {
+                    *code_byte = *byte;
+                }
+            }
+        }
+        let read_word = |addr: u64| ptrace::read(pid, addr as ptrace::AddressType).ok().map(|word| word as u64);
+        Native::written_ranges(&code, &regs, &read_word).map_err(|reason| {
+            self.recording = None;
+            DeetError::RecordStopped(reason, pc)
+        })
+    }
+
+    /// Executes one instruction, logging the registers and the words of ranges it overwrites.
+    fn record_step(&mut self, ranges: &[(u64, usize)]) -> Result<Status, nix::Error> {
+        let regs = Native::registers(self.pid())?;
+        let fpregs = Native::fp_registers(self.pid())?;
+        // Words outside of mapped memory can't be written either, so they are skipped
+        let before: Vec<(usize, u64)> = record::words(ranges)
+            .into_iter()
+            .filter_map(|addr| Some((addr, ptrace::read(self.pid(), addr as ptrace::AddressType).ok()? as u64)))
+            .collect();
+
+        ptrace::step(self.pid(), None)?;
+        let status = self.wait(None)?;
+        if let Status::Stopped(..) = status {
+            let memory = before
+                .into_iter()
+                .filter(|(addr, word)| {
+                    ptrace::read(self.pid(), *addr as ptrace::AddressType).map_or(true, |new| new as u64 != *word)
+                })
+                .collect();
+            let new_fpregs = Native::fp_registers(self.pid())?;
+            let changed = fpregs_bytes(&new_fpregs) != fpregs_bytes(&fpregs);
+            self.recording.as_mut().unwrap().push(Entry {
+                regs,
+                fpregs: if changed { Some(Box::new(fpregs)) } else { None },
+                memory,
+            });
+        }
+        Ok(status)
+    }
+
+    /// Starts logging the instructions executed, which only works where instructions are decoded.
+    pub fn start_recording(&mut self) -> Result<(), DeetError> {
+        if !Native::CAN_RECORD {
+            return Err(DeetError::Input(format!("Process record is not supported on {}.", Native::NAME)));
+        }
+        self.recording = Some(Recording::new());
         Ok(())
     }
 
-    pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
-        fn align_addr_to_word(addr: usize) -> usize {
-            addr & (-(size_of::<usize>() as isize) as usize)
+    pub fn stop_recording(&mut self) {
+        self.recording = None;
+    }
+
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
+    }
+
+    /// Undoes the last instruction executed while recording. Returns false if there is no
+    /// history left to undo.
+    pub fn reverse_stepi(&mut self) -> Result<bool, nix::Error> {
+        let entry = match self.recording.as_mut().and_then(Recording::pop) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        for (addr, word) in entry.memory {
+            ptrace::write(self.pid(), addr as ptrace::AddressType, word as *mut std::ffi::c_void)?;
+        }
+        if let Some(fpregs) = entry.fpregs {
+            Native::set_fp_registers(self.pid(), &fpregs)?;
         }
-        let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        ptrace::write(
-            self.pid(),
-            aligned_addr as ptrace::AddressType,
-            updated_word as *mut std::ffi::c_void,
-        )?;
-        Ok(orig_byte as u8)
+        Native::set_registers(self.pid(), entry.regs)?;
+        self.trapped.set(false);
+        Ok(true)
+    }
+
+    /// Returns the frames of the call stack, innermost first, described by `describe_frames`
+    /// with the module and section of each address.
+    pub fn backtrace(&self, debug_data: &DwarfData) -> Result<Vec<String>, nix::Error> {
+        let addresses = self.stack_addresses(debug_data)?;
+        Ok(describe_frames(debug_data, &addresses, |addr| match self.describe_region(addr) {
+            Some(region) => format!("{:#x} {}", addr, region),
+            None => format!("{:#x}", addr),
+        }))
     }
 
+    /// Describes the part of the process addr is in, as in `in section .text of /lib/libc.so.6`.
+    pub fn describe_region(&self, addr: usize) -> Option<String> {
+        self.modules.describe_region(self.pid(), addr)
+    }
+
+    /// Returns the program counters of the frames of the call stack, innermost first.
+    pub fn stack_addresses(&self, debug_data: &DwarfData) -> Result<Vec<usize>, nix::Error> {
+        let regs = Native::registers(self.pid())?;
+        Ok(self.walk_stack(debug_data, regs))
+    }
+
+    /// Returns the addresses of the caller's frames when stopped at the first instruction of a
+    /// function, before it has set up a frame of its own: the return address it was called with,
+    /// and the frames of the caller.
+    pub fn caller_stack_addresses(&self, debug_data: &DwarfData) -> Result<Vec<usize>, nix::Error> {
+        let mut regs = Native::registers(self.pid())?;
+        let return_address = Native::entry_return_address(self.pid(), &regs)?;
+        let stack_pointer = Native::stack_pointer(&regs) + Native::RETURN_POPS;
+        Native::set_pc(&mut regs, return_address);
+        Native::set_stack_pointer(&mut regs, stack_pointer);
+        Ok(self.walk_stack(debug_data, regs))
+    }
+
+    /// Unwinds the call stack from the frame with the given registers up to main, with the call
+    /// frame information of the executable and the libraries it's in, or else by following the
+    /// frame pointer. The walk ends early at a frame that can't be unwound.
+    fn walk_stack(&self, debug_data: &DwarfData, mut regs: Registers) -> Vec<usize> {
+        let mut addresses = Vec::new();
+        while addresses.len() < MAX_FRAMES {
+            let pc = Native::pc(&regs);
+            addresses.push(pc);
+            if let Some(func) = debug_data.get_function_from_addr(pc) {
+                if func == "main" || func.ends_with("::main") {
+                    break;
+                }
+            }
+            // Past the innermost frame the program counter is a return address, which can be
+            // just past the end of a function that doesn't return
+            let lookup = if addresses.len() == 1 { pc } else { pc - 1 };
+            let caller = match self.caller_registers_at(debug_data, &regs, lookup) {
+                Ok(Some(caller)) => Some(caller),
+                Ok(None) => self.frame_pointer_caller(&regs),
+                Err(_) => None,
+            };
+            // The stack grows down, so a caller below its callee means the walk went astray. Only
+            // the innermost frame can share its caller's stack pointer, by not having pushed
+            // anything, as leaf functions on aarch64 do.
+            let (stack_pointer, innermost) = (Native::stack_pointer(&regs), addresses.len() == 1);
+            match caller {
+                Some(caller)
+                    if Native::pc(&caller) != 0
+                        && (Native::stack_pointer(&caller) > stack_pointer
+                            || innermost && Native::stack_pointer(&caller) == stack_pointer) =>
+                {
+                    regs = caller;
+                }
+                _ => break,
+            }
+        }
+        addresses
+    }
+
+    /// Returns the caller's registers found from the frame pointer, for code without call frame
+    /// information, or None if it doesn't point into the stack.
+    fn frame_pointer_caller(&self, regs: &Registers) -> Option<Registers> {
+        let base_ptr = Native::frame_pointer(regs);
+        let return_address = ptrace::read(self.pid(), (base_ptr + Native::RETURN_ADDRESS_OFFSET) as ptrace::AddressType).ok()?;
+        let caller_base_ptr = ptrace::read(self.pid(), base_ptr as ptrace::AddressType).ok()?;
+        let mut caller = *regs;
+        Native::set_pc(&mut caller, return_address as usize);
+        Native::set_frame_pointer(&mut caller, caller_base_ptr as usize);
+        Native::set_stack_pointer(&mut caller, base_ptr + Native::RETURN_ADDRESS_OFFSET + size_of::<usize>());
+        Some(caller)
+    }
+
+    /// Resumes the inferior, delivering signal to it if given.
+    pub fn resume(&self, signal: Option<signal::Signal>) -> Result<(), nix::Error> {
+        ptrace::cont(self.pid(), signal)
+    }
+
+    /// Asks the running inferior to stop. It reports a SIGSTOP once it has.
+    pub fn interrupt(&self) -> Result<(), nix::Error> {
+        signal::kill(self.pid(), signal::Signal::SIGSTOP)
+    }
+
+    /// Removes the breakpoint at addr by restoring its original bytes. If the inferior is stopped
+    /// where that breakpoint's trap left it, the program counter is moved back so the original
+    /// instruction gets executed when the inferior resumes.
+    pub fn remove_breakpoint(&mut self, addr: usize, orig_bytes: &[u8]) -> Result<(), nix::Error> {
+        self.write_bytes(addr, orig_bytes)?;
+        if self.trapped.get() && self.pc()? == Native::pc_after_breakpoint(addr) {
+            self.jump_to(addr)?;
+        }
+        Ok(())
+    }
+
+    pub fn registers(&self) -> Result<Registers, nix::Error> {
+        Native::registers(self.pid())
+    }
+
+    /// Reads len bytes of the inferior's memory starting at addr.
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(len);
+        let mut word_addr = addr;
+        while bytes.len() < len {
+            let word = ptrace::read(self.pid(), word_addr as ptrace::AddressType)? as u64;
+            bytes.extend_from_slice(&word.to_le_bytes());
+            word_addr += size_of::<usize>();
+        }
+        bytes.truncate(len);
+        Ok(bytes)
+    }
+
+    /// Reads the NUL-terminated string at addr in the inferior's memory, up to a limit.
+    pub fn read_c_string(&self, addr: usize) -> Option<String> {
+        const MAX_LEN: usize = 4096;
+        let mut bytes = Vec::new();
+        while bytes.len() < MAX_LEN {
+            let word = self.read_bytes(addr + bytes.len(), size_of::<usize>()).ok()?;
+            match word.iter().position(|b| *b == 0) {
+                Some(end) => {
+                    bytes.extend_from_slice(&word[..end]);
+                    break;
+                }
+                None => bytes.extend_from_slice(&word),
+            }
+        }
+        Some(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    /// Calls the function at func_addr with the given arguments, following the architecture's
+    /// calling convention, and restores the inferior's registers afterwards. Floating point
+    /// arguments are given as the raw bits to place in the low half of their registers. The
+    /// function returns to return_address, which should be outside of any function that may run
+    /// during the call, such as the program's entry point; a trap is temporarily placed there.
+    pub fn call_function(
+        &mut self,
+        func_addr: usize,
+        return_address: usize,
+        int_args: &[u64],
+        float_args: &[u64],
+    ) -> Result<CallResult, nix::Error> {
+        if int_args.len() > Native::MAX_INT_ARGS || float_args.len() > Native::MAX_FLOAT_ARGS {
+            return Err(nix::Error::Sys(nix::errno::Errno::E2BIG));
+        }
+        let saved_regs = Native::registers(self.pid())?;
+        let saved_fpregs = Native::fp_registers(self.pid())?;
+        let saved_trapped = self.trapped.get();
+        let orig_bytes = self.insert_breakpoint(return_address)?;
+
+        let result = self.run_call(func_addr, return_address, int_args, float_args);
+        if let Ok(CallResult::Interrupted(Status::Exited(_))) | Ok(CallResult::Interrupted(Status::Signaled(_))) = result {
+            return result;
+        }
+        // However the call ended, take the trap out and put the registers back, so that a
+        // failure partway through doesn't leave the process in the middle of the call.
+        let bytes_restored = self.write_bytes(return_address, &orig_bytes);
+        let regs_restored = Native::set_registers(self.pid(), saved_regs);
+        let fpregs_restored = Native::set_fp_registers(self.pid(), &saved_fpregs);
+        self.trapped.set(saved_trapped);
+        let result = result?;
+        bytes_restored?;
+        regs_restored?;
+        fpregs_restored?;
+        Ok(result)
+    }
+
+    /// Sets up the registers for a call to func_addr and runs the inferior until it returns to
+    /// return_address or stops for another reason.
+    fn run_call(
+        &mut self,
+        func_addr: usize,
+        return_address: usize,
+        int_args: &[u64],
+        float_args: &[u64],
+    ) -> Result<CallResult, nix::Error> {
+        let mut regs = Native::registers(self.pid())?;
+        let mut fpregs = Native::fp_registers(self.pid())?;
+        if let Some(slot) = Native::set_up_call(&mut regs, &mut fpregs, func_addr, return_address, int_args, float_args) {
+            ptrace::write(self.pid(), slot as ptrace::AddressType, return_address as *mut std::ffi::c_void)?;
+        }
+        Native::set_registers(self.pid(), regs)?;
+        Native::set_fp_registers(self.pid(), &fpregs)?;
+
+        ptrace::cont(self.pid(), None)?;
+        let status = self.wait(None)?;
+        Ok(match status {
+            Status::Stopped(signal::Signal::SIGTRAP, pc, true) if pc == Native::pc_after_breakpoint(return_address) => {
+                let ret_regs = Native::registers(self.pid())?;
+                let ret_fpregs = Native::fp_registers(self.pid())?;
+                CallResult::Returned(Native::return_value(&ret_regs), Native::float_return_value(&ret_fpregs))
+            }
+            _ => CallResult::Interrupted(status),
+        })
+    }
+
+    /// Returns the current program counter.
+    pub fn pc(&self) -> Result<usize, nix::Error> {
+        Ok(Native::pc(&Native::registers(self.pid())?))
+    }
+
+    /// Moves the program counter to addr without executing anything in between.
+    pub fn jump_to(&mut self, addr: usize) -> Result<(), nix::Error> {
+        let mut regs = Native::registers(self.pid())?;
+        Native::set_pc(&mut regs, addr);
+        Native::set_registers(self.pid(), regs)?;
+        self.trapped.set(false);
+        Ok(())
+    }
+
+    /// Returns the registers the caller of the frame with regs will have once that frame
+    /// returns: the program counter at the return address, the stack pointer at the canonical
+    /// frame address and the registers the function saved restored. Returns None if there is no
+    /// call frame information for the frame's instruction, in the executable or the library it's
+    /// in.
+    pub fn caller_registers(&self, debug_data: &DwarfData, regs: &Registers) -> Result<Option<Registers>, nix::Error> {
+        self.caller_registers_at(debug_data, regs, Native::pc(regs))
+    }
+
+    /// Does `caller_registers` with the call frame information for the instruction at addr.
+    fn caller_registers_at(&self, debug_data: &DwarfData, regs: &Registers, addr: usize) -> Result<Option<Registers>, nix::Error> {
+        let rule = debug_data
+            .frame_rule(addr)
+            .or_else(|| self.modules.module_at(self.pid(), addr)?.frame_rule(addr));
+        let rule = match rule {
+            Some(rule) => rule,
+            None => return Ok(None),
+        };
+        let cfa = match rule.cfa(regs) {
+            Some(cfa) => cfa,
+            None => return Ok(None),
+        };
+        let read_saved = |offset: i64| ptrace::read(self.pid(), cfa.wrapping_add(offset as usize) as ptrace::AddressType);
+        let return_address = match rule.return_address {
+            SavedRegister::InRegister(number) => match Native::register(regs, number) {
+                Some(value) => value,
+                None => return Ok(None),
+            },
+            SavedRegister::AtCfaOffset(offset) => read_saved(offset)? as u64,
+        };
+        let mut caller = *regs;
+        for (number, offset) in rule.saved_registers {
+            Native::set_register(&mut caller, number, read_saved(offset)? as u64);
+        }
+        Native::set_pc(&mut caller, return_address as usize);
+        Native::set_stack_pointer(&mut caller, cfa);
+        Ok(Some(caller))
+    }
+
+    /// Pops the current frame as if the function had returned, leaving the caller with the
+    /// registers `caller_registers` gave and optionally value in the return register.
+    pub fn pop_frame(&mut self, mut caller: Registers, value: Option<i64>) -> Result<(), nix::Error> {
+        if let Some(value) = value {
+            Native::set_return_value(&mut caller, value as u64);
+        }
+        Native::set_registers(self.pid(), caller)?;
+        self.trapped.set(false);
+        Ok(())
+    }
+
+    /// Writes bytes into the inferior's memory at addr, returning the bytes they replaced.
+    pub fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> Result<Vec<u8>, nix::Error> {
+        write_bytes_in(self.pid(), addr, bytes)
+    }
+
+    /// Writes the breakpoint instruction at addr, returning the bytes it replaced.
+    pub fn insert_breakpoint(&mut self, addr: usize) -> Result<Vec<u8>, nix::Error> {
+        self.write_bytes(addr, Native::BREAKPOINT)
+    }
+
+    /// Forks the process with the given pid, which must be stopped, by making it run a fork
+    /// system call in place of its current instruction. Returns the pid of the copy, which is
+    /// left stopped with the same registers and memory as the original.
+    fn fork_process(pid: Pid) -> Result<Pid, nix::Error> {
+        let saved_regs = Native::registers(pid)?;
+        let pc = Native::pc(&saved_regs);
+        let orig_bytes = write_bytes_in(pid, pc, Native::SYSCALL)?;
+        let mut regs = saved_regs;
+        Native::set_fork_syscall(&mut regs);
+        Native::set_registers(pid, regs)?;
+        // Have the kernel attach the copy to us and stop it before it runs anything
+        ptrace::setoptions(pid, ptrace::Options::PTRACE_O_TRACEFORK)?;
+
+        let forked = Inferior::step_into_fork(pid);
+
+        ptrace::setoptions(pid, ptrace::Options::empty())?;
+        write_bytes_in(pid, pc, &orig_bytes)?;
+        Native::set_registers(pid, saved_regs)?;
+        let child_pid = forked?;
+        ptrace::setoptions(child_pid, ptrace::Options::empty())?;
+        write_bytes_in(child_pid, pc, &orig_bytes)?;
+        Native::set_registers(child_pid, saved_regs)?;
+        Ok(child_pid)
+    }
+
+    /// Single-steps over the fork system call set up by fork_process, returning the pid of the
+    /// new process once both it and the original have stopped.
+    fn step_into_fork(pid: Pid) -> Result<Pid, nix::Error> {
+        loop {
+            ptrace::step(pid, None)?;
+            match waitpid(pid, None)? {
+                WaitStatus::PtraceEvent(_, _, event) if event == libc::PTRACE_EVENT_FORK => {
+                    let child_pid = Pid::from_raw(ptrace::getevent(pid)? as i32);
+                    // Finish the system call in the original, and wait for the copy's initial stop
+                    ptrace::step(pid, None)?;
+                    waitpid(pid, None)?;
+                    waitpid(child_pid, None)?;
+                    return Ok(child_pid);
+                }
+                // Signals that arrived while it was stopped, such as the SIGCHLD a checkpoint
+                // gets when a copy of it exits, are discarded
+                WaitStatus::Stopped(_, sig) if sig != signal::Signal::SIGTRAP => continue,
+                _ => return Err(nix::Error::Sys(nix::errno::Errno::ECHILD)),
+            }
+        }
+    }
+
+    /// Saves the current state of the inferior as a new checkpoint and returns its number.
+    pub fn checkpoint(&mut self, break_points: &HashMap<usize, Vec<u8>>) -> Result<usize, nix::Error> {
+        let pid = Inferior::fork_process(self.pid)?;
+        ptrace::setoptions(self.pid, self.ptrace_options)?;
+        let id = self.next_checkpoint_id;
+        self.next_checkpoint_id += 1;
+        self.checkpoints.push(Checkpoint {
+            id,
+            pid,
+            pc: self.pc()?,
+            break_points: break_points.clone(),
+            trapped: self.trapped.get(),
+        });
+        Ok(id)
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Discards the current state of the inferior and continues from a copy of checkpoint id,
+    /// which stays saved so it can be restarted again. Breakpoints are brought up to date with
+    /// break_points. Returns the program counter of the restored state.
+    pub fn restart(&mut self, id: usize, break_points: &HashMap<usize, Vec<u8>>) -> Result<usize, nix::Error> {
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.id == id)
+            .ok_or(nix::Error::Sys(nix::errno::Errno::ESRCH))?;
+        let pid = Inferior::fork_process(checkpoint.pid)?;
+        let saved_break_points = checkpoint.break_points.clone();
+        self.trapped.set(checkpoint.trapped);
+
+        Inferior::kill_process(self.pid);
+        self.pid = pid;
+        self.modules.invalidate();
+        ptrace::setoptions(self.pid, self.ptrace_options)?;
+        // The log describes the execution of the process we just discarded
+        if self.recording.is_some() {
+            self.recording = Some(Recording::new());
+        }
+        for (addr, orig_bytes) in &saved_break_points {
+            if !break_points.contains_key(addr) {
+                self.write_bytes(*addr, orig_bytes)?;
+            }
+        }
+        for addr in break_points.keys() {
+            if !saved_break_points.contains_key(addr) {
+                self.insert_breakpoint(*addr)?;
+            }
+        }
+        self.pc()
+    }
+
+    /// Kills the process of checkpoint id, returning false if there is no such checkpoint.
+    pub fn delete_checkpoint(&mut self, id: usize) -> bool {
+        match self.checkpoints.iter().position(|checkpoint| checkpoint.id == id) {
+            Some(idx) => {
+                Inferior::kill_process(self.checkpoints.remove(idx).pid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn kill_process(pid: Pid) {
+        signal::kill(pid, signal::Signal::SIGKILL).ok();
+        // A process stopped on its way out, as when catching exits, only dies once it's resumed
+        ptrace::cont(pid, None).ok();
+        while let Ok(WaitStatus::PtraceEvent(..)) = waitpid(pid, None) {
+            ptrace::cont(pid, None).ok();
+        }
+    }
+
+    /// Kills the process and the processes kept as its checkpoints.
     pub fn kill(&mut self) {
-        self.child.kill().unwrap();
-        self.wait(None).unwrap();
-        println!("Killing running inferior (pid {})", self.pid());
+        Inferior::kill_process(self.pid);
+        for checkpoint in self.checkpoints.drain(..) {
+            Inferior::kill_process(checkpoint.pid);
+        }
+    }
+}
+
+/// Writes val into the memory of the stopped process pid at addr, returning the byte it replaced.
+fn write_byte_in(pid: Pid, addr: usize, val: u8) -> Result<u8, nix::Error> {
+    fn align_addr_to_word(addr: usize) -> usize {
+        addr & (-(size_of::<usize>() as isize) as usize)
+    }
+    let aligned_addr = align_addr_to_word(addr);
+    let byte_offset = addr - aligned_addr;
+    let word = ptrace::read(pid, aligned_addr as ptrace::AddressType)? as u64;
+    let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+    let masked_word = word & !(0xff << (8 * byte_offset));
+    let updated_word = masked_word | ((val as u64) << (8 * byte_offset));
+    ptrace::write(
+        pid,
+        aligned_addr as ptrace::AddressType,
+        updated_word as *mut std::ffi::c_void,
+    )?;
+    Ok(orig_byte as u8)
+}
+
+/// Writes bytes into the memory of the stopped process pid at addr, returning the bytes they
+/// replaced.
+fn write_bytes_in(pid: Pid, addr: usize, bytes: &[u8]) -> Result<Vec<u8>, nix::Error> {
+    bytes.iter().enumerate().map(|(i, val)| write_byte_in(pid, addr + i, *val)).collect()
+}
+
+/// Describes the frames at the given addresses of a call stack, innermost first, as
+/// `func (file:line) at <addr>`, where describe_addr gives `<addr>`. Calls the compiler inlined
+/// get frames of their own, as `func [inlined] (file:line) at <addr>`. Frames in code without
+/// debugging information, as in libraries, are described by describe_addr alone.
+pub fn describe_frames(debug_data: &DwarfData, addresses: &[usize], describe_addr: impl Fn(usize) -> String) -> Vec<String> {
+    let mut frames = Vec::new();
+    for instruction_ptr in addresses {
+        let addr = describe_addr(*instruction_ptr);
+        let func = match debug_data.get_function_from_addr(*instruction_ptr) {
+            Some(func) => func,
+            None => {
+                frames.push(addr);
+                continue;
+            }
+        };
+        let mut location = match debug_data.get_line_from_addr(*instruction_ptr) {
+            Some(line) => format!("{}:{}", line.file, line.number),
+            None => "??".to_string(),
+        };
+        // Each inlined call is shown at the line it's at, and its caller at the call
+        for call in debug_data.get_inlined_calls_at(*instruction_ptr).iter().rev() {
+            frames.push(format!("{} [inlined] ({}) at {}", call.name, location, addr));
+            location = format!("{}:{}", call.call_file, call.call_line);
+        }
+        frames.push(format!("{} ({}) at {}", func, location, addr));
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<(Vec<String>, Redirections), String> {
+        Redirections::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn leaves_plain_arguments() {
+        let (args, redirections) = parse(&["-v", "file", "2"]).unwrap();
+        assert_eq!(args, vec!["-v".to_string(), "file".to_string(), "2".to_string()]);
+        assert_eq!(redirections, Redirections::default());
+    }
+
+    #[test]
+    fn splits_out_redirections() {
+        let (args, redirections) = parse(&["a", "<", "in.txt", ">>log", "b", "2>", "err"]).unwrap();
+        assert_eq!(args, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            redirections,
+            Redirections {
+                stdin: Some("in.txt".to_string()),
+                stdout: Some(Output::Append("log".to_string())),
+                stderr: Some(Output::Truncate("err".to_string())),
+            }
+        );
+        let (_, redirections) = parse(&[">", "out", "2>>errors"]).unwrap();
+        assert_eq!(redirections.stdout, Some(Output::Truncate("out".to_string())));
+        assert_eq!(redirections.stderr, Some(Output::Append("errors".to_string())));
+    }
+
+    #[test]
+    fn sends_stderr_to_stdout() {
+        let (_, redirections) = parse(&[">out", "2>&1"]).unwrap();
+        assert_eq!(redirections.stdout, Some(Output::Truncate("out".to_string())));
+        assert_eq!(redirections.stderr, Some(Output::Stdout));
+        assert_eq!(parse(&["&>", "all"]).unwrap().1, parse(&[">all", "2>&1"]).unwrap().1);
+        assert_eq!(parse(&["&>>all"]).unwrap().1, parse(&[">>all", "2>&1"]).unwrap().1);
+    }
+
+    #[test]
+    fn applies_redirections_in_order() {
+        // Stderr is duplicated from stdout while stdout is still the terminal
+        let (_, redirections) = parse(&["2>&1", ">out"]).unwrap();
+        assert_eq!(redirections.stdout, Some(Output::Truncate("out".to_string())));
+        assert_eq!(redirections.stderr, None);
+        // Stderr keeps the file stdout had when it was duplicated
+        let (_, redirections) = parse(&[">first", "2>&1", ">>second"]).unwrap();
+        assert_eq!(redirections.stdout, Some(Output::Append("second".to_string())));
+        assert_eq!(redirections.stderr, Some(Output::Truncate("first".to_string())));
+    }
+
+    #[test]
+    fn requires_a_file_name() {
+        assert_eq!(parse(&["a", ">"]), Err("Missing file name after >".to_string()));
+        assert_eq!(parse(&["2>>"]), Err("Missing file name after 2>>".to_string()));
     }
 }
@@ -0,0 +1,155 @@
+//! Listings of the symbols in the debugging information, for `info functions`, `info variables`,
+//! `info types` and `info scope`.
+
+use deet::arch::{Arch, Native};
+use deet::dwarf_data::{function_name, DwarfData, Function, Location, TypeLayout, Variable};
+use crate::pattern::Pattern;
+use std::collections::BTreeMap;
+
+fn print_header(kind: &str, pattern: Option<&str>) {
+    match pattern {
+        Some(pattern) => println!("All {} matching regular expression \"{}\":", kind, pattern),
+        None => println!("All defined {}:", kind),
+    }
+}
+
+fn compile(pattern: Option<&str>) -> Result<Option<Pattern>, String> {
+    pattern.map(Pattern::new).transpose().map_err(|err| format!("Invalid regexp: {}", err))
+}
+
+/// Formats a function like a declaration, as in `int func2(int, int);`.
+fn function_declaration(func: &Function) -> String {
+    let name = function_name(func);
+    let return_type = func.return_type.as_ref().map_or("void", |ty| ty.name.as_str());
+    if name.contains('(') {
+        return format!("{} {};", return_type, name);
+    }
+    let params: Vec<&str> = func.params.iter().map(|ty| ty.name.as_str()).collect();
+    format!("{} {}({});", return_type, name, params.join(", "))
+}
+
+/// Prints the lines of each file in order, after the file's name.
+fn print_by_file(files: BTreeMap<&str, BTreeMap<(String, usize), String>>) {
+    for (file, lines) in files {
+        if lines.is_empty() {
+            continue;
+        }
+        println!();
+        println!("File {}:", file);
+        for ((_, line_number), line) in lines {
+            if line_number > 0 {
+                println!("{}:\t{}", line_number, line);
+            } else {
+                println!("\t{}", line);
+            }
+        }
+    }
+}
+
+/// Lists the functions whose names match pattern, by file, followed by the ELF symbols of
+/// functions without debugging information.
+pub fn print_functions(debug_data: &DwarfData, pattern: Option<&str>) -> Result<(), String> {
+    let regex = compile(pattern)?;
+    let is_match = |name: &str| regex.as_ref().is_none_or(|regex| regex.is_match(name));
+    print_header("functions", pattern);
+    let mut files = BTreeMap::new();
+    let mut addresses = Vec::new();
+    for (file, _) in debug_data.units() {
+        let lines = files.entry(file.name.as_str()).or_insert_with(BTreeMap::new);
+        for func in file.functions.iter().filter(|func| func.address != 0) {
+            addresses.push(func.address);
+            let name = function_name(func);
+            if is_match(&name) {
+                lines.insert((name, func.line_number), function_declaration(func));
+            }
+        }
+    }
+    print_by_file(files);
+
+    let symbols: Vec<_> = debug_data
+        .symbols()
+        .iter()
+        .filter(|symbol| !addresses.contains(&symbol.address) && is_match(&symbol.name))
+        .collect();
+    if !symbols.is_empty() {
+        println!();
+        println!("Non-debugging symbols:");
+        for symbol in symbols {
+            println!("{:#018x}  {}", symbol.address, symbol.name);
+        }
+    }
+    Ok(())
+}
+
+/// Lists the global variables whose names match pattern, by file.
+pub fn print_variables(debug_data: &DwarfData, pattern: Option<&str>) -> Result<(), String> {
+    let regex = compile(pattern)?;
+    print_header("variables", pattern);
+    let mut files = BTreeMap::new();
+    for (file, _) in debug_data.units() {
+        let lines = files.entry(file.name.as_str()).or_insert_with(BTreeMap::new);
+        for var in &file.global_variables {
+            if regex.as_ref().is_none_or(|regex| regex.is_match(&var.name)) {
+                let declaration = format!("{} {};", var.entity_type.name, var.name);
+                lines.insert((var.name.clone(), var.line_number), declaration);
+            }
+        }
+    }
+    print_by_file(files);
+    Ok(())
+}
+
+/// Lists the named types whose names match pattern, by file.
+pub fn print_types(debug_data: &DwarfData, pattern: Option<&str>) -> Result<(), String> {
+    let regex = compile(pattern)?;
+    print_header("types", pattern);
+    let mut files = BTreeMap::new();
+    for (file, types) in debug_data.units() {
+        let lines = files.entry(file.name.as_str()).or_insert_with(BTreeMap::new);
+        for ty in types {
+            // Pointer and array types are named after what they point to or hold
+            let derived = matches!(ty.layout, TypeLayout::Pointer(_) | TypeLayout::Array(..))
+                || ty.name.ends_with('*')
+                || ty.name.ends_with('&');
+            if ty.name.is_empty() || ty.name == "const" || derived {
+                continue;
+            }
+            if regex.as_ref().is_none_or(|regex| regex.is_match(&ty.name)) {
+                lines.insert((ty.name.clone(), 0), ty.name.clone());
+            }
+        }
+    }
+    print_by_file(files);
+    Ok(())
+}
+
+/// Describes where a variable lives, as in `a variable at frame base reg $rbp offset -20`.
+pub fn describe_variable(var: &Variable, frame_base_register: Option<u16>) -> String {
+    match var.location {
+        Location::Address(addr) => format!("static storage at address {:#x}", addr),
+        Location::FramePointerOffset(offset) => match frame_base_register {
+            None => format!("a variable at frame base (CFA) offset {}", offset),
+            Some(reg) => match Native::register_name(reg) {
+                Some(name) => format!("a variable at frame base reg ${} offset {}", name, offset),
+                None => format!("a variable at frame base reg {} offset {}", reg, offset),
+            },
+        },
+    }
+}
+
+/// Lists the parameters and local variables of func in scope at addr.
+pub fn print_scope(func: &Function, addr: usize) {
+    println!("Scope for {}:", function_name(func));
+    let variables = func.variables_at(addr);
+    if variables.is_empty() {
+        println!("Symbol {} contains no locals or arguments.", func.name);
+    }
+    for var in variables {
+        println!(
+            "Symbol {} is {}, length {}.",
+            var.name,
+            describe_variable(var, func.frame_base_register),
+            var.entity_type.size
+        );
+    }
+}
@@ -0,0 +1,23 @@
+//! deet as a library, for writing test harnesses, crash analyzers and other tools that drive a
+//! program under ptrace. A `Target` loads a program's debugging information, sets breakpoints,
+//! runs and steps the program, and reads its variables and memory; each call that lets the
+//! program run returns the `StopEvent` it stopped with. The `deet` command line is built on the
+//! same interface.
+
+pub mod arch;
+mod debug_file;
+mod debug_names;
+pub mod dwarf_data;
+mod dwarf_index;
+pub mod error;
+pub mod expression;
+mod gimli_wrapper;
+pub mod inferior;
+pub mod record;
+pub mod shared_library;
+pub mod target;
+pub mod unwind;
+mod value_printer;
+
+pub use crate::error::DeetError;
+pub use crate::target::{Catch, StopEvent, Stepping, Target};
@@ -1,24 +1,184 @@
+mod completion;
+mod coverage;
 mod debugger;
 mod debugger_command;
-mod inferior;
-mod dwarf_data;
-mod gimli_wrapper;
+mod heap;
+mod info;
+mod pattern;
+mod printf;
+mod process_info;
+mod profile;
+mod session;
+mod tui;
 
 use crate::debugger::Debugger;
+use crate::profile::ProfileOptions;
 use nix::sys::signal::{signal, SigHandler, Signal};
+use std::collections::HashMap;
 use std::env;
+use std::path::Path;
+
+fn usage(program: &str) -> ! {
+    println!("Usage: {} [--tui] <target program>", program);
+    println!("       {} profile [--hz N] [-o FILE] <target program> [args...]", program);
+    println!("       {} coverage [-o FILE] <target program> [args...]", program);
+    println!("       {} heap <target program> [args...]", program);
+    std::process::exit(1);
+}
+
+/// Options of `deet profile` and `deet coverage`, which all take a value
+const OUTPUT_OPTIONS: &[&str] = &["-o", "--output"];
+const PROFILE_OPTIONS: &[&str] = &["-o", "--output", "--hz"];
+
+/// Splits the arguments of a subcommand such as `deet profile` into its options, the target and
+/// the target's arguments. Returns None if an option is not one of the given ones or is missing
+/// its value.
+fn split_options<'a>(
+    args: &'a [String],
+    known: &[&str],
+) -> Option<(HashMap<&'a str, &'a str>, &'a str, &'a [String])> {
+    let mut options = HashMap::new();
+    let mut i = 0;
+    while args.get(i)?.starts_with('-') {
+        if !known.contains(&args[i].as_str()) {
+            return None;
+        }
+        options.insert(args[i].as_str(), args.get(i + 1)?.as_str());
+        i += 2;
+    }
+    Some((options, args[i].as_str(), &args[i + 1..]))
+}
+
+/// Returns the file a subcommand writes to: the one given with -o, or else the target's name with
+/// the given extension.
+fn output_path(options: &HashMap<&str, &str>, target: &str, extension: &str) -> Option<String> {
+    match options.get("-o").or_else(|| options.get("--output")) {
+        Some(output) => Some(output.to_string()),
+        None => Some(format!("{}.{}", Path::new(target).file_name()?.to_string_lossy(), extension)),
+    }
+}
+
+fn parse_profile_args(args: &[String]) -> Option<(ProfileOptions, &str, &[String])> {
+    let (options, target, target_args) = split_options(args, PROFILE_OPTIONS)?;
+    let hz = match options.get("--hz") {
+        Some(hz) => hz.parse().ok().filter(|hz| *hz > 0)?,
+        None => 100,
+    };
+    let output = output_path(&options, target, "folded")?;
+    Some((ProfileOptions { hz, output }, target, target_args))
+}
+
+fn parse_coverage_args(args: &[String]) -> Option<(String, &str, &[String])> {
+    let (options, target, target_args) = split_options(args, OUTPUT_OPTIONS)?;
+    Some((output_path(&options, target, "info")?, target, target_args))
+}
+
+/// Returns the target of an interactive session and whether to use the full-screen interface.
+/// `--tui` may come before or after the target.
+fn parse_debugger_args(args: &[String]) -> Option<(&str, bool)> {
+    let tui = args.iter().any(|arg| arg == "--tui");
+    match args.iter().filter(|arg| *arg != "--tui").collect::<Vec<_>>()[..] {
+        [target] => Some((target.as_str(), tui)),
+        _ => None,
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
-    }
-    let target = &args[1];
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let result = match args.get(1).map(String::as_str) {
+        Some("profile") => {
+            let (options, target, target_args) = parse_profile_args(&args[2..]).unwrap_or_else(|| usage(&args[0]));
+            Some(profile::profile(target, target_args, &options))
+        }
+        Some("coverage") => {
+            let (output, target, target_args) = parse_coverage_args(&args[2..]).unwrap_or_else(|| usage(&args[0]));
+            Some(coverage::coverage(target, target_args, &output))
+        }
+        Some("heap") => {
+            let target = args.get(2).unwrap_or_else(|| usage(&args[0]));
+            Some(heap::heap(target, &args[3..]))
+        }
+        _ => None,
+    };
+    match result {
+        Some(Ok(())) => return,
+        Some(Err(err)) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+        None => {}
+    }
+
+    let (target, tui) = parse_debugger_args(&args[1..]).unwrap_or_else(|| usage(&args[0]));
+
+    let mut debugger = match Debugger::new(target) {
+        Ok(debugger) => debugger,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if tui {
+        debugger.enable_tui();
+    }
+    debugger.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn split_options_separates_options_target_and_arguments() {
+        let args = strings(&["--hz", "50", "-o", "out.folded", "./prog", "-o", "x"]);
+        let (options, target, target_args) = split_options(&args, PROFILE_OPTIONS).unwrap();
+        assert_eq!(options.get("--hz"), Some(&"50"));
+        assert_eq!(options.get("-o"), Some(&"out.folded"));
+        assert_eq!(target, "./prog");
+        assert_eq!(target_args, &strings(&["-o", "x"])[..]);
+    }
+
+    #[test]
+    fn split_options_rejects_unknown_and_incomplete_options() {
+        assert!(split_options(&strings(&["--hz", "50", "./prog"]), OUTPUT_OPTIONS).is_none());
+        assert!(split_options(&strings(&["-v", "./prog"]), PROFILE_OPTIONS).is_none());
+        assert!(split_options(&strings(&["-o"]), OUTPUT_OPTIONS).is_none());
+        assert!(split_options(&strings(&["-o", "out.info"]), OUTPUT_OPTIONS).is_none());
+        assert!(split_options(&[], OUTPUT_OPTIONS).is_none());
+    }
+
+    #[test]
+    fn profile_args_default_and_validate_frequency() {
+        let args = strings(&["/bin/prog", "arg"]);
+        let (options, target, target_args) = parse_profile_args(&args).unwrap();
+        assert_eq!((options.hz, options.output.as_str()), (100, "prog.folded"));
+        assert_eq!((target, target_args), ("/bin/prog", &args[1..]));
+        assert!(parse_profile_args(&strings(&["--hz", "0", "prog"])).is_none());
+        assert!(parse_profile_args(&strings(&["--hz", "fast", "prog"])).is_none());
+    }
+
+    #[test]
+    fn coverage_args_take_output() {
+        let args = strings(&["--output", "cov.info", "prog"]);
+        assert_eq!(parse_coverage_args(&args).unwrap().0, "cov.info");
+        assert_eq!(parse_coverage_args(&strings(&["dir/prog"])).unwrap().0, "prog.info");
+    }
+
+    #[test]
+    fn tui_flag_goes_anywhere() {
+        assert_eq!(parse_debugger_args(&strings(&["prog"])), Some(("prog", false)));
+        assert_eq!(parse_debugger_args(&strings(&["--tui", "prog"])), Some(("prog", true)));
+        assert_eq!(parse_debugger_args(&strings(&["prog", "--tui"])), Some(("prog", true)));
+        assert_eq!(parse_debugger_args(&strings(&["--tui"])), None);
+        assert_eq!(parse_debugger_args(&strings(&["prog", "other"])), None);
+    }
 }
@@ -0,0 +1,214 @@
+//! A small regular expression matcher for filtering symbol names, as in `info functions ^func`.
+//! It supports literals, `.`, bracket expressions such as `[a-z_]` and `[^0-9]`, the `*`, `+`
+//! and `?` repetitions, the `^` and `$` anchors and `\` escapes. Alternation and groups are not
+//! supported. Matching follows every way the pattern can match at once, one character of the
+//! text at a time, so it takes time proportional to the lengths of the pattern and the text.
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Char(char),
+    Any,
+    /// Ranges of characters, and whether the set is negated
+    Set(Vec<(char, char)>, bool),
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => c == *expected,
+            Atom::Any => true,
+            Atom::Set(ranges, negated) => ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi) != *negated,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Repeat {
+    Once,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    items: Vec<(Atom, Repeat)>,
+    anchored_start: bool,
+    anchored_end: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Pattern, String> {
+        let mut chars = pattern.chars().peekable();
+        let anchored_start = chars.peek() == Some(&'^');
+        if anchored_start {
+            chars.next();
+        }
+        let mut items: Vec<(Atom, Repeat)> = Vec::new();
+        let mut anchored_end = false;
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '$' if chars.peek().is_none() => {
+                    anchored_end = true;
+                    break;
+                }
+                '.' => Atom::Any,
+                '\\' => Atom::Char(chars.next().ok_or("Trailing backslash")?),
+                '[' => {
+                    let negated = chars.peek() == Some(&'^');
+                    if negated {
+                        chars.next();
+                    }
+                    let mut ranges = Vec::new();
+                    loop {
+                        let lo = match chars.next() {
+                            Some(']') if !ranges.is_empty() => break,
+                            Some('\\') => chars.next().ok_or("Unmatched [")?,
+                            Some(c) => c,
+                            None => return Err("Unmatched [".to_string()),
+                        };
+                        let mut lookahead = chars.clone();
+                        if lookahead.next() == Some('-') && lookahead.peek().is_some_and(|c| *c != ']') {
+                            chars.next();
+                            let hi = chars.next().unwrap();
+                            if hi < lo {
+                                return Err("Invalid range end".to_string());
+                            }
+                            ranges.push((lo, hi));
+                        } else {
+                            ranges.push((lo, lo));
+                        }
+                    }
+                    Atom::Set(ranges, negated)
+                }
+                '*' | '+' | '?' => return Err(format!("Nothing to repeat before {}", c)),
+                '|' | '(' | ')' => return Err(format!("Unsupported {} (alternation and groups aren't supported)", c)),
+                c => Atom::Char(c),
+            };
+            let repeat = match chars.peek() {
+                Some('*') => Repeat::ZeroOrMore,
+                Some('+') => Repeat::OneOrMore,
+                Some('?') => Repeat::ZeroOrOne,
+                _ => Repeat::Once,
+            };
+            if repeat != Repeat::Once {
+                chars.next();
+            }
+            items.push((atom, repeat));
+        }
+        Ok(Pattern {
+            items,
+            anchored_start,
+            anchored_end,
+        })
+    }
+
+    /// Returns true if the pattern matches somewhere in text.
+    pub fn is_match(&self, text: &str) -> bool {
+        // states[i] is set when the items before i have matched the text read so far. Matches
+        // may start at any character unless anchored, so state 0 is added again before each.
+        let mut states = vec![false; self.items.len() + 1];
+        states[0] = true;
+        self.skip_optional(&mut states);
+        for c in text.chars() {
+            if states[self.items.len()] && !self.anchored_end {
+                return true;
+            }
+            let mut next = vec![false; states.len()];
+            for (i, (atom, repeat)) in self.items.iter().enumerate() {
+                if !states[i] || !atom.matches(c) {
+                    continue;
+                }
+                next[i + 1] = true;
+                // Repetitions may go on matching the same item
+                if let Repeat::ZeroOrMore | Repeat::OneOrMore = repeat {
+                    next[i] = true;
+                }
+            }
+            next[0] |= !self.anchored_start;
+            self.skip_optional(&mut next);
+            states = next;
+        }
+        states[self.items.len()]
+    }
+
+    /// Adds to states the states reached by skipping items that may match nothing.
+    fn skip_optional(&self, states: &mut [bool]) {
+        for (i, (_, repeat)) in self.items.iter().enumerate() {
+            if states[i] && matches!(repeat, Repeat::ZeroOrOne | Repeat::ZeroOrMore) {
+                states[i + 1] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Pattern::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn matches_anywhere_unless_anchored() {
+        assert!(matches("func", "my_func2"));
+        assert!(matches("^func", "func2"));
+        assert!(!matches("^func", "my_func"));
+        assert!(matches("2$", "func2"));
+        assert!(!matches("2$", "func23"));
+        assert!(matches("^main$", "main"));
+        assert!(!matches("^main$", "domain"));
+        assert!(matches("", "anything"));
+    }
+
+    #[test]
+    fn matches_repetitions() {
+        assert!(matches("^fu.*3$", "func3"));
+        assert!(matches("^ab+c$", "abbbc"));
+        assert!(!matches("^ab+c$", "ac"));
+        assert!(matches("^ab?c$", "ac"));
+        assert!(!matches("^ab?c$", "abbc"));
+        assert!(matches("^a.*a$", "abca"));
+        assert!(matches("^a?a?b$", "ab"));
+        assert!(!matches("^a+$", ""));
+    }
+
+    #[test]
+    fn matches_in_linear_time() {
+        // Backtracking would try every way of splitting the text between the .* items
+        let text = "a".repeat(10_000);
+        assert!(!matches(".*.*.*.*.*.*x", &text));
+        assert!(matches(".*.*.*.*.*.*a$", &text));
+    }
+
+    #[test]
+    fn matches_bracket_expressions() {
+        assert!(matches("^[a-c_]+$", "ab_c"));
+        assert!(!matches("^[a-c_]+$", "abd"));
+        assert!(matches("^[^0-9]+$", "func"));
+        assert!(!matches("^[^0-9]+$", "func1"));
+        assert!(matches("[]]", "a]"));
+        assert!(matches("^[a-]$", "-"));
+        assert!(matches("^[\\]x]+$", "]x"));
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        assert!(matches("operator\\*", "operator*"));
+        assert!(!matches("^a\\.b$", "axb"));
+        assert!(matches("a$b", "a$b"));
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        assert_eq!(Pattern::new("*a").unwrap_err(), "Nothing to repeat before *");
+        assert_eq!(Pattern::new("a\\").unwrap_err(), "Trailing backslash");
+        assert_eq!(Pattern::new("[abc").unwrap_err(), "Unmatched [");
+        assert_eq!(Pattern::new("[z-a]").unwrap_err(), "Invalid range end");
+        assert!(Pattern::new("a|b").is_err());
+        assert!(Pattern::new("(ab)+").is_err());
+        assert!(Pattern::new("a\\(\\)").is_ok());
+        assert!(matches("^[(|)]+$", "(|)"));
+    }
+}
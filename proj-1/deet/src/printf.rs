@@ -0,0 +1,377 @@
+//! printf-style formatting of values, for `dprintf`. Formats are parsed once, when the dprintf is
+//! set, so that formatting them each time it's hit stays cheap.
+
+use deet::expression::Value;
+
+#[derive(Debug, Clone)]
+struct Conversion {
+    left: bool,      // `-`: pad on the right
+    plus: bool,      // `+`: always show the sign
+    space: bool,     // ` `: a space in place of a plus sign
+    alternate: bool, // `#`: prefix hex and octal with 0x and 0, keep trailing zeros of %g
+    zero: bool,      // `0`: pad numbers with zeros
+    width: usize,
+    precision: Option<usize>,
+    kind: char,
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Conversion(Conversion),
+}
+
+#[derive(Debug, Clone)]
+pub struct Format {
+    pieces: Vec<Piece>,
+}
+
+impl Format {
+    /// Parses a printf format. Length modifiers such as the `l` of `%ld` are accepted and
+    /// ignored, since values carry their own size.
+    pub fn parse(format: &str) -> Result<Format, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                text.push(c);
+                continue;
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                text.push('%');
+                continue;
+            }
+            let mut conversion = Conversion {
+                left: false,
+                plus: false,
+                space: false,
+                alternate: false,
+                zero: false,
+                width: 0,
+                precision: None,
+                kind: ' ',
+            };
+            while let Some(&flag) = chars.peek() {
+                match flag {
+                    '-' => conversion.left = true,
+                    '+' => conversion.plus = true,
+                    ' ' => conversion.space = true,
+                    '#' => conversion.alternate = true,
+                    '0' => conversion.zero = true,
+                    _ => break,
+                }
+                chars.next();
+            }
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                conversion.width = conversion.width * 10 + digit as usize;
+                chars.next();
+            }
+            if chars.peek() == Some(&'.') {
+                chars.next();
+                let mut precision = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    precision = precision * 10 + digit as usize;
+                    chars.next();
+                }
+                conversion.precision = Some(precision);
+            }
+            while chars.peek().is_some_and(|c| "hlLqjzt".contains(*c)) {
+                chars.next();
+            }
+            conversion.kind = match chars.next() {
+                Some(kind) if "diuxXocsfFeEgGp".contains(kind) => kind,
+                Some(kind) => return Err(format!("Unrecognized format specifier '{}' in printf", kind)),
+                None => return Err("Incomplete format specifier at end of format string".to_string()),
+            };
+            if !text.is_empty() {
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+            }
+            pieces.push(Piece::Conversion(conversion));
+        }
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Format { pieces })
+    }
+
+    /// Returns the number of values the format takes.
+    pub fn conversions(&self) -> usize {
+        self.pieces.iter().filter(|piece| matches!(piece, Piece::Conversion(_))).count()
+    }
+
+    /// Formats the values, which must be as many as the format takes. `%s` reads the string a
+    /// pointer points to with read_string.
+    pub fn format(&self, values: &[Value], read_string: impl Fn(u64) -> Option<String>) -> Result<String, String> {
+        if values.len() != self.conversions() {
+            return Err("Wrong number of arguments for specified format-string".to_string());
+        }
+        let mut out = String::new();
+        let mut values = values.iter();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => out += text,
+                Piece::Conversion(conversion) => out += &conversion.format(values.next().unwrap(), &read_string)?,
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Conversion {
+    fn format(&self, value: &Value, read_string: &impl Fn(u64) -> Option<String>) -> Result<String, String> {
+        let not_a_number = || format!("Value can't be converted to integer for %{}", self.kind);
+        let (sign, prefix, mut body) = match self.kind {
+            'd' | 'i' => {
+                let v = value.as_int().ok_or_else(not_a_number)?;
+                (self.sign(v < 0), "", self.digits((v as i128).abs().to_string()))
+            }
+            'u' => ("", "", self.digits((value.as_int().ok_or_else(not_a_number)? as u64).to_string())),
+            'x' | 'X' | 'o' => {
+                let v = value.as_int().ok_or_else(not_a_number)? as u64;
+                let digits = match self.kind {
+                    'x' => format!("{:x}", v),
+                    'X' => format!("{:X}", v),
+                    _ => format!("{:o}", v),
+                };
+                let prefix = match self.kind {
+                    _ if !self.alternate || v == 0 => "",
+                    'x' => "0x",
+                    'X' => "0X",
+                    _ => "0",
+                };
+                ("", prefix, self.digits(digits))
+            }
+            'p' => match value {
+                Value::Pointer(v) => ("", "", format!("{:#x}", v)),
+                value => ("", "", format!("{:#x}", value.as_int().ok_or_else(not_a_number)?)),
+            },
+            'c' => match value.as_int() {
+                Some(v) => ("", "", (v as u8 as char).to_string()),
+                None => ("", "", value.to_string()),
+            },
+            's' => {
+                let string = match value {
+                    Value::Pointer(0) => "(null)".to_string(),
+                    Value::Pointer(v) => read_string(*v).ok_or(format!("Cannot access memory at address {:#x}", v))?,
+                    value => value.to_string(),
+                };
+                match self.precision {
+                    Some(precision) => ("", "", string.chars().take(precision).collect()),
+                    None => ("", "", string),
+                }
+            }
+            _ => {
+                let v = value
+                    .as_float()
+                    .ok_or_else(|| format!("Value can't be converted to float for %{}", self.kind))?;
+                (self.sign(v.is_sign_negative()), "", self.float(v.abs()))
+            }
+        };
+        if "FEG".contains(self.kind) {
+            body = body.to_uppercase();
+        }
+        let len = sign.len() + prefix.len() + body.chars().count();
+        let padding = self.width.saturating_sub(len);
+        // Zeros go between the sign and the digits, and aren't used when the precision is given
+        let zero_pad = self.zero && !self.left && !"scp".contains(self.kind)
+            && (self.precision.is_none() || "fFeEgG".contains(self.kind));
+        Ok(if self.left {
+            format!("{}{}{}{}", sign, prefix, body, " ".repeat(padding))
+        } else if zero_pad {
+            format!("{}{}{}{}", sign, prefix, "0".repeat(padding), body)
+        } else {
+            format!("{}{}{}{}", " ".repeat(padding), sign, prefix, body)
+        })
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        }
+    }
+
+    /// Pads the digits of an integer to the minimum number the precision asks for.
+    fn digits(&self, digits: String) -> String {
+        match self.precision {
+            Some(0) if digits == "0" => String::new(),
+            Some(precision) if precision > digits.len() => "0".repeat(precision - digits.len()) + &digits,
+            _ => digits,
+        }
+    }
+
+    /// Formats a non-negative floating point number for %f, %e or %g.
+    fn float(&self, v: f64) -> String {
+        if !v.is_finite() {
+            return if v.is_nan() { "nan" } else { "inf" }.to_string();
+        }
+        let precision = self.precision.unwrap_or(6);
+        match self.kind.to_ascii_lowercase() {
+            'f' => format!("{:.*}", precision, v),
+            'e' => exponential(v, precision).0,
+            _ => {
+                // %g uses %e for very large or small exponents and %f otherwise, then drops
+                // trailing zeros
+                let precision = precision.max(1);
+                let exponent = exponential(v, precision - 1).1;
+                let formatted = if exponent < -4 || exponent >= precision as i32 {
+                    exponential(v, precision - 1).0
+                } else {
+                    format!("{:.*}", (precision as i32 - 1 - exponent) as usize, v)
+                };
+                if self.alternate {
+                    return formatted;
+                }
+                match formatted.find('e') {
+                    Some(idx) => trim_fraction(&formatted[..idx]) + &formatted[idx..],
+                    None => trim_fraction(&formatted),
+                }
+            }
+        }
+    }
+}
+
+/// Formats v as C's %e does, with at least two digits of exponent. Also returns the exponent.
+fn exponential(v: f64, precision: usize) -> (String, i32) {
+    let formatted = format!("{:.*e}", precision, v);
+    let (mantissa, exponent) = formatted.split_at(formatted.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    (format!("{}e{}{:02}", mantissa, sign, exponent.abs()), exponent)
+}
+
+/// Drops the trailing zeros of a decimal fraction, and the point if nothing is left after it.
+fn trim_fraction(number: &str) -> String {
+    if !number.contains('.') {
+        return number.to_string();
+    }
+    number.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printf(format: &str, values: &[Value]) -> Result<String, String> {
+        let read_string = |addr| if addr == 0x1000 { Some("ab".to_string()) } else { None };
+        Format::parse(format)?.format(values, read_string)
+    }
+
+    #[test]
+    fn counts_conversions() {
+        assert_eq!(Format::parse("x=%d y=%5.2f %% %s\n").unwrap().conversions(), 3);
+        assert_eq!(Format::parse("no conversions").unwrap().conversions(), 0);
+    }
+
+    #[test]
+    fn formats_integers() {
+        assert_eq!(
+            printf(
+                "%5d|%-5d|%05d|%ld",
+                &[Value::Int(42), Value::Int(42), Value::Int(42), Value::Int(-1)]
+            )
+            .unwrap(),
+            "   42|42   |00042|-1"
+        );
+        assert_eq!(
+            printf("%+d|% d|%+05d", &[Value::Int(5), Value::Int(5), Value::Int(-5)]).unwrap(),
+            "+5| 5|-0005"
+        );
+        assert_eq!(
+            printf(
+                "%#x %#o %X %x",
+                &[Value::Int(255), Value::Int(8), Value::Int(255), Value::Int(-1)]
+            )
+            .unwrap(),
+            "0xff 010 FF ffffffffffffffff"
+        );
+        assert_eq!(
+            printf("%.3d|%.0d|%u", &[Value::Int(7), Value::Int(0), Value::Int(-1)]).unwrap(),
+            "007||18446744073709551615"
+        );
+        assert_eq!(printf("%c%c", &[Value::Int(72), Value::Int(105)]).unwrap(), "Hi");
+        assert_eq!(printf("%p", &[Value::Pointer(0x1000)]).unwrap(), "0x1000");
+    }
+
+    #[test]
+    fn formats_floats() {
+        assert_eq!(
+            printf(
+                "%8.3f|%-8.1f|%08.2f",
+                &[Value::Float(12.34567), Value::Float(2.0), Value::Float(-1.5)]
+            )
+            .unwrap(),
+            "  12.346|2.0     |-0001.50"
+        );
+        assert_eq!(
+            printf("%e|%.2E", &[Value::Float(12345.678), Value::Float(0.000123)]).unwrap(),
+            "1.234568e+04|1.23E-04"
+        );
+        assert_eq!(
+            printf(
+                "%g|%g|%g|%g",
+                &[
+                    Value::Float(100.0),
+                    Value::Float(0.0001),
+                    Value::Float(0.00001),
+                    Value::Float(123456789.0)
+                ]
+            )
+            .unwrap(),
+            "100|0.0001|1e-05|1.23457e+08"
+        );
+        assert_eq!(
+            printf("%#g|%f", &[Value::Float(1.5), Value::Int(2)]).unwrap(),
+            "1.50000|2.000000"
+        );
+    }
+
+    #[test]
+    fn formats_strings() {
+        assert_eq!(
+            printf(
+                "[%-4s][%3s][%.1s]",
+                &[Value::Pointer(0x1000), Value::Pointer(0x1000), Value::Pointer(0x1000)]
+            )
+            .unwrap(),
+            "[ab  ][ ab][a]"
+        );
+        assert_eq!(printf("%s", &[Value::Pointer(0)]).unwrap(), "(null)");
+        assert_eq!(
+            printf("%s", &[Value::Aggregate("\"hi\"".to_string())]).unwrap(),
+            "\"hi\""
+        );
+        assert_eq!(
+            printf("%s", &[Value::Pointer(0x2000)]),
+            Err("Cannot access memory at address 0x2000".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_bad_formats_and_arguments() {
+        assert_eq!(
+            Format::parse("%y").unwrap_err(),
+            "Unrecognized format specifier 'y' in printf"
+        );
+        assert_eq!(
+            Format::parse("total %").unwrap_err(),
+            "Incomplete format specifier at end of format string"
+        );
+        assert_eq!(
+            printf("%d", &[]),
+            Err("Wrong number of arguments for specified format-string".to_string())
+        );
+        assert_eq!(
+            printf("%d", &[Value::Void]),
+            Err("Value can't be converted to integer for %d".to_string())
+        );
+        assert_eq!(
+            printf("%f", &[Value::Pointer(1)]),
+            Err("Value can't be converted to float for %f".to_string())
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! Descriptions of the running process from /proc and the dynamic linker, for `info proc`,
+//! `info proc mappings` and `info sharedlibrary`.
+
+use deet::inferior::Inferior;
+use deet::shared_library::{self, Module};
+use std::fs;
+use std::io;
+
+/// Fields of /proc/<pid>/status that `info proc` shows.
+const STATUS_FIELDS: &[&str] = &["State", "PPid", "Threads", "VmSize", "VmRSS"];
+
+/// Prints the process's id, command line, working directory, executable and a summary of its
+/// status.
+pub fn print_process(inferior: &Inferior) -> Result<(), io::Error> {
+    let pid = inferior.pid();
+    println!("process {}", pid);
+    // The arguments are separated by NULs, and the last ends with one
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid))?;
+    let args: Vec<String> = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect();
+    println!("cmdline = '{}'", args.join(" "));
+    println!("cwd = '{}'", fs::read_link(format!("/proc/{}/cwd", pid))?.display());
+    println!("exe = '{}'", fs::read_link(format!("/proc/{}/exe", pid))?.display());
+    for line in fs::read_to_string(format!("/proc/{}/status", pid))?.lines() {
+        let mut fields = line.splitn(2, ':');
+        let name = fields.next().unwrap_or("");
+        if STATUS_FIELDS.contains(&name) {
+            println!("{} = {}", name.to_lowercase(), fields.next().unwrap_or("").trim());
+        }
+    }
+    Ok(())
+}
+
+/// Prints the process's memory map, as /proc/<pid>/maps lists it.
+pub fn print_mappings(inferior: &Inferior) -> Result<(), io::Error> {
+    println!("process {}", inferior.pid());
+    println!("Mapped address spaces:");
+    println!();
+    println!("{:>18} {:>18} {:>10} {:>10}  {:<5}  objfile", "Start Addr", "End Addr", "Size", "Offset", "Perms");
+    for mapping in shared_library::mappings(inferior.pid())? {
+        println!(
+            "{:>18} {:>18} {:>10} {:>10}  {:<5}  {}",
+            format!("{:#x}", mapping.start),
+            format!("{:#x}", mapping.end),
+            format!("{:#x}", mapping.end - mapping.start),
+            format!("{:#x}", mapping.offset),
+            mapping.perms,
+            mapping.path
+        );
+    }
+    Ok(())
+}
+
+/// Prints the shared libraries in the dynamic linker's link map, with where their code is.
+pub fn print_shared_libraries(inferior: &Inferior) -> Result<(), nix::Error> {
+    // The first entry is the executable itself
+    let libraries: Vec<_> =
+        shared_library::link_map(inferior)?.into_iter().filter(|entry| !entry.path.is_empty()).collect();
+    if libraries.is_empty() {
+        println!("No shared libraries loaded at this time.");
+        return Ok(());
+    }
+    println!("{:<20}{:<20}Shared Object Library", "From", "To");
+    for library in libraries {
+        // The vDSO is listed, but has no file to read
+        match Module::load(&library.path, library.bias).and_then(|module| module.section(".text")) {
+            Some((start, end)) => println!("{:<#20x}{:<#20x}{}", start, end, library.path),
+            None => println!("{:<20}{:<20}{}", "", "", library.path),
+        }
+    }
+    Ok(())
+}
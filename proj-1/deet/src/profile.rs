@@ -0,0 +1,108 @@
+//! `deet profile`, a sampling profiler. The program is stopped at a fixed rate, its stack is walked
+//! the way `backtrace` does it, and the samples are written as folded stacks (one
+//! `main;func1;func2 12` line per distinct stack), which flame graph tools such as flamegraph.pl
+//! and inferno read.
+//!
+//! The program is stopped with SIGSTOP, which it may also send itself (as job control shells
+//! do), so each SIGSTOP's sender tells the samples apart from the program's own stops, which are
+//! passed on.
+
+use deet::dwarf_data::DwarfData;
+use deet::inferior::{Inferior, LaunchOptions, Redirections, Status};
+use nix::errno::Errno;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+pub struct ProfileOptions {
+    /// Samples taken per second
+    pub hz: u32,
+    /// File the folded stacks are written to
+    pub output: String,
+}
+
+/// Returns the folded stack of the frames at the given addresses, outermost function first.
+fn fold(debug_data: &DwarfData, addresses: &[usize]) -> String {
+    let mut names = Vec::new();
+    for (depth, addr) in addresses.iter().enumerate().rev() {
+        // Return addresses may be past the end of the calling function, or of the inlined call
+        let addr = if depth > 0 { addr - 1 } else { *addr };
+        names.push(debug_data.get_function_from_addr(addr).unwrap_or_else(|| "[unknown]".to_string()));
+        names.extend(debug_data.get_inlined_calls_at(addr).iter().map(|call| call.name.clone()));
+    }
+    names.join(";")
+}
+
+/// Where a SIGSTOP the program stopped with came from.
+enum StopOrigin {
+    /// Sent by us to take a sample
+    Sample,
+    /// Sent by the program or someone else, and not delivered yet
+    Program,
+    /// Delivered already: the program stopped, which ptrace reports as a stop of its own
+    GroupStop,
+}
+
+fn stop_origin(pid: Pid) -> Result<StopOrigin, String> {
+    match ptrace::getsiginfo(pid) {
+        // kill records the sender, which for ours is this process
+        Ok(info) if info.si_code == libc::SI_USER && unsafe { info.si_pid() } as u32 == std::process::id() => Ok(StopOrigin::Sample),
+        Ok(_) => Ok(StopOrigin::Program),
+        Err(nix::Error::Sys(Errno::EINVAL)) => Ok(StopOrigin::GroupStop),
+        Err(err) => Err(format!("Could not read the program's signal: {}", err)),
+    }
+}
+
+/// Runs target with args to completion, sampling its stack options.hz times a second.
+pub fn profile(target: &str, args: &[String], options: &ProfileOptions) -> Result<(), String> {
+    let debug_data = DwarfData::from_file(target).map_err(|err| format!("Could not load {}: {:?}", target, err))?;
+    let (args, redirections) = Redirections::parse(args)?;
+    let inferior = Inferior::new(target, &args, &LaunchOptions::default(), &redirections).map_err(|err| err.to_string())?;
+    let interval = Duration::from_secs_f64(1.0 / options.hz.max(1) as f64);
+
+    let mut samples: HashMap<String, usize> = HashMap::new();
+    let mut total = 0;
+    let status = 'run: loop {
+        inferior.resume(None).map_err(|err| format!("Could not resume the program: {}", err))?;
+        thread::sleep(interval);
+        inferior.interrupt().ok();
+        // Other signals may arrive before our SIGSTOP does; pass them on and keep waiting
+        loop {
+            match inferior.wait(None).map_err(|err| format!("Could not wait for the program: {}", err))? {
+                Status::Stopped(Signal::SIGSTOP, ..) => match stop_origin(inferior.pid())? {
+                    StopOrigin::Sample => break,
+                    StopOrigin::Program => {
+                        inferior.resume(Some(Signal::SIGSTOP)).map_err(|err| format!("Could not resume the program: {}", err))?;
+                    }
+                    // Without PTRACE_SEIZE, resuming lets it carry on rather than stay stopped
+                    StopOrigin::GroupStop => {
+                        inferior.resume(None).map_err(|err| format!("Could not resume the program: {}", err))?;
+                    }
+                },
+                Status::Stopped(other, ..) => {
+                    inferior.resume(Some(other)).map_err(|err| format!("Could not resume the program: {}", err))?;
+                }
+                status => break 'run status,
+            }
+        }
+        if let Ok(addresses) = inferior.stack_addresses(&debug_data) {
+            *samples.entry(fold(&debug_data, &addresses)).or_insert(0) += 1;
+            total += 1;
+        }
+    };
+    match status {
+        Status::Exited(exit_code) => println!("Child exited (status {})", exit_code),
+        Status::Signaled(signal) => println!("Child exited due to signal {}", signal),
+        Status::Stopped(..) | Status::Event(..) => {}
+    }
+
+    let folded: BTreeMap<String, usize> = samples.into_iter().collect();
+    let contents: String = folded.iter().map(|(stack, count)| format!("{} {}\n", stack, count)).collect();
+    fs::write(&options.output, contents).map_err(|err| format!("Could not write {}: {}", options.output, err))?;
+    println!("Wrote {} samples of {} stacks to {}", total, folded.len(), options.output);
+    Ok(())
+}
@@ -0,0 +1,599 @@
+//! Process record: while recording, the inferior is single-stepped and the state each instruction
+//! overwrites is logged, so that execution can be undone one instruction at a time.
+//!
+//! Rather than fully decoding instructions, we work out a superset of the memory an instruction
+//! may write: its memory operand (if any), the top of the stack, the destination of string
+//! instructions and the buffers of system calls that fill memory. The words of those ranges that
+//! actually changed after the step are what gets logged. System calls we don't model, and writes
+//! larger than we save, stop recording with an error before they run, so that the log never has
+//! gaps. Changes to the memory map itself (mmap, munmap, brk) aren't undone. Only x86-64
+//! instructions are decoded, so other architectures can't be recorded.
+
+use crate::arch::{FpRegisters, Registers};
+use std::collections::{BTreeSet, VecDeque};
+#[cfg(target_arch = "x86_64")]
+use std::convert::TryInto;
+
+/// Number of instructions kept; older ones are forgotten, like gdb's `record full insn-number-max`
+const MAX_ENTRIES: usize = 200_000;
+/// How much of a memory operand is saved. This covers the largest (AVX-512) operands.
+#[cfg(target_arch = "x86_64")]
+const OPERAND_SIZE: usize = 64;
+/// How much is saved for the xsave family, whose state area is larger than other operands
+#[cfg(target_arch = "x86_64")]
+const XSAVE_SIZE: usize = 4096;
+/// Most memory saved for a repeated string instruction or a system call. Those that may write
+/// more stop recording.
+#[cfg(target_arch = "x86_64")]
+const MAX_BUFFER_SIZE: usize = 1024 * 1024;
+/// Bytes below the stack pointer that push, call and enter may write
+#[cfg(target_arch = "x86_64")]
+const STACK_SIZE: usize = 64;
+
+#[cfg(target_arch = "x86_64")]
+const EFLAGS_DF: u64 = 0x400;
+
+/// The state an instruction overwrote.
+pub struct Entry {
+    pub regs: Registers,
+    /// Floating point registers, if the instruction changed them
+    pub fpregs: Option<Box<FpRegisters>>,
+    /// Previous contents of the words of memory it changed
+    pub memory: Vec<(usize, u64)>,
+}
+
+#[derive(Default)]
+pub struct Recording {
+    entries: VecDeque<Entry>,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording::default()
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Removes the entry of the last instruction executed.
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    /// Returns the registers from before each instruction executed, the last one first.
+    pub fn registers(&self) -> impl Iterator<Item = &Registers> {
+        self.entries.iter().rev().map(|entry| &entry.regs)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Returns the value of a general purpose register by its number in instruction encodings.
+#[cfg(target_arch = "x86_64")]
+fn register(regs: &libc::user_regs_struct, number: u8) -> u64 {
+    match number {
+        0 => regs.rax,
+        1 => regs.rcx,
+        2 => regs.rdx,
+        3 => regs.rbx,
+        4 => regs.rsp,
+        5 => regs.rbp,
+        6 => regs.rsi,
+        7 => regs.rdi,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        _ => regs.r15,
+    }
+}
+
+/// Returns true if the one-byte opcode is followed by a ModRM byte.
+#[cfg(target_arch = "x86_64")]
+fn has_modrm(opcode: u8) -> bool {
+    match opcode {
+        0x00..=0x3f => opcode & 0x7 < 4,
+        0x62 | 0x63 | 0x69 | 0x6b | 0x80..=0x8f | 0xc0 | 0xc1 | 0xc6 | 0xc7 => true,
+        0xd0..=0xd3 | 0xd8..=0xdf | 0xf6 | 0xf7 | 0xfe | 0xff => true,
+        _ => false,
+    }
+}
+
+/// Returns true if the two-byte opcode `0f xx` is followed by a ModRM byte.
+#[cfg(target_arch = "x86_64")]
+fn has_modrm_0f(opcode: u8) -> bool {
+    !matches!(
+        opcode,
+        0x05..=0x09 | 0x0b | 0x0e | 0x30..=0x37 | 0x77 | 0x80..=0x8f | 0xa0..=0xa2 | 0xa8..=0xaa | 0xc8..=0xcf
+    )
+}
+
+/// Largest iovec array accepted, as the kernel's IOV_MAX
+#[cfg(target_arch = "x86_64")]
+const MAX_IOVECS: u64 = 1024;
+
+/// Reads the (base, length) pairs of an iovec array.
+#[cfg(target_arch = "x86_64")]
+fn iovecs(addr: u64, count: u64, read_word: &dyn Fn(u64) -> Option<u64>) -> Option<Vec<(u64, usize)>> {
+    if count > MAX_IOVECS {
+        return None;
+    }
+    (0..count)
+        .map(|i| Some((read_word(addr + 16 * i)?, read_word(addr + 16 * i + 8)? as usize)))
+        .collect()
+}
+
+/// Returns the memory a system call may write, or why it can't be recorded. Calls that write no
+/// memory of the process give no ranges. Unknown calls are refused rather than stepped over,
+/// since undoing them would leave memory as they left it.
+#[cfg(target_arch = "x86_64")]
+fn syscall_writes(regs: &libc::user_regs_struct, read_word: &dyn Fn(u64) -> Option<u64>) -> Result<Vec<(u64, usize)>, String> {
+    let number = regs.rax as i64;
+    let unreadable = || format!("Cannot read the arguments of system call {}", number);
+    // Output parameters the caller may leave null
+    let optional = |addr: u64, len: usize| if addr == 0 { vec![] } else { vec![(addr, len)] };
+    let ranges = match number {
+        libc::SYS_write | libc::SYS_pwrite64 | libc::SYS_writev | libc::SYS_pwritev | libc::SYS_pwritev2
+        | libc::SYS_open | libc::SYS_openat | libc::SYS_openat2 | libc::SYS_close | libc::SYS_close_range
+        | libc::SYS_lseek | libc::SYS_mmap | libc::SYS_mprotect | libc::SYS_munmap | libc::SYS_mremap
+        | libc::SYS_brk | libc::SYS_madvise | libc::SYS_msync | libc::SYS_access | libc::SYS_faccessat
+        | libc::SYS_faccessat2 | libc::SYS_dup | libc::SYS_dup2 | libc::SYS_dup3 | libc::SYS_exit
+        | libc::SYS_exit_group | libc::SYS_getpid | libc::SYS_getppid | libc::SYS_gettid | libc::SYS_getuid
+        | libc::SYS_geteuid | libc::SYS_getgid | libc::SYS_getegid | libc::SYS_getpgrp | libc::SYS_getpgid
+        | libc::SYS_getsid | libc::SYS_setsid | libc::SYS_setpgid | libc::SYS_kill | libc::SYS_tkill
+        | libc::SYS_tgkill | libc::SYS_sched_yield | libc::SYS_set_tid_address | libc::SYS_set_robust_list
+        | libc::SYS_rseq | libc::SYS_unlink | libc::SYS_unlinkat | libc::SYS_mkdir | libc::SYS_mkdirat
+        | libc::SYS_rmdir | libc::SYS_rename | libc::SYS_renameat | libc::SYS_renameat2 | libc::SYS_chdir
+        | libc::SYS_fchdir | libc::SYS_chmod | libc::SYS_fchmod | libc::SYS_fchmodat | libc::SYS_chown
+        | libc::SYS_fchown | libc::SYS_truncate | libc::SYS_ftruncate | libc::SYS_fsync | libc::SYS_fdatasync
+        | libc::SYS_flock | libc::SYS_socket | libc::SYS_connect | libc::SYS_bind | libc::SYS_listen
+        | libc::SYS_shutdown | libc::SYS_sendto | libc::SYS_sendmsg | libc::SYS_setsockopt
+        | libc::SYS_epoll_create | libc::SYS_epoll_create1 | libc::SYS_epoll_ctl | libc::SYS_eventfd2
+        | libc::SYS_alarm | libc::SYS_pause | libc::SYS_umask | libc::SYS_rt_sigreturn | libc::SYS_rt_sigsuspend
+        | libc::SYS_fork | libc::SYS_vfork => vec![],
+        libc::SYS_read | libc::SYS_pread64 | libc::SYS_getdents64 => vec![(regs.rsi, regs.rdx as usize)],
+        libc::SYS_readv | libc::SYS_preadv | libc::SYS_preadv2 => {
+            iovecs(regs.rsi, regs.rdx, read_word).ok_or_else(unreadable)?
+        }
+        libc::SYS_recvfrom => {
+            let mut ranges = vec![(regs.rsi, regs.rdx as usize)];
+            ranges.extend(optional(regs.r8, 128));
+            ranges.extend(optional(regs.r9, 4));
+            ranges
+        }
+        libc::SYS_recvmsg => {
+            // struct msghdr: name, name length, iovecs, iovec count, control, control length, flags
+            let field = |offset: u64| read_word(regs.rsi + offset).ok_or_else(unreadable);
+            let mut ranges = vec![(regs.rsi, 56)];
+            ranges.extend(optional(field(0)?, field(8)? as u32 as usize));
+            ranges.extend(iovecs(field(16)?, field(24)?, read_word).ok_or_else(unreadable)?);
+            ranges.extend(optional(field(32)?, field(40)? as usize));
+            ranges
+        }
+        libc::SYS_accept | libc::SYS_accept4 | libc::SYS_getsockname | libc::SYS_getpeername => {
+            let mut ranges = optional(regs.rsi, 128);
+            ranges.extend(optional(regs.rdx, 4));
+            ranges
+        }
+        libc::SYS_getsockopt => {
+            let len = read_word(regs.r8).ok_or_else(unreadable)? as u32 as usize;
+            let mut ranges = optional(regs.r10, len);
+            ranges.extend(optional(regs.r8, 4));
+            ranges
+        }
+        libc::SYS_socketpair => vec![(regs.r10, 8)],
+        libc::SYS_pipe | libc::SYS_pipe2 => vec![(regs.rdi, 8)],
+        libc::SYS_getrandom | libc::SYS_getcwd => vec![(regs.rdi, regs.rsi as usize)],
+        libc::SYS_readlink => vec![(regs.rsi, regs.rdx as usize)],
+        libc::SYS_readlinkat => vec![(regs.rdx, regs.r10 as usize)],
+        libc::SYS_wait4 => {
+            let mut ranges = optional(regs.rsi, 4);
+            ranges.extend(optional(regs.r10, 144));
+            ranges
+        }
+        libc::SYS_waitid => {
+            let mut ranges = optional(regs.rdx, 128);
+            ranges.extend(optional(regs.r8, 144));
+            ranges
+        }
+        // struct epoll_event is packed into 12 bytes
+        libc::SYS_epoll_wait | libc::SYS_epoll_pwait | libc::SYS_epoll_pwait2 => {
+            vec![(regs.rsi, (regs.rdx as usize).saturating_mul(12))]
+        }
+        libc::SYS_poll => vec![(regs.rdi, (regs.rsi as usize).saturating_mul(8))],
+        libc::SYS_ppoll => {
+            let mut ranges = vec![(regs.rdi, (regs.rsi as usize).saturating_mul(8))];
+            ranges.extend(optional(regs.rdx, 16));
+            ranges
+        }
+        libc::SYS_select | libc::SYS_pselect6 => {
+            let mut ranges: Vec<(u64, usize)> =
+                [regs.rsi, regs.rdx, regs.r10].iter().flat_map(|addr| optional(*addr, 128)).collect();
+            ranges.extend(optional(regs.r8, 16));
+            ranges
+        }
+        libc::SYS_nanosleep | libc::SYS_clock_getres => optional(regs.rsi, 16),
+        libc::SYS_clock_nanosleep => optional(regs.r10, 16),
+        libc::SYS_clock_gettime => vec![(regs.rsi, 16)],
+        libc::SYS_gettimeofday => {
+            let mut ranges = optional(regs.rdi, 16);
+            ranges.extend(optional(regs.rsi, 8));
+            ranges
+        }
+        libc::SYS_time => optional(regs.rdi, 8),
+        libc::SYS_stat | libc::SYS_fstat | libc::SYS_lstat => vec![(regs.rsi, 144)],
+        libc::SYS_newfstatat => vec![(regs.rdx, 144)],
+        libc::SYS_statx => vec![(regs.r8, 256)],
+        libc::SYS_statfs | libc::SYS_fstatfs => vec![(regs.rsi, 120)],
+        libc::SYS_uname => vec![(regs.rdi, 390)],
+        libc::SYS_sysinfo => vec![(regs.rdi, 112)],
+        libc::SYS_getrusage => vec![(regs.rsi, 144)],
+        libc::SYS_getrlimit => vec![(regs.rsi, 16)],
+        libc::SYS_prlimit64 => optional(regs.r10, 16),
+        libc::SYS_rt_sigaction => optional(regs.rdx, 32),
+        libc::SYS_rt_sigprocmask => optional(regs.rdx, 8),
+        libc::SYS_sigaltstack => optional(regs.rsi, 24),
+        libc::SYS_sched_getaffinity => vec![(regs.rdx, regs.rsi as usize)],
+        libc::SYS_sendfile => optional(regs.rdx, 8),
+        libc::SYS_clone if regs.rdi & libc::CLONE_PARENT_SETTID as u64 != 0 => vec![(regs.rdx, 4)],
+        libc::SYS_clone => vec![],
+        libc::SYS_arch_prctl => match regs.rdi {
+            // ARCH_GET_FS and ARCH_GET_GS
+            0x1003 | 0x1004 => vec![(regs.rsi, 8)],
+            _ => vec![],
+        },
+        libc::SYS_fcntl => match regs.rsi as i32 {
+            libc::F_GETLK => vec![(regs.rdx, 32)],
+            // F_GETOWN_EX
+            16 => vec![(regs.rdx, 8)],
+            _ => vec![],
+        },
+        libc::SYS_futex => match regs.rsi as i32 & 0x7f {
+            // FUTEX_WAKE_OP changes the second futex word
+            5 => vec![(regs.r8, 4)],
+            _ => vec![],
+        },
+        libc::SYS_ioctl => match regs.rsi {
+            libc::TCGETS => vec![(regs.rdx, 60)],
+            libc::TIOCGWINSZ => vec![(regs.rdx, 8)],
+            libc::FIONREAD | libc::TIOCGPGRP => vec![(regs.rdx, 4)],
+            libc::TCSETS | libc::TCSETSW | libc::TCSETSF | libc::TIOCSWINSZ | libc::TIOCSPGRP => vec![],
+            request => return Err(format!("Process record does not support ioctl request {:#x}", request)),
+        },
+        libc::SYS_execve | libc::SYS_execveat => {
+            return Err("Process record cannot step back over exec, which replaces the program".to_string())
+        }
+        _ => return Err(format!("Process record does not support system call {}", number)),
+    };
+    let total = ranges.iter().fold(0usize, |total, (_, len)| total.saturating_add(*len));
+    if total > MAX_BUFFER_SIZE {
+        return Err(format!(
+            "System call {} may write {} bytes, more than the {} process record saves",
+            number, total, MAX_BUFFER_SIZE
+        ));
+    }
+    Ok(ranges)
+}
+
+/// Returns the memory ranges (address, length) that the instruction at the start of code may
+/// write when executed with the given registers, reading what system calls point to with
+/// read_word. Fails if the instruction writes memory we can't account for.
+#[cfg(target_arch = "x86_64")]
+pub fn written_ranges(
+    code: &[u8],
+    regs: &libc::user_regs_struct,
+    read_word: &dyn Fn(u64) -> Option<u64>,
+) -> Result<Vec<(u64, usize)>, String> {
+    let byte = |i: usize| code.get(i).copied().unwrap_or(0);
+    let mut ranges = vec![(regs.rsp.wrapping_sub(STACK_SIZE as u64), STACK_SIZE)];
+
+    let mut i = 0;
+    let mut addr32 = false;
+    let mut operand16 = false;
+    let mut rep = false;
+    let mut segment_base = 0;
+    loop {
+        match byte(i) {
+            0x67 => addr32 = true,
+            0xf2 | 0xf3 => rep = true,
+            0x64 => segment_base = regs.fs_base,
+            0x65 => segment_base = regs.gs_base,
+            0x66 => operand16 = true,
+            0xf0 | 0x2e | 0x36 | 0x3e | 0x26 => {}
+            _ => break,
+        }
+        i += 1;
+    }
+    // REX prefix, as W R X B bits
+    let mut rex = 0;
+    if byte(i) & 0xf0 == 0x40 {
+        rex = byte(i) & 0xf;
+        i += 1;
+    }
+
+    let opcode = byte(i);
+    // Where the ModRM byte is, if there is one
+    let mut modrm = None;
+    let mut evex = false;
+    let mut operand_size = OPERAND_SIZE;
+    match opcode {
+        0x0f => match byte(i + 1) {
+            0x38 | 0x3a => modrm = Some(i + 3),
+            0x05 => ranges.extend(syscall_writes(regs, read_word)?),
+            0x34 => return Err("Process record does not support sysenter".to_string()),
+            op => {
+                if op == 0xae || op == 0xc7 {
+                    operand_size = XSAVE_SIZE;
+                }
+                if has_modrm_0f(op) {
+                    modrm = Some(i + 2);
+                }
+            }
+        },
+        // VEX prefixes store the inverted R, X and B bits, and EVEX more besides
+        0xc5 => modrm = Some(i + 3),
+        0xc4 => {
+            rex = (!byte(i + 1) >> 5) & 0x7;
+            modrm = Some(i + 4);
+        }
+        0x62 => {
+            rex = (!byte(i + 1) >> 5) & 0x7;
+            evex = true;
+            modrm = Some(i + 5);
+        }
+        0xcd if byte(i + 1) == 0x80 => {
+            return Err("Process record does not support 32-bit system calls".to_string())
+        }
+        // movs and stos write to [rdi], rcx times with a rep prefix
+        0xa4 | 0xa5 | 0xaa | 0xab => {
+            let size = match opcode {
+                0xa4 | 0xaa => 1,
+                _ if rex & 8 != 0 => 8,
+                _ if operand16 => 2,
+                _ => 4,
+            };
+            let count = if rep { regs.rcx as usize } else { 1 };
+            let len = count.saturating_mul(size);
+            if len > MAX_BUFFER_SIZE {
+                return Err(format!("String instruction writes {} bytes, more than the {} process record saves", len, MAX_BUFFER_SIZE));
+            }
+            let start = if regs.eflags & EFLAGS_DF != 0 {
+                regs.rdi.wrapping_sub(len as u64).wrapping_add(size as u64)
+            } else {
+                regs.rdi
+            };
+            ranges.push((start, len));
+        }
+        // mov to an absolute address
+        0xa2 | 0xa3 => {
+            let addr = if addr32 {
+                u32::from_le_bytes(code.get(i + 1..i + 5).and_then(|b| b.try_into().ok()).unwrap_or([0; 4])) as u64
+            } else {
+                u64::from_le_bytes(code.get(i + 1..i + 9).and_then(|b| b.try_into().ok()).unwrap_or([0; 8]))
+            };
+            ranges.push((addr.wrapping_add(segment_base), 8));
+        }
+        op if has_modrm(op) => modrm = Some(i + 1),
+        _ => {}
+    }
+
+    let modrm_pos = match modrm {
+        Some(pos) => pos,
+        None => return Ok(ranges),
+    };
+    let modrm = byte(modrm_pos);
+    let mode = modrm >> 6;
+    if mode == 3 {
+        return Ok(ranges);
+    }
+    let rex_x = (rex >> 1) & 1;
+    let rex_b = rex & 1;
+    let mut j = modrm_pos + 1;
+    let mut rip_relative = false;
+    let mut addr = if modrm & 7 == 4 {
+        let sib = byte(j);
+        j += 1;
+        let index = ((sib >> 3) & 7) | (rex_x << 3);
+        let index_value = if index == 4 { 0 } else { register(regs, index) << (sib >> 6) };
+        let base_value = if sib & 7 == 5 && mode == 0 {
+            0
+        } else {
+            register(regs, (sib & 7) | (rex_b << 3))
+        };
+        base_value.wrapping_add(index_value)
+    } else if modrm & 7 == 5 && mode == 0 {
+        rip_relative = true;
+        0
+    } else {
+        register(regs, (modrm & 7) | (rex_b << 3))
+    };
+    let (disp, disp_len) = match mode {
+        1 => (byte(j) as i8 as i64, 1),
+        2 => (i32::from_le_bytes([byte(j), byte(j + 1), byte(j + 2), byte(j + 3)]) as i64, 4),
+        _ if rip_relative || (modrm & 7 == 4 && byte(modrm_pos + 1) & 7 == 5) => {
+            (i32::from_le_bytes([byte(j), byte(j + 1), byte(j + 2), byte(j + 3)]) as i64, 4)
+        }
+        _ => (0, 0),
+    };
+    if rip_relative {
+        // The displacement is relative to the end of the instruction, which may still have an
+        // immediate of up to 4 bytes after it
+        addr = regs.rip + (j + disp_len) as u64;
+        operand_size += 4;
+    }
+    let (low, high) = if evex && mode == 1 {
+        // EVEX scales 8-bit displacements by the operand size, which can be up to 64
+        (disp.min(disp * 64), disp.max(disp * 64))
+    } else {
+        (disp, disp)
+    };
+    let mut start = addr.wrapping_add(low as u64).wrapping_add(segment_base);
+    if addr32 {
+        start &= 0xffff_ffff;
+    }
+    ranges.push((start, (high - low) as usize + operand_size));
+    Ok(ranges)
+}
+
+/// Returns the addresses of the words covering the ranges.
+pub fn words(ranges: &[(u64, usize)]) -> BTreeSet<usize> {
+    let mut words = BTreeSet::new();
+    for &(start, len) in ranges {
+        let start = start as usize & !7;
+        let end = (start as u64).saturating_add(len as u64 + 8) as usize;
+        words.extend((start..end).step_by(8));
+    }
+    words
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    const RSP: u64 = 0x7fff_0000;
+
+    fn registers() -> libc::user_regs_struct {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rsp = RSP;
+        regs.rip = 0x401000;
+        regs
+    }
+
+    fn no_memory(_: u64) -> Option<u64> {
+        None
+    }
+
+    /// Returns the ranges beyond the top of the stack, which every instruction may write.
+    fn ranges(code: &[u8], regs: &libc::user_regs_struct) -> Vec<(u64, usize)> {
+        let ranges = written_ranges(code, regs, &no_memory).unwrap();
+        assert_eq!(ranges[0], (RSP - STACK_SIZE as u64, STACK_SIZE));
+        ranges[1..].to_vec()
+    }
+
+    #[test]
+    fn push_and_call_write_the_stack() {
+        let regs = registers();
+        // push rbp
+        assert_eq!(ranges(&[0x55], &regs), vec![]);
+        // call rel32
+        assert_eq!(ranges(&[0xe8, 0x10, 0, 0, 0], &regs), vec![]);
+        let stack = written_ranges(&[0x55], &regs, &no_memory).unwrap()[0];
+        assert!(stack.0 <= RSP - 8 && RSP <= stack.0 + stack.1 as u64);
+    }
+
+    #[test]
+    fn mov_to_memory_writes_its_operand() {
+        let mut regs = registers();
+        regs.rbp = 0x7ffe_1000;
+        // mov [rbp-8], rax
+        assert_eq!(ranges(&[0x48, 0x89, 0x45, 0xf8], &regs), vec![(0x7ffe_0ff8, OPERAND_SIZE)]);
+        // mov [rax+rcx*8+0x10], rdx
+        regs.rax = 0x1000;
+        regs.rcx = 2;
+        assert_eq!(ranges(&[0x48, 0x89, 0x54, 0xc8, 0x10], &regs), vec![(0x1020, OPERAND_SIZE)]);
+        // mov [r12], eax, with REX.B selecting r12
+        regs.r12 = 0x5000;
+        assert_eq!(ranges(&[0x41, 0x89, 0x04, 0x24], &regs), vec![(0x5000, OPERAND_SIZE)]);
+        // mov [rip+0x100], rax, relative to the end of the 7-byte instruction
+        assert_eq!(ranges(&[0x48, 0x89, 0x05, 0x00, 0x01, 0, 0], &regs), vec![(0x401107, OPERAND_SIZE + 4)]);
+        // mov rax, rbx writes no memory
+        assert_eq!(ranges(&[0x48, 0x89, 0xd8], &regs), vec![]);
+    }
+
+    #[test]
+    fn string_instructions_write_rcx_elements() {
+        let mut regs = registers();
+        regs.rdi = 0x2000;
+        regs.rcx = 4;
+        // rep movsq
+        assert_eq!(ranges(&[0xf3, 0x48, 0xa5], &regs), vec![(0x2000, 32)]);
+        // rep stosb, backwards with the direction flag set
+        regs.eflags = EFLAGS_DF;
+        assert_eq!(ranges(&[0xf3, 0xaa], &regs), vec![(0x1ffd, 4)]);
+        // movsd without rep
+        regs.eflags = 0;
+        assert_eq!(ranges(&[0xa5], &regs), vec![(0x2000, 4)]);
+        regs.rcx = MAX_BUFFER_SIZE as u64;
+        assert!(written_ranges(&[0xf3, 0x48, 0xab], &regs, &no_memory).is_err());
+    }
+
+    #[test]
+    fn xsave_writes_its_state_area() {
+        let mut regs = registers();
+        regs.rdi = 0x3000;
+        // xsave [rdi]
+        assert_eq!(ranges(&[0x0f, 0xae, 0x27], &regs), vec![(0x3000, XSAVE_SIZE)]);
+        // xsave64 [rdi]
+        assert_eq!(ranges(&[0x48, 0x0f, 0xae, 0x27], &regs), vec![(0x3000, XSAVE_SIZE)]);
+    }
+
+    #[test]
+    fn system_calls_write_their_buffers() {
+        let mut regs = registers();
+        regs.rax = libc::SYS_read as u64;
+        regs.rsi = 0x4000;
+        regs.rdx = 100;
+        assert_eq!(ranges(&[0x0f, 0x05], &regs), vec![(0x4000, 100)]);
+        regs.rax = libc::SYS_write as u64;
+        assert_eq!(ranges(&[0x0f, 0x05], &regs), vec![]);
+        regs.rax = libc::SYS_ioctl as u64;
+        regs.rsi = libc::TCGETS;
+        regs.rdx = 0x5000;
+        assert_eq!(ranges(&[0x0f, 0x05], &regs), vec![(0x5000, 60)]);
+    }
+
+    #[test]
+    fn readv_writes_each_iovec() {
+        let mut regs = registers();
+        regs.rax = libc::SYS_readv as u64;
+        regs.rsi = 0x6000;
+        regs.rdx = 2;
+        let memory = |addr: u64| match addr {
+            0x6000 => Some(0x7000),
+            0x6008 => Some(10),
+            0x6010 => Some(0x8000),
+            0x6018 => Some(20),
+            _ => None,
+        };
+        let ranges = written_ranges(&[0x0f, 0x05], &regs, &memory).unwrap();
+        assert_eq!(ranges[1..], [(0x7000, 10), (0x8000, 20)]);
+        // The iovecs can't be read
+        assert!(written_ranges(&[0x0f, 0x05], &regs, &no_memory).is_err());
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_undo() {
+        let mut regs = registers();
+        regs.rax = libc::SYS_io_uring_enter as u64;
+        assert_eq!(
+            written_ranges(&[0x0f, 0x05], &regs, &no_memory),
+            Err(format!("Process record does not support system call {}", libc::SYS_io_uring_enter))
+        );
+        regs.rax = libc::SYS_read as u64;
+        regs.rdx = MAX_BUFFER_SIZE as u64 + 1;
+        assert!(written_ranges(&[0x0f, 0x05], &regs, &no_memory).is_err());
+        regs.rax = libc::SYS_ioctl as u64;
+        regs.rsi = 0xdead;
+        assert!(written_ranges(&[0x0f, 0x05], &regs, &no_memory).is_err());
+        assert!(written_ranges(&[0xcd, 0x80], &regs, &no_memory).is_err());
+    }
+
+    #[test]
+    fn words_cover_ranges() {
+        assert_eq!(words(&[(0x1004, 8)]).into_iter().collect::<Vec<_>>(), vec![0x1000, 0x1008]);
+        assert_eq!(words(&[(0x1000, 4), (0x1008, 8)]).into_iter().collect::<Vec<_>>(), vec![0x1000, 0x1008, 0x1010]);
+    }
+}
@@ -0,0 +1,78 @@
+//! Sessions, which keep the breakpoints, displays and settings of a target from one run of deet to
+//! the next. A session is saved as the deet commands that recreate it, and replayed when the
+//! target is opened again. Breakpoints are saved by the location they were set at rather than by
+//! address, so replaying them finds them again after the target is recompiled.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Returns the file the session of target is kept in. Targets are told apart by their full path,
+/// and the file is named after the target to make it easy to find.
+pub fn session_path(target: &str) -> Option<PathBuf> {
+    let data_dir = match std::env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".local").join("share"),
+    };
+    let target = fs::canonicalize(target).ok()?;
+    // FNV-1a, which unlike the standard library's hasher is sure to be the same in every build
+    let hash = target
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3));
+    let name = target.file_name()?.to_string_lossy();
+    Some(data_dir.join("deet").join("sessions").join(format!("{}-{:016x}", name, hash)))
+}
+
+/// Reads the commands of a saved session. Blank lines and `#` comments are skipped.
+pub fn load(path: &Path) -> Vec<String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
+    };
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Saves the commands of a session, or removes the session if there are none.
+pub fn save(path: &Path, target: &str, commands: &[String]) -> Result<(), std::io::Error> {
+    if commands.is_empty() {
+        return match fs::remove_file(path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::File::create(path)?;
+    writeln!(file, "# deet session for {}", target)?;
+    for command in commands {
+        writeln!(file, "{}", command)?;
+    }
+    Ok(())
+}
+
+/// Quotes text as a C string literal, the way dprintf formats are written.
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\t' => quoted += "\\t",
+            '\r' => quoted += "\\r",
+            '\x07' => quoted += "\\a",
+            '\x1b' => quoted += "\\e",
+            '\0' => quoted += "\\0",
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,312 @@
+//! The shared libraries a process has loaded, as listed in /proc/<pid>/maps and the dynamic
+//! linker's link map, and the functions and sections they define. deet only reads debugging
+//! information for the executable itself, so this is all it knows about code in libraries.
+
+use crate::dwarf_data::{demangle, ElfSymbol};
+use crate::inferior::Inferior;
+use crate::unwind::{CallFrames, FrameRule};
+use nix::sys::ptrace;
+use nix::unistd::Pid;
+use object::{Object, ObjectSection, ObjectSegment};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// One line of /proc/<pid>/maps.
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    pub perms: String, // As in `r-xp`
+    pub offset: usize,
+    pub path: String, // Empty for anonymous mappings
+}
+
+/// Returns the memory mappings of the process with the given pid, in address order.
+pub fn mappings(pid: Pid) -> Result<Vec<Mapping>, std::io::Error> {
+    let contents = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    Ok(contents.lines().filter_map(parse_mapping).collect())
+}
+
+fn parse_mapping(line: &str) -> Option<Mapping> {
+    let mut fields = line.splitn(6, ' ');
+    let range = fields.next()?;
+    let (start, end) = range.split_at(range.find('-')?);
+    let perms = fields.next()?.to_string();
+    let offset = usize::from_str_radix(fields.next()?, 16).ok()?;
+    let path = fields.nth(2).unwrap_or("").trim_start().to_string();
+    Some(Mapping {
+        start: usize::from_str_radix(start, 16).ok()?,
+        end: usize::from_str_radix(&end[1..], 16).ok()?,
+        perms,
+        offset,
+        path,
+    })
+}
+
+/// Returns the mapping containing addr, among those of a process.
+pub fn mapping_at(mappings: &[Mapping], addr: usize) -> Option<&Mapping> {
+    mappings.iter().find(|mapping| mapping.start <= addr && addr < mapping.end)
+}
+
+/// The memory map of a process and the ELF files mapped into it, for telling what is at an
+/// address. The mappings are read once per stop, since the process changes them as it runs.
+/// Each file is loaded the first time an address in it is looked up, and kept for as long as
+/// it's mapped at the same address.
+#[derive(Default)]
+pub struct ModuleCache {
+    mappings: RefCell<Option<Rc<Vec<Mapping>>>>,
+    modules: RefCell<LoadedModules>,
+}
+
+/// Files by path and the address their first mapping starts at, with None for those that can't
+/// be read
+type LoadedModules = HashMap<(String, usize), Option<Rc<Module>>>;
+
+impl ModuleCache {
+    /// Forgets the mappings, for when the process has run since they were read.
+    pub fn invalidate(&self) {
+        self.mappings.replace(None);
+    }
+
+    /// Returns the memory mappings of the process with the given pid, reading them if this is
+    /// the first time since it stopped. They're empty if they can't be read.
+    pub fn mappings(&self, pid: Pid) -> Rc<Vec<Mapping>> {
+        self.mappings
+            .borrow_mut()
+            .get_or_insert_with(|| Rc::new(mappings(pid).unwrap_or_default()))
+            .clone()
+    }
+
+    /// Returns the ELF file mapped at addr, if it's mapped from one that can be read.
+    pub fn module_at(&self, pid: Pid, addr: usize) -> Option<Rc<Module>> {
+        let mappings = self.mappings(pid);
+        let mapping = mapping_at(&mappings, addr).filter(|mapping| mapping.path.starts_with('/'))?;
+        let first = mappings.iter().find(|first| first.path == mapping.path && first.offset == 0)?;
+        self.modules
+            .borrow_mut()
+            .entry((first.path.clone(), first.start))
+            .or_insert_with(|| Module::load_at(&first.path, first.start).map(Rc::new))
+            .clone()
+    }
+
+    /// Describes the part of the process addr is in, as in `in section .text of /lib/libc.so.6`,
+    /// or `in [stack]` for memory that isn't mapped from a file.
+    pub fn describe_region(&self, pid: Pid, addr: usize) -> Option<String> {
+        let mappings = self.mappings(pid);
+        let mapping = mapping_at(&mappings, addr)?;
+        if !mapping.path.starts_with('/') {
+            return Some(match mapping.path.as_str() {
+                "" => "in an anonymous mapping".to_string(),
+                path => format!("in {}", path),
+            });
+        }
+        let module = self.module_at(pid, addr);
+        Some(match module.as_ref().and_then(|module| module.section_at(addr)) {
+            Some(section) => format!("in section {} of {}", section, mapping.path),
+            None => format!("in {}", mapping.path),
+        })
+    }
+}
+
+/// An ELF file loaded into a process, with the addresses its sections are loaded at.
+pub struct Module {
+    pub path: String,
+    pub bias: usize, // Difference between the loaded addresses and those in the file
+    // Names and loaded address ranges of the sections that are loaded
+    sections: Vec<(String, usize, usize)>,
+    // At the addresses in the file
+    call_frames: CallFrames,
+}
+
+impl Module {
+    /// Reads the sections of the ELF file at path, loaded bias bytes above where it was linked.
+    pub fn load(path: &str, bias: usize) -> Option<Module> {
+        Module::load_with(path, |_| bias)
+    }
+
+    /// Reads the sections of the ELF file at path, whose first segment is mapped at base.
+    pub fn load_at(path: &str, base: usize) -> Option<Module> {
+        // Position-independent files are linked at 0, and others at the address they're loaded at
+        Module::load_with(path, |first_segment| base.wrapping_sub(first_segment & !(PAGE_SIZE - 1)))
+    }
+
+    fn load_with(path: &str, bias: impl FnOnce(usize) -> usize) -> Option<Module> {
+        let file = fs::File::open(path).ok()?;
+        let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+        let object = object::File::parse(&mmap).ok()?;
+        let bias = bias(object.segments().map(|segment| segment.address()).min()? as usize);
+        let sections = object
+            .sections()
+            .filter(|section| section.address() != 0)
+            .filter_map(|section| {
+                let start = bias.wrapping_add(section.address() as usize);
+                Some((section.name().ok()?.to_string(), start, start + section.size() as usize))
+            })
+            .collect();
+        Some(Module { path: path.to_string(), bias, sections, call_frames: CallFrames::load(&object) })
+    }
+
+    /// Returns the name of the section containing the loaded address addr.
+    pub fn section_at(&self, addr: usize) -> Option<&str> {
+        let (name, _, _) = self.sections.iter().find(|(_, start, end)| *start <= addr && addr < *end)?;
+        Some(name)
+    }
+
+    /// Returns how to unwind the frame executing the instruction at the loaded address addr,
+    /// from the file's call frame information.
+    pub fn frame_rule(&self, addr: usize) -> Option<FrameRule> {
+        self.call_frames.frame_rule(addr.wrapping_sub(self.bias))
+    }
+
+    /// Returns the loaded address range of the named section.
+    pub fn section(&self, name: &str) -> Option<(usize, usize)> {
+        let (_, start, end) = self.sections.iter().find(|(section, _, _)| section == name)?;
+        Some((*start, *end))
+    }
+}
+
+const PAGE_SIZE: usize = 0x1000;
+const DT_NULL: usize = 0;
+const DT_DEBUG: usize = 21;
+/// Objects in the link map, beyond which it's taken to be corrupt
+const MAX_LINK_MAP_ENTRIES: usize = 4096;
+
+/// An object the dynamic linker has loaded, from its link map.
+pub struct LinkMapEntry {
+    pub path: String, // Empty for the executable
+    pub bias: usize,
+}
+
+/// Walks the dynamic linker's list of the objects it has loaded, which it points to from the
+/// DT_DEBUG entry of the executable's dynamic section. The list is empty for static executables,
+/// and until the dynamic linker has run.
+pub fn link_map(inferior: &Inferior) -> Result<Vec<LinkMapEntry>, nix::Error> {
+    let pid = inferior.pid();
+    let read_word = |addr: usize| ptrace::read(pid, addr as ptrace::AddressType).map(|word| word as usize);
+    let exe = fs::read_link(format!("/proc/{}/exe", pid)).ok().and_then(|path| path.to_str().map(str::to_string));
+    let dynamic = mappings(pid)
+        .ok()
+        .and_then(|mappings| mappings.into_iter().find(|mapping| Some(&mapping.path) == exe.as_ref()))
+        .and_then(|first| Module::load_at(&first.path, first.start))
+        .and_then(|module| module.section(".dynamic"));
+    let (mut entry, end) = match dynamic {
+        Some(dynamic) => dynamic,
+        None => return Ok(Vec::new()),
+    };
+    let mut r_debug = 0;
+    while entry < end {
+        match read_word(entry)? {
+            DT_NULL => break,
+            DT_DEBUG => r_debug = read_word(entry + 8)?,
+            _ => {}
+        }
+        entry += 16;
+    }
+    if r_debug == 0 {
+        return Ok(Vec::new());
+    }
+    // struct r_debug { int r_version; struct link_map *r_map; ... }
+    let mut link_map = read_word(r_debug + 8)?;
+    let mut entries = Vec::new();
+    while link_map != 0 && entries.len() < MAX_LINK_MAP_ENTRIES {
+        // struct link_map { ElfW(Addr) l_addr; char *l_name; ElfW(Dyn) *l_ld; struct link_map *l_next, *l_prev; }
+        let name = read_word(link_map + 8)?;
+        entries.push(LinkMapEntry {
+            path: inferior.read_c_string(name).unwrap_or_default(),
+            bias: read_word(link_map)?,
+        });
+        link_map = read_word(link_map + 24)?;
+    }
+    Ok(entries)
+}
+
+pub struct SharedLibrary {
+    pub path: String,
+    pub base: usize, // Address the library's first segment is loaded at
+    pub end: usize,  // End of its last segment
+    // Functions, sorted by address, at their addresses in the file
+    symbols: Vec<ElfSymbol>,
+}
+
+impl SharedLibrary {
+    /// Reads the functions of the ELF file at path, loaded at base.
+    pub fn load(path: &str, base: usize, end: usize) -> Option<SharedLibrary> {
+        let symbols = load_functions(path)?;
+        Some(SharedLibrary { path: path.to_string(), base, end, symbols })
+    }
+
+    /// Returns the loaded address of the function with the given raw name.
+    pub fn get_symbol_addr(&self, name: &str) -> Option<usize> {
+        let symbol = self.symbols.iter().find(|symbol| symbol.raw_name == name)?;
+        Some(self.base + symbol.address)
+    }
+
+    /// Returns the function containing the loaded address addr and the offset of addr
+    /// into it.
+    pub fn get_symbol_at(&self, addr: usize) -> Option<(&ElfSymbol, usize)> {
+        let file_addr = addr.checked_sub(self.base)?;
+        let symbol = self.symbols.iter().rev().find(|symbol| symbol.address <= file_addr)?;
+        Some((symbol, file_addr - symbol.address))
+    }
+
+    /// Describes the loaded address addr as `func+0x12 (libname.so)`.
+    pub fn describe_addr(&self, addr: usize) -> String {
+        let name = Path::new(&self.path).file_name().map_or(self.path.as_str().into(), |name| name.to_string_lossy());
+        match self.get_symbol_at(addr) {
+            Some((symbol, 0)) => format!("{} ({})", symbol.name, name),
+            Some((symbol, offset)) => format!("{}+{:#x} ({})", symbol.name, offset, name),
+            None => format!("{:#x} ({})", addr, name),
+        }
+    }
+}
+
+/// Returns the shared libraries the process with the given pid has loaded, with their functions. Libraries that can't be read are left out.
+pub fn loaded_libraries(pid: Pid) -> Result<Vec<SharedLibrary>, std::io::Error> {
+    let mut libraries: Vec<SharedLibrary> = Vec::new();
+    for mapping in mappings(pid)? {
+        if let Some(library) = libraries.iter_mut().find(|library| library.path == mapping.path) {
+            library.end = library.end.max(mapping.end);
+            continue;
+        }
+        let file_name = Path::new(&mapping.path).file_name().unwrap_or_default().to_string_lossy();
+        if mapping.offset != 0 || !(file_name.ends_with(".so") || file_name.contains(".so.")) {
+            continue;
+        }
+        libraries.extend(SharedLibrary::load(&mapping.path, mapping.start, mapping.end));
+    }
+    Ok(libraries)
+}
+
+/// Returns the library mapped at addr, if any.
+pub fn library_at(libraries: &[SharedLibrary], addr: usize) -> Option<&SharedLibrary> {
+    libraries.iter().find(|library| library.base <= addr && addr < library.end)
+}
+
+/// Collects the functions defined in the file's symbol tables. Unlike `DwarfData`'s symbols,
+/// aliases are kept, since libc often defines a function under several names.
+fn load_functions(path: &str) -> Option<Vec<ElfSymbol>> {
+    let file = fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap::Mmap::map(&file).ok()? };
+    let object = object::File::parse(&mmap).ok()?;
+    let mut symbols: Vec<ElfSymbol> = object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .filter(|(_, symbol)| symbol.kind() == object::SymbolKind::Text && symbol.address() != 0)
+        .filter(|(_, symbol)| !symbol.is_undefined())
+        .filter_map(|(_, symbol)| {
+            let raw_name = symbol.name()?;
+            Some(ElfSymbol {
+                name: demangle(raw_name),
+                raw_name: raw_name.to_string(),
+                address: symbol.address().try_into().ok()?,
+                size: symbol.size().try_into().ok()?,
+            })
+        })
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.address);
+    Some(symbols)
+}
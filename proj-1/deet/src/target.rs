@@ -0,0 +1,1001 @@
+//! A program being debugged: its debugging information, its breakpoints, and the process running
+//! it, if there is one. This is the interface programs built on deet use; the `deet` command line
+//! is a front end to it. Calls that let the process run return the `StopEvent` it stopped with.
+
+use crate::arch::{Arch, Native, Registers};
+use crate::dwarf_data::{function_name, DwarfData, Encoding, Line, Location, TypeLayout};
+use crate::error::DeetError;
+use crate::expression::{self, Expr, Value};
+use crate::inferior::{CallResult, Event, Inferior, LaunchOptions, Redirections, Status};
+use crate::record::Recording;
+use crate::value_printer::{self, ValuePrinter};
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitPidFlag;
+use nix::unistd::Pid;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::mem::size_of;
+
+/// Why the process stopped running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopEvent {
+    /// The process hit the breakpoint at the address
+    Breakpoint(usize),
+    /// A step finished, at the address
+    Stepped(usize),
+    /// The process got a signal, at the address
+    Signal(Signal, usize),
+    /// The process exited with the status
+    Exited(i32),
+    /// The process was killed by the signal
+    Signaled(Signal),
+    /// The process stopped at an event a catchpoint is set for, at the address
+    Caught(Event, usize),
+    /// The process got a signal a catchpoint is set for, at the address. It's delivered when the
+    /// process resumes.
+    CaughtSignal(Signal, usize),
+    /// Going backwards, the start of the recorded history was reached, at the address
+    HistoryEnd(usize),
+}
+
+/// What a catchpoint stops the process at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Catch {
+    Fork,
+    Vfork,
+    Exec,
+    /// Exiting, before the process is gone
+    Exit,
+    Signal(Signal),
+}
+
+impl fmt::Display for Catch {
+    /// Formats the catchpoint as `catch` takes it, as in `fork` or `signal SIGINT`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Catch::Fork => write!(f, "fork"),
+            Catch::Vfork => write!(f, "vfork"),
+            Catch::Exec => write!(f, "exec"),
+            Catch::Exit => write!(f, "exit"),
+            Catch::Signal(signal) => write!(f, "signal {}", signal),
+        }
+    }
+}
+
+/// A step or finish in progress. Breakpoints on the way stop it, and `Target::advance` carries it
+/// on from them, which is how the command line steps past its dprintfs.
+pub struct Stepping {
+    kind: StepKind,
+    /// Bounds of the function the step started in
+    func: Option<(usize, usize)>,
+    /// Stack pointer the step started with
+    stack_pointer: usize,
+    /// Breakpoint the step stopped at, which it carries on from
+    resume_at: Option<usize>,
+}
+
+enum StepKind {
+    /// To the start of another line, over calls or not
+    Line {
+        over_calls: bool,
+        start_line: Option<(String, usize)>,
+        start_inlined: usize,
+    },
+    /// Out of the inlined code at the given address ranges
+    LeaveInlined(Vec<(usize, usize)>),
+    /// Back to the caller, at the return address with the caller's stack pointer
+    Return(usize, usize),
+}
+
+/// What a step does on reaching an instruction
+enum StepAction {
+    Stop,
+    /// Executes the instruction and looks again
+    Step,
+    /// Runs the function just called until it returns, rather than stepping through it
+    RunOut,
+}
+
+pub struct Target {
+    path: String,
+    debug_data: DwarfData,
+    /// Breakpoints by address, with the bytes each replaced in the running process
+    break_points: HashMap<usize, Vec<u8>>,
+    catches: HashSet<Catch>,
+    launch_options: LaunchOptions,
+    inferior: Option<Inferior>,
+    /// An inferior whose process exited but that has checkpoints left to restart
+    exited_inferior: Option<Inferior>,
+    /// Values printed so far, which expressions refer to as `$1`, `$2`, ...
+    value_history: Vec<Value>,
+    /// Variables set with `set $name = ...`, which are kept across runs
+    convenience_variables: HashMap<String, Value>,
+}
+
+impl Target {
+    /// Loads the debugging information of the program at path.
+    pub fn open(path: &str) -> Result<Target, DeetError> {
+        let debug_data = DwarfData::from_file(path).map_err(|err| DeetError::Dwarf(path.to_string(), err))?;
+        Ok(Target {
+            path: path.to_string(),
+            debug_data,
+            break_points: HashMap::new(),
+            catches: HashSet::new(),
+            launch_options: LaunchOptions::default(),
+            inferior: None,
+            exited_inferior: None,
+            value_history: Vec::new(),
+            convenience_variables: HashMap::new(),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn debug_data(&self) -> &DwarfData {
+        &self.debug_data
+    }
+
+    /// The environment, working directory and terminal the program is started with.
+    pub fn launch_options(&self) -> &LaunchOptions {
+        &self.launch_options
+    }
+
+    pub fn launch_options_mut(&mut self) -> &mut LaunchOptions {
+        &mut self.launch_options
+    }
+
+    /// Returns the running process, if there is one.
+    pub fn inferior(&self) -> Option<&Inferior> {
+        self.inferior.as_ref()
+    }
+
+    pub fn inferior_mut(&mut self) -> Option<&mut Inferior> {
+        self.inferior.as_mut()
+    }
+
+    /// Returns the running process, or if it exited, the one kept for its checkpoints.
+    pub fn checkpointed_inferior(&self) -> Option<&Inferior> {
+        self.inferior.as_ref().or(self.exited_inferior.as_ref())
+    }
+
+    pub fn checkpointed_inferior_mut(&mut self) -> Option<&mut Inferior> {
+        match self.inferior {
+            Some(ref mut inferior) => Some(inferior),
+            None => self.exited_inferior.as_mut(),
+        }
+    }
+
+    fn running(&self) -> Result<&Inferior, DeetError> {
+        self.inferior.as_ref().ok_or(DeetError::NoProcess)
+    }
+
+    /// Starts the program with args, killing the process already running it. The new process is
+    /// stopped before its first instruction. Returns the addresses of the breakpoints that could
+    /// not be written into it.
+    pub fn start(&mut self, args: &[String], redirections: &Redirections) -> Result<Vec<usize>, DeetError> {
+        self.kill();
+        let mut inferior = Inferior::new(&self.path, &args.to_vec(), &self.launch_options, redirections)?;
+        let invalid = inferior.insert_breakpoints(&mut self.break_points);
+        inferior.set_ptrace_options(self.ptrace_options())?;
+        self.inferior = Some(inferior);
+        Ok(invalid)
+    }
+
+    /// Starts the program with args and runs it until it stops.
+    pub fn run(&mut self, args: &[String]) -> Result<StopEvent, DeetError> {
+        self.start(args, &Redirections::default())?;
+        self.cont()
+    }
+
+    /// Kills the process running the program and any checkpoints it has. Returns the pid of the
+    /// process, if one was running.
+    pub fn kill(&mut self) -> Option<Pid> {
+        if let Some(mut inferior) = self.exited_inferior.take() {
+            inferior.kill();
+        }
+        let mut inferior = self.inferior.take()?;
+        inferior.kill();
+        Some(inferior.pid())
+    }
+
+    pub fn catches(&self) -> &HashSet<Catch> {
+        &self.catches
+    }
+
+    /// Sets a catchpoint, returning false if there already is one for catch.
+    pub fn set_catch(&mut self, catch: Catch) -> Result<bool, DeetError> {
+        let added = self.catches.insert(catch);
+        self.update_ptrace_options()?;
+        Ok(added)
+    }
+
+    /// Deletes a catchpoint, returning false if there was none for catch.
+    pub fn remove_catch(&mut self, catch: Catch) -> Result<bool, DeetError> {
+        let removed = self.catches.remove(&catch);
+        self.update_ptrace_options()?;
+        Ok(removed)
+    }
+
+    /// The ptrace options that make the process stop at the events catchpoints are set for.
+    fn ptrace_options(&self) -> ptrace::Options {
+        let mut options = ptrace::Options::empty();
+        for catch in &self.catches {
+            options |= match catch {
+                Catch::Fork => ptrace::Options::PTRACE_O_TRACEFORK,
+                Catch::Vfork => ptrace::Options::PTRACE_O_TRACEVFORK,
+                Catch::Exec => ptrace::Options::PTRACE_O_TRACEEXEC,
+                Catch::Exit => ptrace::Options::PTRACE_O_TRACEEXIT,
+                // Signals stop the process anyway
+                Catch::Signal(_) => ptrace::Options::empty(),
+            };
+        }
+        options
+    }
+
+    fn update_ptrace_options(&mut self) -> Result<(), DeetError> {
+        let options = self.ptrace_options();
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_ptrace_options(options)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a location given as `*addr`, `line`, `file:line` or a function name to an address.
+    pub fn resolve_location(&self, loc: &str) -> Result<usize, DeetError> {
+        let illegal = || DeetError::Input(format!("illegal location {}", loc));
+        if let Some(addr) = loc.strip_prefix('*') {
+            return parse_address(addr, 16).ok_or_else(illegal);
+        }
+        if let Some(line_number) = parse_address(loc, 10) {
+            return self.debug_data.get_addr_for_line(None, line_number).ok_or_else(illegal);
+        }
+        if let Some(idx) = loc.rfind(':') {
+            if let Some(line_number) = parse_address(&loc[idx + 1..], 10) {
+                return self.debug_data.get_addr_for_line(Some(&loc[..idx]), line_number).ok_or_else(illegal);
+            }
+        }
+        let functions = self.debug_data.find_functions(None, loc);
+        if functions.len() > 1 {
+            let candidates = functions
+                .iter()
+                .map(|func| match self.debug_data.get_line_from_addr(func.address) {
+                    Some(line) => format!("{} at {:#x} ({})", function_name(func), func.address, line),
+                    None => format!("{} at {:#x}", function_name(func), func.address),
+                })
+                .collect();
+            return Err(DeetError::AmbiguousLocation(loc.to_string(), candidates));
+        }
+        match functions.first() {
+            Some(func) => Ok(self.debug_data.skip_prologue(func.address)),
+            // Without debugging information we can only stop at the very start of the function
+            None => self.debug_data.get_symbol(loc).map(|symbol| symbol.address).ok_or_else(illegal),
+        }
+    }
+
+    /// Returns the breakpoints by address, with the bytes each replaced in the running process.
+    pub fn breakpoints(&self) -> &HashMap<usize, Vec<u8>> {
+        &self.break_points
+    }
+
+    /// Sets a breakpoint at addr, writing it into the running process if there is one.
+    pub fn set_breakpoint(&mut self, addr: usize) -> Result<(), DeetError> {
+        if self.break_points.contains_key(&addr) {
+            return Ok(());
+        }
+        let orig_bytes = match self.inferior.as_mut() {
+            Some(inferior) => inferior
+                .insert_breakpoint(addr)
+                .map_err(|_| DeetError::Input(format!("Invalid breakpoint address {:#x}", addr)))?,
+            None => Vec::new(),
+        };
+        self.break_points.insert(addr, orig_bytes);
+        Ok(())
+    }
+
+    /// Sets a breakpoint at a location, as given to `resolve_location`, and returns its address.
+    pub fn break_at(&mut self, loc: &str) -> Result<usize, DeetError> {
+        let addr = self.resolve_location(loc)?;
+        self.set_breakpoint(addr)?;
+        Ok(addr)
+    }
+
+    /// Removes the breakpoint at addr. Returns whether there was one.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        match self.break_points.remove(&addr) {
+            Some(orig_bytes) => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.remove_breakpoint(addr, &orig_bytes).ok();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the address of the instruction the process stopped with the given program counter
+    /// is at, which is the breakpoint it hit if it stopped on one.
+    pub fn stop_address(&self, pc: usize) -> usize {
+        let addr = Native::breakpoint_at(pc);
+        if self.inferior.as_ref().is_some_and(Inferior::trapped) && self.break_points.contains_key(&addr) {
+            addr
+        } else {
+            pc
+        }
+    }
+
+    /// Returns the event for a status of the process, and lets go of the process if it ended.
+    fn stop_event(&mut self, status: Status) -> StopEvent {
+        match status {
+            Status::Exited(exit_code) => {
+                self.process_ended();
+                StopEvent::Exited(exit_code)
+            }
+            Status::Signaled(signal) => {
+                self.process_ended();
+                StopEvent::Signaled(signal)
+            }
+            Status::Stopped(Signal::SIGTRAP, pc, true) if self.break_points.contains_key(&Native::breakpoint_at(pc)) => {
+                StopEvent::Breakpoint(Native::breakpoint_at(pc))
+            }
+            Status::Stopped(signal, rip, _) if self.catches.contains(&Catch::Signal(signal)) => {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    inferior.deliver_signal(signal);
+                }
+                StopEvent::CaughtSignal(signal, rip)
+            }
+            Status::Stopped(signal, rip, _) => StopEvent::Signal(signal, rip),
+            Status::Event(event, rip) => {
+                self.handle_event(event);
+                StopEvent::Caught(event, rip)
+            }
+        }
+    }
+
+    /// Does what an event of the process calls for: children it forked are let go of, and after
+    /// an exec the breakpoints are written into the new program, or dropped if it's a different
+    /// one.
+    fn handle_event(&mut self, event: Event) {
+        let inferior = match self.inferior.as_mut() {
+            Some(inferior) => inferior,
+            None => return,
+        };
+        match event {
+            Event::Fork(child) => {
+                inferior.detach_child(child, &self.break_points, false).ok();
+            }
+            Event::Vfork(child) => {
+                inferior.detach_child(child, &self.break_points, true).ok();
+            }
+            Event::Exec => {
+                let exe = fs::read_link(format!("/proc/{}/exe", inferior.pid())).ok();
+                if exe.is_some() && exe == fs::canonicalize(&self.path).ok() {
+                    for (addr, orig_bytes) in self.break_points.iter_mut() {
+                        if let Ok(bytes) = inferior.insert_breakpoint(*addr) {
+                            *orig_bytes = bytes;
+                        }
+                    }
+                } else {
+                    self.break_points.clear();
+                }
+            }
+            Event::Exit(_) => {}
+        }
+    }
+
+    /// Drops the inferior after its process exited, keeping it aside if it has checkpoints that
+    /// can still be restarted.
+    fn process_ended(&mut self) {
+        if let Some(inferior) = self.inferior.take().filter(|inferior| !inferior.checkpoints().is_empty()) {
+            self.exited_inferior = Some(inferior);
+        }
+    }
+
+    /// Finds out how the process ended, after a call failed with `DeetError::ProcessExited`.
+    /// Returns None if it can't tell, in which case the process is let go of anyway.
+    pub fn reap(&mut self) -> Option<StopEvent> {
+        match self.inferior.as_ref()?.wait(Some(WaitPidFlag::WNOHANG)) {
+            Ok(status @ Status::Exited(_)) | Ok(status @ Status::Signaled(_)) => Some(self.stop_event(status)),
+            _ => {
+                self.inferior = None;
+                None
+            }
+        }
+    }
+
+    /// Runs the process until it stops.
+    pub fn cont(&mut self) -> Result<StopEvent, DeetError> {
+        let status = self.inferior.as_mut().ok_or(DeetError::NoProcess)?.continue_run(&self.break_points)?;
+        Ok(self.stop_event(status))
+    }
+
+    /// Runs the process until it reaches one of stops or stops otherwise. Reaching one is
+    /// reported as a breakpoint there.
+    pub fn run_to(&mut self, stops: &[usize]) -> Result<StopEvent, DeetError> {
+        self.running()?;
+        let mut added = Vec::new();
+        for stop in stops {
+            if !self.break_points.contains_key(stop) && self.set_breakpoint(*stop).is_ok() {
+                added.push(*stop);
+            }
+        }
+        let result = self.cont();
+        for stop in added {
+            self.remove_breakpoint(stop);
+        }
+        result
+    }
+
+    /// Executes a single instruction.
+    pub fn step_instruction(&mut self) -> Result<StopEvent, DeetError> {
+        let status = self.inferior.as_mut().ok_or(DeetError::NoProcess)?.step_instruction(&self.break_points)?;
+        Ok(match self.stop_event(status) {
+            StopEvent::Signal(Signal::SIGTRAP, rip) => StopEvent::Stepped(rip),
+            event => event,
+        })
+    }
+
+    /// Steps to the start of the next source line, stopping in functions called on the way.
+    pub fn step_line(&mut self) -> Result<StopEvent, DeetError> {
+        let mut stepping = self.begin_step(false)?;
+        self.advance(&mut stepping)
+    }
+
+    /// Steps to the start of the next source line of the current function.
+    pub fn next_line(&mut self) -> Result<StopEvent, DeetError> {
+        let mut stepping = self.begin_step(true)?;
+        self.advance(&mut stepping)
+    }
+
+    /// Runs until the current function returns, or if the process is in code that was inlined,
+    /// until it leaves that code.
+    pub fn finish(&mut self) -> Result<StopEvent, DeetError> {
+        let mut stepping = self.begin_finish()?;
+        self.advance(&mut stepping)
+    }
+
+    /// Sets up a step to the start of the next source line, to be carried out with `advance`.
+    /// With over_calls, functions called along the way run to completion, as for `next`;
+    /// otherwise stepping stops in the first of them that has source information.
+    pub fn begin_step(&self, over_calls: bool) -> Result<Stepping, DeetError> {
+        let regs = self.running()?.registers()?;
+        let start = self.stop_address(Native::pc(&regs));
+        Ok(Stepping {
+            kind: StepKind::Line {
+                over_calls,
+                start_line: self.line_at(start),
+                start_inlined: self.debug_data.get_inlined_calls_at(start).len(),
+            },
+            func: self.function_bounds(start),
+            stack_pointer: Native::stack_pointer(&regs),
+            resume_at: None,
+        })
+    }
+
+    /// Sets up leaving the inlined code the process is in, to be carried out with `advance`.
+    /// Returns None if it isn't in inlined code.
+    pub fn begin_leave_inlined(&self) -> Result<Option<Stepping>, DeetError> {
+        let regs = self.running()?.registers()?;
+        let start = self.stop_address(Native::pc(&regs));
+        Ok(self.debug_data.get_inlined_calls_at(start).last().map(|call| Stepping {
+            kind: StepKind::LeaveInlined(call.ranges.clone()),
+            func: self.function_bounds(start),
+            stack_pointer: Native::stack_pointer(&regs),
+            resume_at: None,
+        }))
+    }
+
+    /// Sets up a finish, to be carried out with `advance`: leaving the inlined code the process
+    /// is in, or else returning from the current function.
+    pub fn begin_finish(&self) -> Result<Stepping, DeetError> {
+        if let Some(stepping) = self.begin_leave_inlined()? {
+            return Ok(stepping);
+        }
+        let regs = self.frame_registers()?;
+        let start = Native::pc(&regs);
+        let name = self.debug_data.get_function_from_addr(start).unwrap_or_default();
+        if name == "main" || name.ends_with("::main") {
+            return Err(DeetError::Input("\"finish\" not meaningful in the outermost frame.".to_string()));
+        }
+        let caller = self
+            .caller_registers()?
+            .ok_or_else(|| DeetError::Input("Can not find the return address: no call frame information".to_string()))?;
+        Ok(Stepping {
+            kind: StepKind::Return(Native::pc(&caller), Native::stack_pointer(&caller)),
+            func: self.function_bounds(start),
+            stack_pointer: Native::stack_pointer(&regs),
+            resume_at: None,
+        })
+    }
+
+    /// Carries out a step until it finishes, a breakpoint is hit on the way, or the process
+    /// stops otherwise. After a breakpoint, calling it again carries on with the step.
+    pub fn advance(&mut self, stepping: &mut Stepping) -> Result<StopEvent, DeetError> {
+        if let StepKind::Return(return_address, stack_pointer) = stepping.kind {
+            // Running out of the function carries on from wherever a breakpoint stopped it
+            stepping.resume_at = None;
+            return Ok(match self.run_out(stepping, return_address, stack_pointer)? {
+                Some(event) => event,
+                None => StopEvent::Stepped(return_address),
+            });
+        }
+        loop {
+            let rip = match stepping.resume_at.take() {
+                Some(addr) => addr,
+                None => match self.inferior.as_mut().ok_or(DeetError::NoProcess)?.step_instruction(&self.break_points)? {
+                    Status::Stopped(Signal::SIGTRAP, pc, true) if self.break_points.contains_key(&Native::breakpoint_at(pc)) => {
+                        // Carrying on starts from the instruction the breakpoint is at
+                        let addr = Native::breakpoint_at(pc);
+                        stepping.resume_at = Some(addr);
+                        return Ok(StopEvent::Breakpoint(addr));
+                    }
+                    Status::Stopped(Signal::SIGTRAP, rip, _) => rip,
+                    status => return Ok(self.stop_event(status)),
+                },
+            };
+            match self.step_action(stepping, rip)? {
+                StepAction::Stop => return Ok(StopEvent::Stepped(rip)),
+                StepAction::Step => {}
+                StepAction::RunOut => {
+                    // Without call frame information the callee can only be stepped through
+                    if let Some(caller) = self.caller_registers()? {
+                        let (return_address, stack_pointer) = (Native::pc(&caller), Native::stack_pointer(&caller));
+                        if let Some(event) = self.run_out(stepping, return_address, stack_pointer)? {
+                            return Ok(event);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the function the process is in until it returns to return_address with the caller's
+    /// stack pointer, which the step carries on from. Returns the event it stopped at instead,
+    /// if any.
+    fn run_out(&mut self, stepping: &mut Stepping, return_address: usize, stack_pointer: usize) -> Result<Option<StopEvent>, DeetError> {
+        let breakpoint_there = self.break_points.contains_key(&return_address);
+        loop {
+            match self.run_to(&[return_address])? {
+                StopEvent::Breakpoint(addr) if addr == return_address && !breakpoint_there => {
+                    let regs = self.running()?.registers()?;
+                    if Native::stack_pointer(&regs) >= stack_pointer {
+                        stepping.resume_at = Some(return_address);
+                        return Ok(None);
+                    }
+                    // A recursive call returned there. Its caller carries on from the return
+                    // address, which needs to be stepped past before it can be stopped at again.
+                    match self.inferior.as_mut().unwrap().step_instruction(&self.break_points)? {
+                        Status::Stopped(Signal::SIGTRAP, ..) => {}
+                        status => return Ok(Some(self.stop_event(status))),
+                    }
+                }
+                StopEvent::Breakpoint(addr) => {
+                    stepping.resume_at = Some(addr);
+                    return Ok(Some(StopEvent::Breakpoint(addr)));
+                }
+                event => return Ok(Some(event)),
+            }
+        }
+    }
+
+    /// Returns what a step does once it reached rip.
+    fn step_action(&self, stepping: &Stepping, rip: usize) -> Result<StepAction, DeetError> {
+        let stack_pointer = Native::stack_pointer(&self.running()?.registers()?);
+        let in_func = stepping.func.is_some_and(|(start, end)| start <= rip && rip < end);
+        match &stepping.kind {
+            StepKind::Line { over_calls, start_line, start_inlined } => {
+                // A recursive call is only told apart from the rest of the function by its entry
+                let entered = !in_func || stepping.func.is_some_and(|(start, _)| start == rip);
+                let in_callee = stack_pointer < stepping.stack_pointer && stepping.func.is_some() && entered;
+                let line = self.line_at(rip);
+                // Functions called are run to completion when stepping over them, and when
+                // stepping into them if they have no source to stop in
+                if in_callee && (*over_calls || line.is_none()) {
+                    return Ok(StepAction::RunOut);
+                }
+                // Calls that were inlined are stepped over too, though they share the caller's frame
+                let in_inlined_callee = in_func && self.debug_data.get_inlined_calls_at(rip).len() > *start_inlined;
+                if *over_calls && in_inlined_callee {
+                    return Ok(StepAction::Step);
+                }
+                // Stop at the first instruction of a different line, so that returning to the
+                // middle of the caller's line keeps going to the next one. Prologues are stepped
+                // through.
+                let at_line_start = self.debug_data.get_line_range(rip).is_some_and(|(row, _)| row.address == rip);
+                let at_entry = self.debug_data.get_function_at(rip).is_some_and(|func| func.address == rip);
+                Ok(if line.is_some() && line != *start_line && at_line_start && !at_entry {
+                    StepAction::Stop
+                } else {
+                    StepAction::Step
+                })
+            }
+            // Functions called from the inlined code don't count as leaving it, and are run to
+            // completion, but leaving the function altogether, as by a tail call, does
+            StepKind::LeaveInlined(ranges) => Ok(if in_func {
+                if ranges.iter().any(|(start, end)| *start <= rip && rip < *end) {
+                    StepAction::Step
+                } else {
+                    StepAction::Stop
+                }
+            } else if stack_pointer < stepping.stack_pointer {
+                StepAction::RunOut
+            } else {
+                StepAction::Stop
+            }),
+            // Returning isn't stepped to, `advance` runs out of the function instead
+            StepKind::Return(..) => Ok(StepAction::Stop),
+        }
+    }
+
+    fn line_at(&self, addr: usize) -> Option<(String, usize)> {
+        self.debug_data.get_line_from_addr(addr).map(|line| (line.file, line.number))
+    }
+
+    fn function_bounds(&self, addr: usize) -> Option<(usize, usize)> {
+        self.debug_data.get_function_at(addr).map(|func| (func.address, func.address + func.text_length))
+    }
+
+    /// Undoes the last instruction executed while recording.
+    pub fn reverse_step_instruction(&mut self) -> Result<StopEvent, DeetError> {
+        self.reverse(|_, _, _| true)
+    }
+
+    /// Goes back to the start of the previous source line executed, stopping in functions called
+    /// on the way.
+    pub fn reverse_step_line(&mut self) -> Result<StopEvent, DeetError> {
+        self.reverse_line(false)
+    }
+
+    /// Goes back to the start of the previous source line of the current function executed.
+    pub fn reverse_next_line(&mut self) -> Result<StopEvent, DeetError> {
+        self.reverse_line(true)
+    }
+
+    /// Undoes recorded instructions until a breakpoint is reached or the history runs out.
+    pub fn reverse_continue(&mut self) -> Result<StopEvent, DeetError> {
+        let break_points: HashSet<usize> = self.break_points.keys().copied().collect();
+        self.reverse(|_, regs, _| break_points.contains(&Native::pc(regs)))
+    }
+
+    /// Goes back to the start of the previous source line executed. With over_calls, the lines
+    /// of functions called from the current one are skipped, as `next` would.
+    fn reverse_line(&mut self, over_calls: bool) -> Result<StopEvent, DeetError> {
+        let regs = self.frame_registers()?;
+        let (rip, stack_pointer) = (Native::pc(&regs), Native::stack_pointer(&regs));
+        let line_at = |debug_data: &DwarfData, addr: usize| {
+            debug_data.get_line_from_addr(addr).map(|line| (line.file, line.number))
+        };
+        let start_line = line_at(&self.debug_data, rip);
+        let func = self.function_bounds(rip);
+        let in_callee = move |regs: &Registers| {
+            let addr = Native::pc(regs);
+            over_calls
+                && Native::stack_pointer(regs) < stack_pointer
+                && func.is_some_and(|(start, end)| addr < start || addr >= end)
+        };
+        // The line we're going back to, once we've left the current one
+        let mut target_line = None;
+        self.reverse(|debug_data, regs, history| {
+            if in_callee(regs) {
+                return false;
+            }
+            let line = line_at(debug_data, Native::pc(regs));
+            if target_line.is_none() && line.is_some() && line != start_line {
+                target_line = line;
+            }
+            if target_line.is_none() {
+                return false;
+            }
+            // Keep going back while the instruction before was still part of the target line.
+            // Before a return from a function called, that's the call.
+            match history.registers().find(|prev| !in_callee(prev)) {
+                Some(prev) => line_at(debug_data, Native::pc(prev)) != target_line,
+                None => true,
+            }
+        })
+    }
+
+    /// Undoes recorded instructions until done returns true or the history runs out. done is
+    /// given the registers reached and the recording of the instructions before. Stopping at a
+    /// breakpoint is reported as hitting it.
+    fn reverse(
+        &mut self,
+        mut done: impl FnMut(&DwarfData, &Registers, &Recording) -> bool,
+    ) -> Result<StopEvent, DeetError> {
+        let inferior = self.inferior.as_mut().ok_or(DeetError::NoProcess)?;
+        if inferior.recording().is_none() {
+            return Err(DeetError::Input(if Native::CAN_RECORD {
+                "Target is not recording; use record first".to_string()
+            } else {
+                format!("Process record is not supported on {}.", Native::NAME)
+            }));
+        }
+        let history_end = loop {
+            if !inferior.reverse_stepi()? {
+                break true;
+            }
+            let regs = inferior.registers()?;
+            // The recording is still there, having had an instruction to undo
+            if done(&self.debug_data, &regs, inferior.recording().unwrap()) {
+                break false;
+            }
+        };
+        let rip = inferior.pc()?;
+        if history_end {
+            return Ok(StopEvent::HistoryEnd(rip));
+        }
+        if self.break_points.contains_key(&rip) {
+            // Breakpoints are left the way the forward direction's trap leaves them
+            inferior.trap_at(rip)?;
+            return Ok(StopEvent::Breakpoint(rip));
+        }
+        Ok(StopEvent::Stepped(rip))
+    }
+
+    /// Returns the source line of the instruction the process is stopped at.
+    pub fn location(&self) -> Result<Option<Line>, DeetError> {
+        let rip = self.stop_address(self.running()?.pc()?);
+        Ok(self.debug_data.get_line_from_addr(rip))
+    }
+
+    pub fn registers(&self) -> Result<Registers, DeetError> {
+        Ok(self.running()?.registers()?)
+    }
+
+    /// Reads len bytes of the process's memory at addr, as the program sees them, without
+    /// breakpoints.
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, DeetError> {
+        Ok(self.running()?.read_bytes(addr, len)?)
+    }
+
+    /// Describes the frames of the process's call stack, innermost first, as `func (file:line)`.
+    pub fn backtrace(&self) -> Result<Vec<String>, DeetError> {
+        Ok(self.running()?.backtrace(&self.debug_data)?)
+    }
+
+    /// Pops the current frame as if the function had returned, optionally storing value in the
+    /// return register. Returns the address execution will resume at in the caller.
+    pub fn pop_frame(&mut self, value: Option<i64>) -> Result<usize, DeetError> {
+        let caller = self
+            .caller_registers()?
+            .ok_or_else(|| DeetError::Input("Can not pop the frame: no call frame information".to_string()))?;
+        self.inferior.as_mut().unwrap().pop_frame(caller, value)?;
+        Ok(Native::pc(&caller))
+    }
+
+    /// Returns the address the current frame will return to.
+    pub fn return_address(&self) -> Result<usize, DeetError> {
+        let caller = self
+            .caller_registers()?
+            .ok_or_else(|| DeetError::Input("Can not find the return address: no call frame information".to_string()))?;
+        Ok(Native::pc(&caller))
+    }
+
+    /// Returns the registers the caller of the current frame will have once it returns, found
+    /// with the call frame information, or None if there is none for the current instruction.
+    fn caller_registers(&self) -> Result<Option<Registers>, DeetError> {
+        let regs = self.frame_registers()?;
+        Ok(self.running()?.caller_registers(&self.debug_data, &regs)?)
+    }
+
+    /// Returns the registers of the process with the program counter at the instruction it is
+    /// stopped at, which is the breakpoint it hit if it stopped on one. Unwinding the frame
+    /// starts from these, since the breakpoint stands in for an instruction yet to execute.
+    fn frame_registers(&self) -> Result<Registers, DeetError> {
+        let mut regs = self.running()?.registers()?;
+        let pc = self.stop_address(Native::pc(&regs));
+        Native::set_pc(&mut regs, pc);
+        Ok(regs)
+    }
+
+    /// Reads the named variable of the current function, or else the global variable. Values
+    /// other than numbers and pointers come formatted as text.
+    pub fn read_variable(&self, name: &str) -> Result<Value, DeetError> {
+        let inferior = self.running()?;
+        let regs = self.frame_registers().or(Err("Could not read registers"))?;
+        let pc = Native::pc(&regs);
+        let func = self.debug_data.get_function_at(pc);
+        let (var, frame_base_register) = match func
+            .and_then(|func| func.variables_at(pc).into_iter().find(|var| var.name == name))
+        {
+            Some(var) => (var, func.unwrap().frame_base_register),
+            None => (
+                self.debug_data
+                    .get_global_variable(name)
+                    .ok_or(format!("No symbol \"{}\" in current context.", name))?,
+                None,
+            ),
+        };
+        let addr = match var.location {
+            Location::Address(addr) => addr,
+            Location::FramePointerOffset(offset) => {
+                let frame_base = match frame_base_register {
+                    // By default, frame offsets are relative to the canonical frame address,
+                    // which the call frame information tells how to find.
+                    None => self
+                        .debug_data
+                        .frame_rule(Native::pc(&regs))
+                        .and_then(|rule| rule.cfa(&regs))
+                        .ok_or("Could not find the frame address: no call frame information")? as u64,
+                    Some(reg) => Native::register(&regs, reg).ok_or(format!("Unsupported frame base register {}", reg))?,
+                };
+                (frame_base as isize + offset) as usize
+            }
+        };
+        let ty = self.debug_data.resolve_type(var.entity_type.offset);
+        match ty.map(|ty| (&ty.layout, ty)) {
+            Some((TypeLayout::Base(_), ty)) if ty.name != "bool" && ty.name != "char" => {}
+            Some((TypeLayout::Pointer(_), ty)) if !value_printer::is_rust_reference(ty) => {
+                let bytes = inferior
+                    .read_bytes(addr, size_of::<usize>())
+                    .or(Err(format!("Cannot access memory at address {:#x}", addr)))?;
+                return Ok(Value::Pointer(Value::from_bytes(Encoding::Unsigned, &bytes).as_int().unwrap() as u64));
+            }
+            Some(_) => {
+                let printer = ValuePrinter::new(&self.debug_data, inferior);
+                return Ok(Value::Aggregate(printer.format(var.entity_type.offset, addr)));
+            }
+            None => {}
+        }
+        let encoding = ty.map_or(Encoding::Unsigned, |ty| ty.encoding());
+        let bytes = inferior
+            .read_bytes(addr, var.entity_type.size)
+            .or(Err(format!("Cannot access memory at address {:#x}", addr)))?;
+        Ok(Value::from_bytes(encoding, &bytes))
+    }
+
+    /// Evaluates an expression in the context of the current frame of the process. `$`, `$N`
+    /// and `$$N` refer to the value history, and other names starting with `$` to convenience
+    /// variables, which need no process.
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, DeetError> {
+        match expr {
+            Expr::Int(v) => Ok(Value::Int(*v)),
+            Expr::Float(v) => Ok(Value::Float(*v)),
+            Expr::Neg(inner) => match self.evaluate(inner)? {
+                Value::Int(v) => Ok(Value::Int(v.wrapping_neg())),
+                Value::Float(v) => Ok(Value::Float(-v)),
+                _ => Err("invalid operand to unary -".into()),
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                Ok(Value::binary(*op, lhs, rhs)?)
+            }
+            Expr::Var(name) if name.starts_with('$') => {
+                Ok(expression::history_value(&self.value_history, &self.convenience_variables, name)?)
+            }
+            Expr::Var(name) => self.read_variable(name),
+            Expr::Call(name, args) => {
+                let mut values = Vec::new();
+                for arg in args {
+                    values.push(self.evaluate(arg)?);
+                }
+                self.call_function(name, &values)
+            }
+        }
+    }
+
+    /// Adds value to the value history. Returns its number, by which `$N` refers to it.
+    pub fn record_value(&mut self, value: Value) -> usize {
+        self.value_history.push(value);
+        self.value_history.len()
+    }
+
+    /// Variables set with `set $name = ...`, by name without the `$`.
+    pub fn convenience_variables(&self) -> &HashMap<String, Value> {
+        &self.convenience_variables
+    }
+
+    pub fn set_convenience_variable(&mut self, name: &str, value: Value) {
+        self.convenience_variables.insert(name.to_string(), value);
+    }
+
+    /// Calls the named function of the program in the process, and returns what it returns.
+    pub fn call_function(&mut self, name: &str, args: &[Value]) -> Result<Value, DeetError> {
+        self.running()?;
+        let func = self
+            .debug_data
+            .get_function(name)
+            .ok_or(format!("No function \"{}\" in the program.", name))?
+            .clone();
+        let mut int_args = Vec::new();
+        let mut float_args = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            // Converted to the parameter's type as C would: floats passed for integer parameters
+            // are truncated towards zero.
+            let (float, int) = match *arg {
+                Value::Void => return Err("void value in expression".into()),
+                Value::Aggregate(_) => return Err("only scalar arguments can be passed".into()),
+                Value::Float(v) => (v, v as i64),
+                Value::Int(v) => (v as f64, v),
+                Value::Pointer(v) => (v as f64, v as i64),
+            };
+            match func.params.get(i).map(|ty| ty.name.as_str()) {
+                // Single-precision arguments occupy only the low 32 bits of their register.
+                Some("float") => float_args.push((float as f32).to_bits() as u64),
+                Some("double") => float_args.push(float.to_bits()),
+                None if matches!(arg, Value::Float(_)) => float_args.push(float.to_bits()),
+                _ => int_args.push(int as u64),
+            }
+        }
+
+        if int_args.len() > Native::MAX_INT_ARGS || float_args.len() > Native::MAX_FLOAT_ARGS {
+            return Err(DeetError::Input(format!(
+                "Too many arguments to {}: at most {} integer and {} floating point arguments can be passed",
+                name,
+                Native::MAX_INT_ARGS,
+                Native::MAX_FLOAT_ARGS
+            )));
+        }
+
+        let entry_point = self.debug_data.entry_point();
+        let result = self
+            .inferior
+            .as_mut()
+            .ok_or(DeetError::NoProcess)?
+            .call_function(func.address, entry_point, &int_args, &float_args)
+            .or(Err(format!("Could not call {}", name)))?;
+        match result {
+            CallResult::Returned(int_value, float_value) => Ok(match func.return_type {
+                None => Value::Void,
+                Some(ty) if ty.name == "float" || ty.name == "double" => {
+                    Value::from_bytes(Encoding::Float, &float_value.to_le_bytes()[..ty.size])
+                }
+                Some(ty) if ty.size > 0 => {
+                    let encoding = self.debug_data.resolve_type(ty.offset).map_or(Encoding::Unsigned, |ty| ty.encoding());
+                    Value::from_bytes(encoding, &int_value.to_le_bytes()[..ty.size])
+                }
+                Some(_) => Value::Int(int_value as i64),
+            }),
+            CallResult::Interrupted(Status::Exited(exit_code)) => {
+                self.inferior = None;
+                Err(format!("Child exited (status {}) while calling {}", exit_code, name).into())
+            }
+            CallResult::Interrupted(Status::Signaled(signal)) => {
+                self.inferior = None;
+                Err(format!("Child exited due to signal {} while calling {}", signal, name).into())
+            }
+            CallResult::Interrupted(Status::Stopped(signal, ..)) => Err(format!(
+                "The program stopped (signal {}) in {}; the call was abandoned",
+                signal, name
+            )
+            .into()),
+            CallResult::Interrupted(Status::Event(event, _)) => {
+                self.handle_event(event);
+                Err(format!("The program stopped at a catchpoint in {}; the call was abandoned", name).into())
+            }
+        }
+    }
+
+    /// Saves a checkpoint of the process, returning its number.
+    pub fn checkpoint(&mut self) -> Result<usize, DeetError> {
+        Ok(self.inferior.as_mut().ok_or(DeetError::NoProcess)?.checkpoint(&self.break_points)?)
+    }
+
+    /// Continues from checkpoint id, discarding the current state of the process, and returns the
+    /// program counter it's stopped with. Checkpoints of a process that exited can be
+    /// restarted too.
+    pub fn restart(&mut self, id: usize) -> Result<usize, DeetError> {
+        if self.inferior.is_none() {
+            self.inferior = self.exited_inferior.take();
+        }
+        let no_checkpoint = || DeetError::Input(format!("No checkpoint number {}.", id));
+        let inferior = self.inferior.as_mut().ok_or_else(no_checkpoint)?;
+        inferior.restart(id, &self.break_points).map_err(|err| match err {
+            nix::Error::Sys(nix::errno::Errno::ESRCH) => no_checkpoint(),
+            err => DeetError::Input(format!("Could not restart checkpoint {}: {}", id, err)),
+        })
+    }
+}
+
+fn parse_address(addr: &str, radix: u32) -> Option<usize> {
+    let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
+        &addr[2..]
+    } else {
+        addr
+    };
+    usize::from_str_radix(addr_without_0x, radix).ok()
+}
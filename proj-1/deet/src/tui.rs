@@ -0,0 +1,299 @@
+//! The full-screen interface of `deet --tui`. The screen is split into a source pane that follows
+//! the current line, panes for the registers, the call stack, the local variables and the
+//! auto-display expressions, and the command line at the bottom. The panes are drawn with `tui`
+//! into a viewport covering the top of the screen, and the bottom rows are made a scrolling
+//! region so that commands and their output behave as usual there.
+
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::{cursor, execute, terminal};
+use deet::arch::{Arch, Native, Registers};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Stdout};
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Paragraph};
+use tui::{Terminal, TerminalOptions, Viewport};
+
+/// What the panes show, gathered by the debugger after each command.
+#[derive(Default)]
+pub struct Snapshot {
+    /// File and line the inferior is stopped at
+    pub location: Option<(String, usize)>,
+    /// Where main starts, shown before the program runs
+    pub entry: Option<(String, usize)>,
+    /// Files and lines with breakpoints
+    pub breakpoint_lines: Vec<(String, usize)>,
+    pub registers: Option<Registers>,
+    pub backtrace: Vec<String>,
+    /// Local variables, as `name = value`
+    pub locals: Vec<String>,
+    /// Auto-display expressions, as `expr = value`
+    pub watches: Vec<String>,
+}
+
+pub enum Key {
+    Char(char),
+    Enter,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    F(u8),
+    /// The terminal changed size, so the panes need to be laid out again
+    Resize,
+    Other,
+}
+
+/// Minimum number of rows left for the command line and command output
+const COMMAND_ROWS: u16 = 8;
+/// Width the register pane needs to show two registers per line
+const REGISTERS_WIDTH: u16 = 50;
+
+/// Limits scrolling to the rows from top to bottom, counting from 0, or lifts the limit.
+/// crossterm has no command for this.
+struct SetScrollRegion(Option<(u16, u16)>);
+
+impl crossterm::Command for SetScrollRegion {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        match self.0 {
+            Some((top, bottom)) => write!(f, "\x1b[{};{}r", top + 1, bottom + 1),
+            None => write!(f, "\x1b[r"),
+        }
+    }
+}
+
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    /// The part of the screen the panes were last drawn in
+    area: Rect,
+    sources: HashMap<String, Option<Vec<String>>>,
+    /// Source line the cursor is on, which breakpoints are toggled at
+    cursor: Option<(String, usize)>,
+    /// Location shown at the last draw, to move the cursor along when it changes
+    last_location: Option<(String, usize)>,
+    last_registers: Option<Registers>,
+    changed_registers: Vec<&'static str>,
+}
+
+/// Returns the part of a terminal of the given size that the panes take up, leaving the rest
+/// for the command line.
+fn pane_area((cols, rows): (u16, u16)) -> Rect {
+    let command_rows = COMMAND_ROWS.max(rows / 4).min(rows.saturating_sub(6));
+    Rect::new(0, 0, cols, rows - command_rows)
+}
+
+fn pane(title: &str) -> Block<'_> {
+    Block::default().borders(Borders::ALL).title(title.to_string())
+}
+
+impl Tui {
+    /// Switches the terminal to the alternate screen. The original screen is restored when the
+    /// Tui is dropped.
+    pub fn new() -> io::Result<Tui> {
+        let area = pane_area(terminal::size()?);
+        execute!(io::stdout(), terminal::EnterAlternateScreen, terminal::Clear(terminal::ClearType::All))?;
+        let terminal = Terminal::with_options(
+            CrosstermBackend::new(io::stdout()),
+            TerminalOptions { viewport: Viewport::fixed(area) },
+        )?;
+        Ok(Tui {
+            terminal,
+            area,
+            sources: HashMap::new(),
+            cursor: None,
+            last_location: None,
+            last_registers: None,
+            changed_registers: Vec::new(),
+        })
+    }
+
+    pub fn cursor(&self) -> Option<&(String, usize)> {
+        self.cursor.as_ref()
+    }
+
+    /// Moves the source cursor by delta lines.
+    pub fn move_cursor(&mut self, delta: isize) {
+        if let Some((file, line)) = self.cursor.take() {
+            let len = self.source(&file).map_or(1, |lines| lines.len().max(1));
+            let line = (line as isize + delta).max(1).min(len as isize) as usize;
+            self.cursor = Some((file, line));
+        }
+    }
+
+    fn source(&mut self, file: &str) -> Option<&Vec<String>> {
+        self.sources
+            .entry(file.to_string())
+            .or_insert_with(|| fs::read_to_string(file).ok().map(|text| text.lines().map(|line| line.replace('\t', "    ")).collect()))
+            .as_ref()
+    }
+
+    /// Redraws the panes, laying them out again if the terminal changed size, and leaves the
+    /// terminal cursor on the command line.
+    pub fn draw(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        let size = terminal::size()?;
+        let area = pane_area(size);
+        if area != self.area {
+            // This clears the whole screen, command line included
+            self.terminal.resize(area)?;
+            self.area = area;
+        }
+
+        if snapshot.location != self.last_location {
+            self.cursor = snapshot.location.clone();
+            self.last_location = snapshot.location.clone();
+        }
+        if self.cursor.is_none() {
+            self.cursor = snapshot.entry.clone();
+        }
+        let changed = snapshot.registers.as_ref().filter(|_| !registers_equal(&snapshot.registers, &self.last_registers));
+        if let Some(registers) = changed {
+            let old = self.last_registers.as_ref().map(Native::display_registers).unwrap_or_default();
+            self.changed_registers = Native::display_registers(registers)
+                .into_iter()
+                .zip(old)
+                .filter(|((_, new), (_, old))| new != old)
+                .map(|((name, _), _)| name)
+                .collect();
+            self.last_registers = snapshot.registers;
+        }
+
+        // The right panes need room for two columns of registers
+        let right_cols = (area.width * 2 / 5).max(REGISTERS_WIDTH.min(area.width / 2));
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(0), Constraint::Length(right_cols)])
+            .split(area);
+        // The stack, locals and watch panes share what the registers leave
+        let register_rows = (area.height / 2).min(11);
+        let third = (area.height - register_rows) / 3;
+        let right = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(register_rows),
+                Constraint::Length(third),
+                Constraint::Length(third),
+                Constraint::Min(0),
+            ])
+            .split(columns[1]);
+
+        let source_lines = self.source_lines(snapshot, columns[0].height.saturating_sub(2) as usize);
+        let source_title = self.cursor.as_ref().map_or("Source".to_string(), |(file, _)| file.clone());
+        let register_lines: Vec<Spans> = match &snapshot.registers {
+            Some(regs) => Native::display_registers(regs)
+                .chunks(2)
+                .map(|pair| {
+                    let text: Vec<String> = pair.iter().map(|(name, value)| format!("{:>6} {:016x}", name, value)).collect();
+                    let changed = pair.iter().any(|(name, _)| self.changed_registers.contains(name));
+                    let style = if changed {
+                        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                    } else {
+                        Style::default()
+                    };
+                    Spans::from(Span::styled(text.join(" "), style))
+                })
+                .collect(),
+            None => vec![Spans::from("No process is running")],
+        };
+        let plain = |lines: &[String]| -> Vec<Spans> { lines.iter().map(|line| Spans::from(line.clone())).collect() };
+        let stack_lines = plain(&snapshot.backtrace);
+        let locals_lines = plain(&snapshot.locals);
+        let watch_lines = plain(&snapshot.watches);
+
+        self.terminal.draw(|frame| {
+            frame.render_widget(Paragraph::new(source_lines).block(pane(&source_title)), columns[0]);
+            frame.render_widget(Paragraph::new(register_lines).block(pane("Registers")), right[0]);
+            frame.render_widget(Paragraph::new(stack_lines).block(pane("Stack")), right[1]);
+            frame.render_widget(Paragraph::new(locals_lines).block(pane("Locals")), right[2]);
+            frame.render_widget(Paragraph::new(watch_lines).block(pane("Watch")), right[3]);
+        })?;
+
+        // Keep the command line scrolling below the panes, with the prompt on its last row
+        let (_, rows) = size;
+        execute!(
+            io::stdout(),
+            SetScrollRegion(Some((area.height, rows.saturating_sub(1)))),
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            cursor::Show
+        )
+    }
+
+    /// Returns the lines of the source pane, centered on the cursor.
+    fn source_lines(&mut self, snapshot: &Snapshot, height: usize) -> Vec<Spans<'static>> {
+        let (file, cursor_line) = match self.cursor.clone() {
+            Some(cursor) => cursor,
+            None => return vec![Spans::from("No source to show")],
+        };
+        let source = match self.source(&file) {
+            Some(source) => source.clone(),
+            None => return vec![Spans::from(format!("Source of {} is not available", file))],
+        };
+        let first = cursor_line.saturating_sub(height / 2).max(1);
+        (first..first + height)
+            .filter(|number| *number <= source.len())
+            .map(|number| {
+                let current = snapshot.location.as_ref() == Some(&(file.clone(), number));
+                let breakpoint = snapshot.breakpoint_lines.contains(&(file.clone(), number));
+                let gutter = format!("{}{}", if breakpoint { "●" } else { " " }, if current { ">" } else { " " });
+                let style = if current {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else if number == cursor_line {
+                    Style::default().add_modifier(Modifier::UNDERLINED)
+                } else {
+                    Style::default()
+                };
+                Spans::from(vec![
+                    Span::styled(gutter, Style::default().fg(Color::Red)),
+                    Span::styled(format!("{:>5} {}", number, source[number - 1]), style),
+                ])
+            })
+            .collect()
+    }
+
+    /// Waits for a key press, reading it without echo or line buffering.
+    pub fn read_key(&self) -> Key {
+        // Without a terminal to read keys from, fall back to the command line
+        if terminal::enable_raw_mode().is_err() {
+            return Key::Enter;
+        }
+        let event = event::read();
+        terminal::disable_raw_mode().ok();
+        let key = match event {
+            Ok(Event::Key(key)) => key,
+            Ok(Event::Resize(..)) => return Key::Resize,
+            Ok(_) => return Key::Other,
+            // Input is gone, as it is when it reaches its end
+            Err(_) => return Key::Char('q'),
+        };
+        match key.code {
+            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => Key::Char('q'),
+            _ if key.modifiers.contains(KeyModifiers::CONTROL) => Key::Other,
+            KeyCode::Enter => Key::Enter,
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::PageUp => Key::PageUp,
+            KeyCode::PageDown => Key::PageDown,
+            KeyCode::F(n) => Key::F(n),
+            KeyCode::Char(c) => Key::Char(c),
+            _ => Key::Other,
+        }
+    }
+}
+
+fn registers_equal(a: &Option<Registers>, b: &Option<Registers>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Native::display_registers(a) == Native::display_registers(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().ok();
+        execute!(io::stdout(), SetScrollRegion(None), cursor::Show, terminal::LeaveAlternateScreen).ok();
+    }
+}
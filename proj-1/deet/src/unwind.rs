@@ -0,0 +1,192 @@
+//! Call frame information: the `.eh_frame` and `.debug_frame` tables compilers emit so that a
+//! frame can be unwound from any of its instructions, whether or not the function keeps a frame
+//! pointer and wherever it places its saved registers.
+
+use crate::arch::{Arch, Native};
+use crate::debug_file;
+use gimli::UnwindSection;
+use object::{Object, ObjectSection};
+use std::rc::Rc;
+
+type CfiReader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+/// Where the value a register had in the caller can be found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavedRegister {
+    /// In the given register of the current frame
+    InRegister(u16),
+    /// In memory, at the given offset from the canonical frame address
+    AtCfaOffset(i64),
+}
+
+/// How to find the caller's frame from an instruction: the canonical frame address (CFA), which
+/// is the stack pointer the caller had before the call, where the return address is kept and
+/// which of the caller's registers the function has saved on the stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameRule {
+    /// The CFA is the value of this register plus cfa_offset
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    pub return_address: SavedRegister,
+    /// Registers saved in memory, by DWARF number, with their offsets from the CFA
+    pub saved_registers: Vec<(u16, i64)>,
+}
+
+impl FrameRule {
+    /// Returns the canonical frame address of the frame with the given registers.
+    pub fn cfa(&self, regs: &<Native as Arch>::Registers) -> Option<usize> {
+        let base = Native::register(regs, self.cfa_register)?;
+        Some((base as i64).wrapping_add(self.cfa_offset) as usize)
+    }
+}
+
+/// The call frame tables of an executable, with the addresses their pointers are relative to.
+pub struct CallFrames {
+    eh_frame: Option<(gimli::EhFrame<CfiReader>, gimli::BaseAddresses)>,
+    debug_frame: Option<(gimli::DebugFrame<CfiReader>, gimli::BaseAddresses)>,
+}
+
+impl CallFrames {
+    /// Loads the tables of object. `.eh_frame` is kept in the executable itself even when its
+    /// debugging information is in a separate file, so this should be given the executable.
+    pub fn load(object: &object::File) -> CallFrames {
+        let endian = if object.is_little_endian() {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
+        let section_address = |name| object.section_by_name(name).map_or(0, |section| section.address());
+        let reader = |name| {
+            debug_file::section_data(object, name)
+                .filter(|data| !data.is_empty())
+                .map(|data| gimli::EndianRcSlice::new(Rc::from(&*data), endian))
+        };
+        let bases = gimli::BaseAddresses::default()
+            .set_eh_frame_hdr(section_address(".eh_frame_hdr"))
+            .set_eh_frame(section_address(".eh_frame"))
+            .set_text(section_address(".text"))
+            .set_got(section_address(".got"));
+        let address_size = if object.is_64() { 8 } else { 4 };
+        CallFrames {
+            eh_frame: reader(".eh_frame").map(|data| {
+                let mut eh_frame = gimli::EhFrame::from(data);
+                eh_frame.set_address_size(address_size);
+                (eh_frame, bases.clone())
+            }),
+            debug_frame: reader(".debug_frame").map(|data| {
+                let mut debug_frame = gimli::DebugFrame::from(data);
+                debug_frame.set_address_size(address_size);
+                (debug_frame, bases.clone())
+            }),
+        }
+    }
+
+    /// Returns how to unwind the frame executing the instruction at addr, or None if there is
+    /// no call frame information for it or its rules go beyond offsets from registers.
+    pub fn frame_rule(&self, addr: usize) -> Option<FrameRule> {
+        if let Some((eh_frame, bases)) = &self.eh_frame {
+            if let Some(rule) = frame_rule_in(eh_frame, bases, addr as u64) {
+                return Some(rule);
+            }
+        }
+        let (debug_frame, bases) = self.debug_frame.as_ref()?;
+        frame_rule_in(debug_frame, bases, addr as u64)
+    }
+}
+
+fn frame_rule_in<S: UnwindSection<CfiReader>>(section: &S, bases: &gimli::BaseAddresses, addr: u64) -> Option<FrameRule> {
+    let fde = section.fde_for_address(bases, addr, S::cie_from_offset).ok()?;
+    let return_address_register = fde.cie().return_address_register().0;
+    let mut ctx = gimli::UninitializedUnwindContext::new();
+    let row = fde.unwind_info_for_address(section, bases, &mut ctx, addr).ok()?;
+    let (cfa_register, cfa_offset) = match *row.cfa() {
+        gimli::CfaRule::RegisterAndOffset { register, offset } => (register.0, offset),
+        gimli::CfaRule::Expression(_) => return None,
+    };
+    let return_address = match row.register(gimli::Register(return_address_register)) {
+        // Without a rule, the return address is still in the register it was passed in
+        gimli::RegisterRule::Undefined | gimli::RegisterRule::SameValue => {
+            SavedRegister::InRegister(return_address_register)
+        }
+        gimli::RegisterRule::Register(other) => SavedRegister::InRegister(other.0),
+        gimli::RegisterRule::Offset(offset) => SavedRegister::AtCfaOffset(offset),
+        _ => return None,
+    };
+    let saved_registers = (0..Native::REGISTER_NAMES.len() as u16)
+        .filter(|register| *register != return_address_register)
+        .filter_map(|register| match row.register(gimli::Register(register)) {
+            gimli::RegisterRule::Offset(offset) => Some((register, offset)),
+            _ => None,
+        })
+        .collect();
+    Some(FrameRule { cfa_register, cfa_offset, return_address, saved_registers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FUNC: u64 = 0x1000;
+
+    /// A `.debug_frame` with one function at FUNC that pushes the frame pointer (register 6) and
+    /// then makes the stack pointer (register 7) the frame pointer, as x86-64 code does.
+    fn call_frames() -> CallFrames {
+        let mut cie = vec![0xff, 0xff, 0xff, 0xff, 1, 0]; // CIE id, version 1, no augmentation
+        cie.extend(&[1, 0x78, 16]); // Code alignment 1, data alignment -8, return address in 16
+        cie.extend(&[0x0c, 7, 8]); // DW_CFA_def_cfa: register 7 + 8
+        cie.extend(&[0x90, 1]); // DW_CFA_offset: register 16 at CFA - 8
+        let mut fde = vec![0, 0, 0, 0]; // The CIE is at offset 0
+        fde.extend(&FUNC.to_le_bytes());
+        fde.extend(&16u64.to_le_bytes());
+        fde.extend(&[0x41, 0x0e, 16]); // After 1 byte, DW_CFA_def_cfa_offset 16
+        fde.extend(&[0x86, 2]); // DW_CFA_offset: register 6 at CFA - 16
+        fde.extend(&[0x43, 0x0d, 6]); // After 3 more bytes, DW_CFA_def_cfa_register 6
+        let mut data = Vec::new();
+        for entry in [cie, fde].iter() {
+            data.extend(&(entry.len() as u32).to_le_bytes());
+            data.extend(entry);
+        }
+        let mut debug_frame = gimli::DebugFrame::from(gimli::EndianRcSlice::new(Rc::from(&data[..]), gimli::RunTimeEndian::Little));
+        debug_frame.set_address_size(8);
+        CallFrames { eh_frame: None, debug_frame: Some((debug_frame, gimli::BaseAddresses::default())) }
+    }
+
+    #[test]
+    fn follows_rules_through_prologue() {
+        let frames = call_frames();
+        assert_eq!(
+            frames.frame_rule(FUNC as usize),
+            Some(FrameRule {
+                cfa_register: 7,
+                cfa_offset: 8,
+                return_address: SavedRegister::AtCfaOffset(-8),
+                saved_registers: vec![],
+            })
+        );
+        let after_push = frames.frame_rule(FUNC as usize + 1).unwrap();
+        assert_eq!((after_push.cfa_register, after_push.cfa_offset), (7, 16));
+        assert_eq!(after_push.saved_registers, vec![(6, -16)]);
+        let in_body = frames.frame_rule(FUNC as usize + 8).unwrap();
+        assert_eq!((in_body.cfa_register, in_body.cfa_offset), (6, 16));
+        assert_eq!(in_body.return_address, SavedRegister::AtCfaOffset(-8));
+    }
+
+    #[test]
+    fn has_no_rule_outside_functions() {
+        let frames = call_frames();
+        assert_eq!(frames.frame_rule(FUNC as usize - 1), None);
+        assert_eq!(frames.frame_rule(FUNC as usize + 16), None);
+    }
+
+    #[test]
+    fn computes_cfa_from_register() {
+        let mut regs: <Native as Arch>::Registers = unsafe { std::mem::zeroed() };
+        Native::set_register(&mut regs, 6, 0x7ff0);
+        let rule = FrameRule { cfa_register: 6, cfa_offset: 16, return_address: SavedRegister::InRegister(30), saved_registers: vec![] };
+        assert_eq!(rule.cfa(&regs), Some(0x8000));
+        let rule = FrameRule { cfa_register: 6, cfa_offset: -16, ..rule };
+        assert_eq!(rule.cfa(&regs), Some(0x7fe0));
+        let rule = FrameRule { cfa_register: 1000, ..rule };
+        assert_eq!(rule.cfa(&regs), None);
+    }
+}
@@ -0,0 +1,318 @@
+//! Formats values in the inferior's memory according to their DWARF types. Structs, enums,
+//! arrays and pointers are handled generically; Rust's `String`, `&str`, `Vec<T>`, slices,
+//! `Box<T>` and enums such as `Option`/`Result` are recognized by name and shown the way
+//! `{:?}` would show them.
+
+use crate::dwarf_data::{DwarfData, Encoding, Member, TypeInfo, TypeLayout, Variant};
+use crate::expression::Value;
+use crate::inferior::Inferior;
+use std::convert::TryInto;
+
+/// Maximum number of elements shown for arrays, slices and vectors
+const MAX_ELEMENTS: usize = 200;
+/// Maximum number of bytes shown for strings
+const MAX_STRING_LEN: usize = 200;
+/// Maximum nesting depth of values we descend into
+const MAX_DEPTH: usize = 8;
+
+pub struct ValuePrinter<'a> {
+    debug_data: &'a DwarfData,
+    inferior: &'a Inferior,
+}
+
+impl<'a> ValuePrinter<'a> {
+    pub fn new(debug_data: &'a DwarfData, inferior: &'a Inferior) -> ValuePrinter<'a> {
+        ValuePrinter { debug_data, inferior }
+    }
+
+    /// Formats the value of the type at type_offset stored at addr.
+    pub fn format(&self, type_offset: usize, addr: usize) -> String {
+        self.format_at(type_offset, addr, 0)
+    }
+
+    fn read(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        self.inferior.read_bytes(addr, len).ok()
+    }
+
+    fn read_usize(&self, addr: usize) -> Option<usize> {
+        let bytes = self.read(addr, std::mem::size_of::<usize>())?;
+        Some(usize::from_le_bytes(bytes[..].try_into().ok()?))
+    }
+
+    fn read_uint(&self, addr: usize, size: usize) -> Option<u64> {
+        let bytes = self.read(addr, size.min(8))?;
+        let mut raw = [0u8; 8];
+        raw[..bytes.len()].copy_from_slice(&bytes);
+        Some(u64::from_le_bytes(raw))
+    }
+
+    fn size_of(&self, type_offset: usize) -> usize {
+        self.debug_data.get_type(type_offset).map_or(0, |info| info.size)
+    }
+
+    fn format_at(&self, type_offset: usize, addr: usize, depth: usize) -> String {
+        let info = match self.debug_data.get_type(type_offset) {
+            Some(info) => info,
+            None => return "<unknown type>".to_string(),
+        };
+        if depth > MAX_DEPTH {
+            return "...".to_string();
+        }
+        match info.layout {
+            TypeLayout::Base(encoding) => self.format_base(info, encoding, addr),
+            TypeLayout::Alias(target) => self.format_at(target, addr, depth),
+            TypeLayout::Pointer(target) => self.format_pointer(info, target, addr, depth),
+            TypeLayout::Struct(ref members) => self.format_struct(info, members, addr, depth),
+            TypeLayout::Variants {
+                ref discriminant,
+                ref variants,
+            } => self.format_variants(discriminant, variants, addr, depth),
+            TypeLayout::Enumeration(ref values) => match self.read_uint(addr, info.size) {
+                Some(raw) => {
+                    let value = match Value::from_bytes(Encoding::Unsigned, &raw.to_le_bytes()[..info.size.min(8)]) {
+                        Value::Int(v) => v,
+                        _ => raw as i64,
+                    };
+                    match values.iter().find(|(_, v)| *v == value) {
+                        Some((name, _)) => name.clone(),
+                        None => value.to_string(),
+                    }
+                }
+                None => unreadable(addr),
+            },
+            TypeLayout::Array(elem, len) => self.format_elements(elem, addr, len, depth),
+        }
+    }
+
+    fn format_base(&self, info: &TypeInfo, encoding: Encoding, addr: usize) -> String {
+        let bytes = match self.read(addr, info.size) {
+            Some(bytes) => bytes,
+            None => return unreadable(addr),
+        };
+        let value = Value::from_bytes(encoding, &bytes);
+        match (info.name.as_str(), value) {
+            ("bool", Value::Int(v)) => (v != 0).to_string(),
+            // Rust chars are four-byte Unicode scalar values
+            ("char", Value::Int(v)) if info.size == 4 => match std::char::from_u32(v as u32) {
+                Some(c) => format!("{:?}", c),
+                None => v.to_string(),
+            },
+            (name, Value::Int(v)) if name.contains("char") && info.size == 1 => {
+                format!("{} {:?}", v, (v as u8) as char)
+            }
+            (_, value) => value.to_string(),
+        }
+    }
+
+    fn format_pointer(
+        &self,
+        info: &TypeInfo,
+        target: Option<usize>,
+        addr: usize,
+        depth: usize,
+    ) -> String {
+        let ptr = match self.read_usize(addr) {
+            Some(ptr) => ptr,
+            None => return unreadable(addr),
+        };
+        let target = match target {
+            Some(target) if ptr != 0 => target,
+            _ => return format!("{:#x}", ptr),
+        };
+        if is_rust_reference(info) {
+            return self.format_at(target, ptr, depth + 1);
+        }
+        // C strings
+        match self.debug_data.get_type(target) {
+            Some(pointee) if pointee.name.ends_with("char") && pointee.size == 1 => {
+                format!("{:#x} {}", ptr, self.format_c_string(ptr))
+            }
+            _ => format!("{:#x}", ptr),
+        }
+    }
+
+    fn format_c_string(&self, addr: usize) -> String {
+        let mut bytes = Vec::new();
+        while bytes.len() < MAX_STRING_LEN {
+            match self.read(addr + bytes.len(), 8) {
+                Some(chunk) => match chunk.iter().position(|b| *b == 0) {
+                    Some(end) => {
+                        bytes.extend_from_slice(&chunk[..end]);
+                        return format!("{:?}", String::from_utf8_lossy(&bytes));
+                    }
+                    None => bytes.extend_from_slice(&chunk),
+                },
+                None => break,
+            }
+        }
+        format!("{:?}...", String::from_utf8_lossy(&bytes))
+    }
+
+    fn format_string(&self, ptr: usize, len: usize) -> String {
+        match self.read(ptr, len.min(MAX_STRING_LEN)) {
+            Some(bytes) if len <= MAX_STRING_LEN => format!("{:?}", String::from_utf8_lossy(&bytes)),
+            Some(bytes) => format!("{:?}...", String::from_utf8_lossy(&bytes)),
+            None => unreadable(ptr),
+        }
+    }
+
+    fn format_elements(&self, elem: usize, addr: usize, len: usize, depth: usize) -> String {
+        let elem_size = self.size_of(elem);
+        let mut elements: Vec<String> = (0..len.min(MAX_ELEMENTS))
+            .map(|i| self.format_at(elem, addr + i * elem_size, depth + 1))
+            .collect();
+        if len > MAX_ELEMENTS {
+            elements.push("...".to_string());
+        }
+        format!("[{}]", elements.join(", "))
+    }
+
+    fn format_struct(&self, info: &TypeInfo, members: &[Member], addr: usize, depth: usize) -> String {
+        let name = info.name.as_str();
+        let member = |member_name: &str| members.iter().find(|m| m.name == member_name);
+
+        if name == "&str" || name == "&mut str" {
+            if let (Some(ptr), Some(len)) = (member("data_ptr"), member("length")) {
+                return match (self.read_usize(addr + ptr.offset), self.read_usize(addr + len.offset)) {
+                    (Some(ptr), Some(len)) => self.format_string(ptr, len),
+                    _ => unreadable(addr),
+                };
+            }
+        }
+        if name.starts_with("&[") || name.starts_with("&mut [") || name.starts_with("*const [")
+            || name.starts_with("*mut [")
+        {
+            if let (Some(ptr), Some(len)) = (member("data_ptr"), member("length")) {
+                let elem = match self.resolve(ptr.type_offset).map(|t| &t.layout) {
+                    Some(TypeLayout::Pointer(Some(elem))) => *elem,
+                    _ => return format!("{} {{ ... }}", name),
+                };
+                return match (self.read_usize(addr + ptr.offset), self.read_usize(addr + len.offset)) {
+                    (Some(ptr), Some(len)) => self.format_elements(elem, ptr, len, depth),
+                    _ => unreadable(addr),
+                };
+            }
+        }
+        if is_rust_type(info, "alloc::string::String") {
+            if let Some(vec) = member("vec") {
+                let parts = self.resolve(vec.type_offset).and_then(|vec_info| self.vec_parts(vec_info, addr + vec.offset));
+                if let Some((ptr, len, _)) = parts {
+                    return self.format_string(ptr, len);
+                }
+            }
+        }
+        if is_rust_type(info, "alloc::vec::Vec") {
+            if let Some((ptr, len, Some(elem))) = self.vec_parts(info, addr) {
+                return self.format_elements(elem, ptr, len, depth);
+            }
+        }
+        self.format_fields(name, members, addr, depth)
+    }
+
+    /// Formats a struct as `Name { a: 1, b: 2 }`, or `Name(1, 2)` for tuple structs.
+    fn format_fields(&self, name: &str, members: &[Member], addr: usize, depth: usize) -> String {
+        if members.is_empty() {
+            return name.to_string();
+        }
+        let tuple_like = members.iter().all(|m| m.name.starts_with("__"));
+        let fields: Vec<String> = members
+            .iter()
+            .map(|m| {
+                let value = self.format_at(m.type_offset, addr + m.offset, depth + 1);
+                if tuple_like {
+                    value
+                } else {
+                    format!("{}: {}", m.name, value)
+                }
+            })
+            .collect();
+        let separator = if name.is_empty() { "" } else { " " };
+        if tuple_like {
+            format!("{}({})", name, fields.join(", "))
+        } else {
+            format!("{}{}{{ {} }}", name, separator, fields.join(", "))
+        }
+    }
+
+    fn format_variants(
+        &self,
+        discriminant: &Option<Member>,
+        variants: &[Variant],
+        addr: usize,
+        depth: usize,
+    ) -> String {
+        let variant = match discriminant {
+            Some(discr) => {
+                let size = self.size_of(discr.type_offset);
+                let value = match self.read_uint(addr + discr.offset, size) {
+                    Some(value) => value,
+                    None => return unreadable(addr),
+                };
+                variants
+                    .iter()
+                    .find(|v| v.discr_value == Some(value))
+                    .or_else(|| variants.iter().find(|v| v.discr_value.is_none()))
+            }
+            None => variants.first(),
+        };
+        let member = match variant {
+            Some(variant) => &variant.member,
+            None => return "<invalid enum value>".to_string(),
+        };
+        match self.resolve(member.type_offset).map(|t| &t.layout) {
+            Some(TypeLayout::Struct(fields)) => {
+                self.format_fields(&member.name, fields, addr + member.offset, depth)
+            }
+            _ => member.name.clone(),
+        }
+    }
+
+    /// Finds the buffer pointer, length and element type of a Vec<T>. The pointer is buried in
+    /// RawVec, whose layout changes between Rust versions, so we take the first pointer in it.
+    fn vec_parts(&self, info: &TypeInfo, addr: usize) -> Option<(usize, usize, Option<usize>)> {
+        let members = match info.layout {
+            TypeLayout::Struct(ref members) => members,
+            _ => return None,
+        };
+        let len = members.iter().find(|m| m.name == "len")?;
+        let buf = members.iter().find(|m| m.name == "buf")?;
+        let (ptr, pointee) = self.find_pointer(buf.type_offset, addr + buf.offset, 0)?;
+        let elem = info.template_params.first().cloned().or(pointee);
+        Some((ptr, self.read_usize(addr + len.offset)?, elem))
+    }
+
+    fn find_pointer(&self, type_offset: usize, addr: usize, depth: usize) -> Option<(usize, Option<usize>)> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        match self.resolve(type_offset)?.layout {
+            TypeLayout::Pointer(pointee) => Some((self.read_usize(addr)?, pointee)),
+            TypeLayout::Struct(ref members) => members
+                .iter()
+                .find_map(|m| self.find_pointer(m.type_offset, addr + m.offset, depth + 1)),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, type_offset: usize) -> Option<&'a TypeInfo> {
+        self.debug_data.resolve_type(type_offset)
+    }
+}
+
+/// Returns true for Rust references and boxes, which are shown as the value they point to, like
+/// {:?} does.
+pub fn is_rust_reference(info: &TypeInfo) -> bool {
+    info.rust && (info.name.starts_with('&') || is_rust_type(info, "alloc::boxed::Box"))
+}
+
+/// Returns true if the type comes from Rust code and is named by the Rust type path (e.g.
+/// `alloc::vec::Vec`), with or without its module path and generic arguments. The check for
+/// the language keeps C and C++ types that happen to share the name from being misread.
+fn is_rust_type(info: &TypeInfo, path: &str) -> bool {
+    let base = info.name.split('<').next().unwrap_or("");
+    info.rust && (base == path || Some(base) == path.rsplit("::").next())
+}
+
+fn unreadable(addr: usize) -> String {
+    format!("<cannot access memory at {:#x}>", addr)
+}